@@ -1,4 +1,7 @@
 fn main() {
-    println!("cargo:rustc-link-search=./lib");
-    println!("cargo:rustc-link-lib=chapro");
+    // chapro is built for the Cortex-M33 and only used by the software dsp
+    if std::env::var_os("CARGO_FEATURE_NRF").is_some() {
+        println!("cargo:rustc-link-search=./lib");
+        println!("cargo:rustc-link-lib=chapro");
+    }
 }
@@ -1,10 +1,17 @@
 #![no_std]
 #![no_main]
 
-use cs47l63::driver;
 use yote::{
-    hw_dsp::{dsp, shared_bus::SharedBus},
+    hw_dsp::{
+        asp::AspConfig,
+        dsp,
+        error::DspError,
+        events::{self, CodecEvents, IrqSource},
+        shared_bus::SharedBus,
+        volume::{self, OutputChannel},
+    },
     play_state::PlayState,
+    sample_rate::{SampleRate, SampleRateConfig},
     wave::{self, Waveform, NUM_SAMPLES},
 };
 
@@ -18,7 +25,7 @@ use embassy_nrf::{
     bind_interrupts,
     gpio::{AnyPin, Input, Level, Output, OutputDrive, Pull},
     gpiote::{InputChannel, InputChannelPolarity},
-    i2s::{self, MasterClock, I2S},
+    i2s::{self, I2S},
     peripherals::{I2S0, SERIAL3},
     spim::{self, Frequency},
 };
@@ -68,7 +75,7 @@ async fn main(spawner: Spawner) {
 
     // create an spi bus that can be shared between tasks
     let shared_bus = SharedBus::new(spi, cs_codec);
-    static SHARED_BUS: StaticCell<SharedBus> = StaticCell::new();
+    static SHARED_BUS: StaticCell<SharedBus<SERIAL3>> = StaticCell::new();
     let shared_bus = &*SHARED_BUS.init(shared_bus);
 
     // gpio setup
@@ -89,9 +96,11 @@ async fn main(spawner: Spawner) {
     let _rgb1_red = Output::new(AnyPin::from(p.P0_04), Level::Low, OutputDrive::HighDrive);
 
     // i2s sound bus for full duplex audio
-    let master_clock: MasterClock = i2s::ApproxSampleRate::_11025.into();
-    let sample_rate = master_clock.sample_rate();
-    info!("Sample rate: {}", sample_rate);
+    // the codec, i2s bus and audio generation all use the same sample rate
+    let rate = unwrap!(SampleRateConfig::new(SampleRate::_11025Hz));
+    let master_clock = rate.master_clock();
+    let sample_rate = rate.sample_rate();
+    info!("Sample rate: {}", rate);
     let mut config = i2s::Config::default();
     config.sample_width = i2s::SampleWidth::_16bit;
     config.channels = i2s::Channels::MonoLeft;
@@ -116,13 +125,23 @@ async fn main(spawner: Spawner) {
     // controls play / pause state messaging between tasks
     static PLAY_STATE: PlayState = PlayState::new();
 
+    // codec events are published by the irq task and can be received by any number of tasks
+    static CODEC_EVENTS: CodecEvents = CodecEvents::new();
+
     // task for responding to irq events from dsp
-    unwrap!(spawner.spawn(process_events(shared_bus, hw_codec_irq)));
+    unwrap!(spawner.spawn(process_events(shared_bus, hw_codec_irq, &CODEC_EVENTS)));
+    unwrap!(spawner.spawn(log_codec_events(&CODEC_EVENTS)));
 
     // task for responding to button press events
     unwrap!(spawner.spawn(process_buttons(btn_play_pause, &PLAY_STATE)));
 
-    if let Err(e) = audio_system_init(shared_bus, &mut hw_codec_reset_out).await {
+    // the codec FLL locks to the i2s master clock so the stream has to be running before the codec is configured
+    if let Err(e) = stream.start().await {
+        error!("Error starting i2s stream: {:?}", e);
+        return;
+    }
+
+    if let Err(e) = audio_system_init(shared_bus, &mut hw_codec_reset_out, &rate).await {
         error!("Error initialising audio codec: {:?}", e);
         return;
     }
@@ -147,14 +166,13 @@ async fn play_audio(
     play_state: &PlayState,
     sample_rate: u32,
     stream: &mut FullDuplexStream<'static, I2S0, i16, 2, 32>,
-    shared_bus: &'static SharedBus,
+    shared_bus: &'static SharedBus<SERIAL3>,
     hw_codec_reset_out: &mut Output<'_, AnyPin>,
 ) -> Result<(), i2s::Error> {
-    stream.start().await?;
     let mut waveform = Waveform::new(440.0, sample_rate as f32);
 
     let mut bus = shared_bus.borrow().await;
-    match dsp::volume_adjust(&mut bus, 12).await {
+    match volume::adjust(&mut bus, OutputChannel::Out1L, 12.0, None).await {
         Ok(level_db) => info!("Volume set to {} dB", level_db),
         Err(e) => error!("Error setting volume: {:?}", e),
     }
@@ -200,30 +218,41 @@ async fn play_audio(
 
 #[embassy_executor::task(pool_size = 1)]
 async fn process_events(
-    shared_bus: &'static SharedBus,
+    shared_bus: &'static SharedBus<SERIAL3>,
     hw_codec_irq: InputChannel<'static, AnyChannel, AnyPin>,
+    codec_events: &'static CodecEvents,
 ) {
     loop {
         info!("[EVT_TASK] Waiting for IRQ");
         hw_codec_irq.wait().await;
         info!("[EVT_TASK] IRQ triggered, handling events");
 
-        let event_flags = {
+        let num_events = {
             // only borrow the bus for as long as it takes to process the event handler
             let mut bus = shared_bus.borrow().await;
-            driver::event_handler(&mut bus).await
+            events::handle_irq(&mut bus, codec_events).await
         };
 
-        match event_flags {
-            Ok(event_flags) => info!(
-                "[EVT_TASK] Completed handling IRQ triggered events: {}",
-                event_flags
-            ),
+        match num_events {
+            Ok(num_events) => info!("[EVT_TASK] Published {} codec events", num_events),
             Err(e) => error!("[EVT_TASK] Error handling IRQ triggered events: {:?}", e),
         }
     }
 }
 
+#[embassy_executor::task(pool_size = 1)]
+async fn log_codec_events(codec_events: &'static CodecEvents) {
+    let Ok(mut subscriber) = codec_events.subscriber() else {
+        error!("[LOG_TASK] Too many codec event subscribers");
+        return;
+    };
+
+    loop {
+        let event = subscriber.next_message_pure().await;
+        info!("[LOG_TASK] Codec event: {}", event);
+    }
+}
+
 #[embassy_executor::task(pool_size = 1)]
 async fn process_buttons(
     btn_play_pause: InputChannel<'static, AnyChannel, AnyPin>,
@@ -244,22 +273,40 @@ async fn debounce_button() {
 }
 
 async fn audio_system_init(
-    shared_bus: &SharedBus,
+    shared_bus: &SharedBus<SERIAL3>,
     hw_codec_reset_out: &mut Output<'_, AnyPin>,
-) -> Result<(), spim::Error> {
+    rate: &SampleRateConfig,
+) -> Result<(), DspError> {
     // drive RESET low then high
     hw_codec_reset_out.set_low();
     Timer::after(Duration::from_millis(24)).await;
     hw_codec_reset_out.set_high();
 
+    // hold on to the bus for as long as it takes to bring up the codec
+    let mut bus = shared_bus.borrow().await;
+
+    // the reset pin has cleared all codec registers
+    bus.invalidate_cache();
+
+    // make sure the codec is answering before writing anything to it
+    let codec = dsp::probe(&mut bus).await?;
+    info!(
+        "Found CS47L63 revision {}{}",
+        codec.revision_letter(),
+        codec.metal_revision
+    );
+
     // reset
-    dsp::reset(shared_bus).await?;
+    dsp::reset(&mut bus).await?;
     info!("System init and reset complete");
 
     // configure codec
-    dsp::default_conf_enable(shared_bus).await?;
+    dsp::default_conf_enable(&mut bus, rate, &AspConfig::new()).await?;
     info!("HW codec configured for streaming");
 
+    // report codec clocking and output problems as events
+    events::enable_all(&mut bus, IrqSource::CLOCK_AND_OUTPUT).await?;
+
     // enable equalizer
     //dsp::enable_equalizer(shared_bus).await?;
     //info!("Equalizer enabled");
@@ -4,10 +4,13 @@
 use cs47l63::hw_interface::Bus;
 use embassy_nrf::gpio::{Input, Pin, Pull};
 use embassy_nrf::peripherals::P0_18;
+use yote::hw_dsp::asp::AspConfig;
 use yote::hw_dsp::dsp;
-use yote::hw_dsp::shared_bus::SpiBusInnerFixed;
+use yote::hw_dsp::error::DspError;
+use yote::hw_dsp::shared_bus::PowerGatedBus;
+use yote::sample_rate::{SampleRate, SampleRateConfig};
 
-use defmt::{error, info};
+use defmt::{error, info, unwrap, warn};
 use defmt_rtt as _;
 use embassy_executor::Spawner;
 use embassy_nrf::gpiote::{Channel, InputChannelPolarity};
@@ -54,6 +57,17 @@ async fn main(_spawner: Spawner) {
 
     let mut is_playing = true;
 
+    // the spi peripheral and pins are released between transactions to save power
+    let mut bus = PowerGatedBus::new(
+        &mut p.SERIAL3,
+        Irqs,
+        &mut p.P0_08,
+        &mut p.P0_10,
+        &mut p.P0_09,
+        &mut p.P0_17,
+        Frequency::M4,
+    );
+
     loop {
         info!("Resetting audio codec");
         hw_codec_reset_out.set_low();
@@ -62,19 +76,6 @@ async fn main(_spawner: Spawner) {
 
         if is_playing {
             info!("Initialising dsp");
-            let mut config = spim::Config::default();
-            config.frequency = Frequency::M4;
-            let spi = spim::Spim::new(
-                &mut p.SERIAL3,
-                Irqs,
-                &mut p.P0_08,
-                &mut p.P0_10,
-                &mut p.P0_09,
-                config,
-            );
-            let cs = Output::new(&mut p.P0_17, Level::High, OutputDrive::Standard);
-            let mut bus = SpiBusInnerFixed { spi, cs };
-
             if let Err(e) = audio_system_init(&mut bus, &mut hw_codec_reset_out).await {
                 error!("Error initialising audio codec: {:?}", e);
                 return;
@@ -96,18 +97,33 @@ async fn debounce_button() {
 async fn audio_system_init(
     bus: &mut impl Bus<spim::Error>,
     hw_codec_reset_out: &mut Output<'_, P0_18>,
-) -> Result<(), spim::Error> {
+) -> Result<(), DspError> {
     // drive RESET low then high
     hw_codec_reset_out.set_low();
     Timer::after(Duration::from_millis(24)).await;
     hw_codec_reset_out.set_high();
 
+    // make sure the codec is answering before writing anything to it
+    let codec = dsp::probe(bus).await?;
+    info!(
+        "Found CS47L63 revision {}{}",
+        codec.revision_letter(),
+        codec.metal_revision
+    );
+
     // reset
-    dsp::reset_spi(bus).await?;
+    dsp::reset(bus).await?;
     info!("System init and reset complete");
 
     // configure codec
-    dsp::default_conf_enable_spi(bus).await?;
+    // nothing is streamed over i2s so the codec rate only has to suit the mic to speaker path
+    // and without the i2s master clock the FLL has no reference to lock to
+    let rate = unwrap!(SampleRateConfig::new(SampleRate::_11025Hz));
+    match dsp::default_conf_enable(bus, &rate, &AspConfig::new()).await {
+        Ok(()) => {}
+        Err(DspError::FllLockFailed) => warn!("FLL not locked, there is no i2s master clock"),
+        Err(e) => return Err(e),
+    }
     info!("HW codec configured for streaming");
 
     Ok(())
@@ -1,10 +1,17 @@
 #![no_std]
 #![no_main]
 
-use cs47l63::driver;
 use yote::{
-    hw_dsp::{dsp, shared_bus::SharedBus},
+    hw_dsp::{
+        asp::AspConfig,
+        dsp,
+        error::DspError,
+        events::{self, CodecEvents, IrqSource},
+        shared_bus::SharedBus,
+        volume::{self, OutputChannel},
+    },
     play_state::PlayState,
+    sample_rate::{SampleRate, SampleRateConfig},
     wave::{self, NUM_SAMPLES},
 };
 
@@ -18,7 +25,7 @@ use embassy_nrf::{
     bind_interrupts,
     gpio::{AnyPin, Input, Level, Output, OutputDrive, Pull},
     gpiote::{InputChannel, InputChannelPolarity},
-    i2s::{self, MasterClock, I2S},
+    i2s::{self, I2S},
     peripherals::{I2S0, SERIAL3},
     spim::{self, Frequency},
 };
@@ -60,7 +67,7 @@ async fn main(spawner: Spawner) {
 
     // create an spi bus that can be shared between tasks
     let shared_bus = SharedBus::new(spi, cs_codec);
-    static SHARED_BUS: StaticCell<SharedBus> = StaticCell::new();
+    static SHARED_BUS: StaticCell<SharedBus<SERIAL3>> = StaticCell::new();
     let shared_bus = &*SHARED_BUS.init(shared_bus);
 
     // gpio setup
@@ -103,15 +110,16 @@ async fn main(spawner: Spawner) {
     let _led3_green = Output::new(AnyPin::from(p.P1_01), Level::Low, OutputDrive::HighDrive);
 
     // i2s sound bus for full duplex audio
-    let master_clock: MasterClock = i2s::ApproxSampleRate::_11025.into();
-    let sample_rate = master_clock.sample_rate();
-    info!("Sample rate: {}", sample_rate);
+    // the codec, i2s bus and audio generation all use the same sample rate
+    let rate = unwrap!(SampleRateConfig::new(SampleRate::_11025Hz));
+    let master_clock = rate.master_clock();
+    info!("Sample rate: {}", rate);
     let mut config = i2s::Config::default();
     config.sample_width = i2s::SampleWidth::_16bit;
     config.channels = i2s::Channels::MonoLeft;
     let buffers_in = i2s::DoubleBuffering::<wave::Sample, NUM_SAMPLES>::new();
     let buffers_out = i2s::DoubleBuffering::<wave::Sample, NUM_SAMPLES>::new();
-    let _stream = I2S::new_master(
+    let mut stream = I2S::new_master(
         p.I2S0,
         Irqs,
         p.P0_12,
@@ -132,8 +140,12 @@ async fn main(spawner: Spawner) {
     static PLAY_STATE: PlayState = PlayState::new();
     static TONE_PLAYING: AtomicBool = AtomicBool::new(false);
 
+    // codec events are published by the irq task and can be received by any number of tasks
+    static CODEC_EVENTS: CodecEvents = CodecEvents::new();
+
     // task for responding to irq events from dsp
-    unwrap!(spawner.spawn(process_events(shared_bus, hw_codec_irq)));
+    unwrap!(spawner.spawn(process_events(shared_bus, hw_codec_irq, &CODEC_EVENTS)));
+    unwrap!(spawner.spawn(log_codec_events(&CODEC_EVENTS)));
 
     // task for responding to button press events
     unwrap!(spawner.spawn(process_buttons(
@@ -147,8 +159,19 @@ async fn main(spawner: Spawner) {
         &TONE_PLAYING
     )));
 
-    if let Err(e) =
-        audio_system_init(shared_bus, &mut hw_codec_sel_out, &mut hw_codec_reset_out).await
+    // the codec FLL locks to the i2s master clock so the stream has to be running before the codec is configured
+    if let Err(e) = stream.start().await {
+        error!("Error starting i2s stream: {:?}", e);
+        return;
+    }
+
+    if let Err(e) = audio_system_init(
+        shared_bus,
+        &mut hw_codec_sel_out,
+        &mut hw_codec_reset_out,
+        &rate,
+    )
+    .await
     {
         error!("Error initialising audio codec: {:?}", e);
         return;
@@ -164,34 +187,45 @@ async fn main(spawner: Spawner) {
 
 #[embassy_executor::task(pool_size = 1)]
 async fn process_events(
-    shared_bus: &'static SharedBus,
+    shared_bus: &'static SharedBus<SERIAL3>,
     hw_codec_irq: InputChannel<'static, AnyChannel, AnyPin>,
+    codec_events: &'static CodecEvents,
 ) {
     loop {
         info!("[EVT_TASK] Waiting for IRQ");
         hw_codec_irq.wait().await;
         info!("[EVT_TASK] IRQ triggered, handling events");
 
-        let event_flags = {
+        let num_events = {
             // only borrow the bus for as long as it takes to process the event handler
             let mut bus = shared_bus.borrow().await;
-            driver::event_handler(&mut bus).await
+            events::handle_irq(&mut bus, codec_events).await
         };
 
-        match event_flags {
-            Ok(event_flags) => info!(
-                "[EVT_TASK] Completed handling IRQ triggered events: {}",
-                event_flags
-            ),
+        match num_events {
+            Ok(num_events) => info!("[EVT_TASK] Published {} codec events", num_events),
             Err(e) => error!("[EVT_TASK] Error handling IRQ triggered events: {:?}", e),
         }
     }
 }
 
+#[embassy_executor::task(pool_size = 1)]
+async fn log_codec_events(codec_events: &'static CodecEvents) {
+    let Ok(mut subscriber) = codec_events.subscriber() else {
+        error!("[LOG_TASK] Too many codec event subscribers");
+        return;
+    };
+
+    loop {
+        let event = subscriber.next_message_pure().await;
+        info!("[LOG_TASK] Codec event: {}", event);
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 #[embassy_executor::task(pool_size = 1)]
 async fn process_buttons(
-    shared_bus: &'static SharedBus,
+    shared_bus: &'static SharedBus<SERIAL3>,
     button1_vol_down: InputChannel<'static, AnyChannel, AnyPin>,
     button2_vol_up: InputChannel<'static, AnyChannel, AnyPin>,
     button3_play: InputChannel<'static, AnyChannel, AnyPin>,
@@ -200,7 +234,7 @@ async fn process_buttons(
     play_state: &'static PlayState,
     tone_playing: &'static AtomicBool,
 ) {
-    const VOLUME_ADJUST_STEP_DB: i32 = 3;
+    const VOLUME_ADJUST_STEP_DB: f32 = 3.0;
     info!("[BTN_TASK] Waiting for buttons");
 
     let button1 = volume_button_handler(button1_vol_down, shared_bus, -VOLUME_ADJUST_STEP_DB);
@@ -230,13 +264,23 @@ async fn process_buttons(
 
 async fn volume_button_handler(
     volume_button: InputChannel<'static, AnyChannel, AnyPin>,
-    shared_bus: &'static SharedBus,
-    adjustment_db: i32,
+    shared_bus: &'static SharedBus<SERIAL3>,
+    adjustment_db: f32,
 ) {
+    // ramp the volume to avoid zipper noise
+    const VOLUME_RAMP_MS: u32 = 30;
+
     loop {
         volume_button.wait().await;
         let mut bus = shared_bus.borrow().await;
-        match dsp::volume_adjust(&mut bus, adjustment_db).await {
+        match volume::adjust(
+            &mut bus,
+            OutputChannel::Out1L,
+            adjustment_db,
+            Some(VOLUME_RAMP_MS),
+        )
+        .await
+        {
             Ok(level_db) => info!("[BTN_TASK] Volume set to {} dB", level_db),
             Err(e) => error!("[BTN_TASK] Error setting volume: {:?}", e),
         }
@@ -247,7 +291,7 @@ async fn volume_button_handler(
 
 async fn mute_button_handler(
     mute_button: InputChannel<'static, AnyChannel, AnyPin>,
-    shared_bus: &'static SharedBus,
+    shared_bus: &'static SharedBus<SERIAL3>,
 ) {
     let mut mute = false;
     loop {
@@ -255,7 +299,7 @@ async fn mute_button_handler(
         let mut bus = shared_bus.borrow().await;
 
         mute = !mute;
-        match dsp::volume_mute(&mut bus, mute).await {
+        match volume::mute(&mut bus, OutputChannel::Out1L, mute).await {
             Ok(()) => {
                 if mute {
                     info!("[BTN_TASK] Muted");
@@ -275,10 +319,11 @@ async fn debounce_button() {
 }
 
 async fn audio_system_init(
-    shared_bus: &SharedBus,
+    shared_bus: &SharedBus<SERIAL3>,
     hw_codec_sel_out: &mut Output<'_, AnyPin>,
     hw_codec_reset_out: &mut Output<'_, AnyPin>,
-) -> Result<(), spim::Error> {
+    rate: &SampleRateConfig,
+) -> Result<(), DspError> {
     // select the on-board HW codec
     hw_codec_sel_out.set_low();
     Timer::after(Duration::from_millis(2)).await;
@@ -288,14 +333,31 @@ async fn audio_system_init(
     Timer::after(Duration::from_millis(24)).await;
     hw_codec_reset_out.set_high();
 
+    // hold on to the bus for as long as it takes to bring up the codec
+    let mut bus = shared_bus.borrow().await;
+
+    // the reset pin has cleared all codec registers
+    bus.invalidate_cache();
+
+    // make sure the codec is answering before writing anything to it
+    let codec = dsp::probe(&mut bus).await?;
+    info!(
+        "Found CS47L63 revision {}{}",
+        codec.revision_letter(),
+        codec.metal_revision
+    );
+
     // reset
-    dsp::reset(shared_bus).await?;
+    dsp::reset(&mut bus).await?;
     info!("System init and reset complete");
 
     // configure codec
-    dsp::default_conf_enable(shared_bus).await?;
+    dsp::default_conf_enable(&mut bus, rate, &AspConfig::new()).await?;
     info!("HW codec configured for streaming");
 
+    // report codec clocking and output problems as events
+    events::enable_all(&mut bus, IrqSource::CLOCK_AND_OUTPUT).await?;
+
     // enable equalizer
     //dsp::enable_equalizer(shared_bus).await?;
     //info!("Equalizer enabled");
@@ -12,6 +12,7 @@ use embassy_nrf::Peripherals;
 use yote::{
     hw_dsp::{dsp, shared_bus::SharedBus},
     play_state::PlayState,
+    sample_rate::{SampleRate, SampleRateConfig},
     wave::{self, Waveform, NUM_SAMPLES},
 };
 
@@ -25,7 +26,7 @@ use embassy_nrf::{
     bind_interrupts,
     gpio::{AnyPin, Input, Level, Output, OutputDrive, Pull},
     gpiote::{InputChannel, InputChannelPolarity},
-    i2s::{self, MasterClock, I2S},
+    i2s::{self, I2S},
     peripherals::{I2S0, SERIAL3},
     spim::{self, Frequency},
 };
@@ -124,9 +125,10 @@ async fn main(_spawner: Spawner) {
     let mut p = embassy_nrf::init(Default::default());
 
     // i2s sound bus for full duplex audio
-    let master_clock: MasterClock = i2s::ApproxSampleRate::_11025.into();
-    let sample_rate = master_clock.sample_rate();
-    info!("Sample rate: {}", sample_rate);
+    // the codec, i2s bus and audio generation all use the same sample rate
+    let rate = unwrap!(SampleRateConfig::new(SampleRate::_11025Hz));
+    let master_clock = rate.master_clock();
+    info!("Sample rate: {}", rate);
     let mut config = i2s::Config::default();
     config.sample_width = i2s::SampleWidth::_16bit;
     config.channels = i2s::Channels::MonoLeft;
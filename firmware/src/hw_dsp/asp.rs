@@ -0,0 +1,478 @@
+use super::config::gpio_ctrl1;
+use super::dsp::reg_conf_write;
+use super::error::DspError;
+use cs47l63::{
+    hw_interface::Bus,
+    registers::{audio_serial_port::asp_ctrl, gpio::gpio_ctrl},
+};
+#[cfg(feature = "nrf")]
+use embassy_nrf::i2s;
+
+// Audio serial port (ASP1) settings: format, word and slot widths, channels, clock roles and polarity.
+//
+// Every frame is made of slots, one per channel. A slot is `slot_bits` bit clocks long and carries a word of `word_bits` bits.
+// I2S and left-justified frames have two slots (left and right) and the frame sync marks the channel.
+// DSP (TDM) frames have up to 8 slots and a frame sync pulse at the start of the frame.
+// The bit clock and frame sync are either driven by the codec (master) or by the other end of the link (slave).
+//
+// The nRF I2S peripheral only supports I2S and left-aligned frames of two slots as wide as its sample width with normal polarity,
+// so use `AspConfig::check_nrf` to validate a configuration against the nRF I2S config it is used with.
+
+/// Maximum number of channels (slots) in a DSP (TDM) frame
+pub const MAX_TDM_CHANNELS: u8 = 8;
+
+/// Word width range in bits
+pub const MIN_WORD_BITS: u8 = 8;
+pub const MAX_WORD_BITS: u8 = 32;
+
+/// Longest slot in bit clocks
+pub const MAX_SLOT_BITS: u8 = 128;
+
+/// The frame format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AspFormat {
+    I2s,
+    LeftJustified,
+    /// DSP mode with a frame sync pulse, used for TDM
+    Dsp,
+}
+
+/// Which end of the link drives a clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ClockRole {
+    Master,
+    Slave,
+}
+
+impl ClockRole {
+    #[cfg(feature = "nrf")]
+    fn other(&self) -> Self {
+        match self {
+            Self::Master => Self::Slave,
+            Self::Slave => Self::Master,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AspError {
+    /// The word width is outside of MIN_WORD_BITS to MAX_WORD_BITS
+    WordWidthOutOfRange,
+    /// The slot is shorter than the word or longer than MAX_SLOT_BITS
+    SlotWidthOutOfRange,
+    /// No channels, more than 2 for I2S and left-justified or more than MAX_TDM_CHANNELS for DSP
+    ChannelsOutOfRange,
+    /// The nRF does not support the format (or the nRF alignment does not match it)
+    NrfFormatMismatch,
+    /// The slot width differs from the nRF sample width
+    NrfSlotWidthMismatch,
+    /// More channels than the two slots of the nRF frame
+    NrfChannelsMismatch,
+    /// The codec and the nRF do not have opposite clock roles
+    NrfClockRoleMismatch,
+    /// The nRF does not support inverted clocks
+    NrfPolarityMismatch,
+}
+
+/// The settings of ASP1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct AspConfig {
+    format: AspFormat,
+    word_bits: u8,
+    slot_bits: u8,
+    rx_channels: u8,
+    tx_channels: u8,
+    bclk_role: ClockRole,
+    fsync_role: ClockRole,
+    bclk_invert: bool,
+    fsync_invert: bool,
+}
+
+impl AspConfig {
+    /// codec as slave to the nRF: I2S with 16 bit slots and two channels in each direction
+    /// this is the format the codec has always been configured with (`config::ASP1_ENABLE`), which sets a 32 bit
+    /// data width with 16 bit slots so only the top 16 bits of each word fit in a slot.
+    /// `set_widths` does not allow a word wider than its slot so call it to choose a different format.
+    pub const fn new() -> Self {
+        Self {
+            format: AspFormat::I2s,
+            word_bits: 32,
+            slot_bits: 16,
+            rx_channels: 2,
+            tx_channels: 2,
+            bclk_role: ClockRole::Slave,
+            fsync_role: ClockRole::Slave,
+            bclk_invert: false,
+            fsync_invert: false,
+        }
+    }
+
+    /// sets the format, the number of channels is checked against it by `set_channels`
+    pub fn set_format(&mut self, format: AspFormat) -> Result<&mut Self, AspError> {
+        check_channels(format, self.rx_channels)?;
+        check_channels(format, self.tx_channels)?;
+        self.format = format;
+        Ok(self)
+    }
+
+    /// sets the number of valid bits in a slot and the length of a slot in bit clocks
+    pub fn set_widths(&mut self, word_bits: u8, slot_bits: u8) -> Result<&mut Self, AspError> {
+        if !(MIN_WORD_BITS..=MAX_WORD_BITS).contains(&word_bits) {
+            return Err(AspError::WordWidthOutOfRange);
+        }
+
+        if !(word_bits..=MAX_SLOT_BITS).contains(&slot_bits) {
+            return Err(AspError::SlotWidthOutOfRange);
+        }
+
+        self.word_bits = word_bits;
+        self.slot_bits = slot_bits;
+        Ok(self)
+    }
+
+    /// sets the number of channels received (RX1 upwards) and transmitted (TX1 upwards)
+    pub fn set_channels(
+        &mut self,
+        rx_channels: u8,
+        tx_channels: u8,
+    ) -> Result<&mut Self, AspError> {
+        check_channels(self.format, rx_channels)?;
+        check_channels(self.format, tx_channels)?;
+        self.rx_channels = rx_channels;
+        self.tx_channels = tx_channels;
+        Ok(self)
+    }
+
+    /// sets which end drives the bit clock and the frame sync
+    pub fn set_roles(&mut self, bclk: ClockRole, fsync: ClockRole) -> &mut Self {
+        self.bclk_role = bclk;
+        self.fsync_role = fsync;
+        self
+    }
+
+    /// inverts the bit clock (data changes on the rising edge) and the frame sync
+    pub fn set_polarity(&mut self, bclk_invert: bool, fsync_invert: bool) -> &mut Self {
+        self.bclk_invert = bclk_invert;
+        self.fsync_invert = fsync_invert;
+        self
+    }
+
+    /// checks that the settings work with the nRF I2S peripheral using the config and role given
+    #[cfg(feature = "nrf")]
+    pub fn check_nrf(&self, config: &i2s::Config, nrf_role: ClockRole) -> Result<(), AspError> {
+        let format_matches = match self.format {
+            AspFormat::I2s => matches!(config.format, i2s::Format::I2S),
+            AspFormat::LeftJustified => {
+                matches!(config.format, i2s::Format::Aligned)
+                    && matches!(config.align, i2s::Align::Left)
+            }
+            AspFormat::Dsp => false,
+        };
+        if !format_matches {
+            return Err(AspError::NrfFormatMismatch);
+        }
+
+        let nrf_sample_bits = match config.sample_width {
+            i2s::SampleWidth::_8bit => 8,
+            i2s::SampleWidth::_16bit => 16,
+            i2s::SampleWidth::_24bit => 24,
+        };
+        if self.slot_bits != nrf_sample_bits {
+            return Err(AspError::NrfSlotWidthMismatch);
+        }
+
+        // the nRF frame always has two slots, in mono mode the other slot is ignored
+        if self.rx_channels > 2 || self.tx_channels > 2 {
+            return Err(AspError::NrfChannelsMismatch);
+        }
+
+        let codec_role = nrf_role.other();
+        if self.bclk_role != codec_role || self.fsync_role != codec_role {
+            return Err(AspError::NrfClockRoleMismatch);
+        }
+
+        if self.bclk_invert || self.fsync_invert {
+            return Err(AspError::NrfPolarityMismatch);
+        }
+
+        Ok(())
+    }
+
+    pub fn format(&self) -> AspFormat {
+        self.format
+    }
+
+    /// the number of bit clocks in a frame
+    pub fn frame_bits(&self) -> u32 {
+        let slots = match self.format {
+            AspFormat::I2s | AspFormat::LeftJustified => 2,
+            AspFormat::Dsp => self.rx_channels.max(self.tx_channels) as u32,
+        };
+        slots * self.slot_bits as u32
+    }
+
+    /// the ASP1 pin, control and channel enable registers
+    pub const fn registers(&self) -> [[u32; 2]; 9] {
+        [
+            // GPIO1 to GPIO4 are DOUT, DIN, BCLK and FSYNC
+            gpio_ctrl1(
+                gpio_ctrl::Num::_1(gpio_ctrl::PinFunction::AlternateFunction),
+                gpio_ctrl::Direction::Output,
+            ),
+            gpio_ctrl1(
+                gpio_ctrl::Num::_2(gpio_ctrl::PinFunction::AlternateFunction),
+                gpio_ctrl::Direction::Input,
+            ),
+            gpio_ctrl1(
+                gpio_ctrl::Num::_3(gpio_ctrl::PinFunction::AlternateFunction),
+                direction(self.bclk_role),
+            ),
+            gpio_ctrl1(
+                gpio_ctrl::Num::_4(gpio_ctrl::PinFunction::AlternateFunction),
+                direction(self.fsync_role),
+            ),
+            // the width fields are the slot length, the data width fields the word length
+            asp_ctrl::AspControl2 {
+                num: asp_ctrl::Num::_1,
+                rx_width: self.slot_bits,
+                tx_width: self.slot_bits,
+                format: match self.format {
+                    AspFormat::I2s => asp_ctrl::AspFormat::I2sMode,
+                    AspFormat::LeftJustified => asp_ctrl::AspFormat::LeftJustifiedMode,
+                    // DSP mode A, the frame sync pulse is one bit clock before the first slot
+                    AspFormat::Dsp => asp_ctrl::AspFormat::Tdm1Mode,
+                },
+                bclk_invert: self.bclk_invert,
+                bclk_frc: asp_ctrl::AspBclkOutputControl::Normal,
+                bclk_mstr: master_select(self.bclk_role),
+                fsync_invert: self.fsync_invert,
+                fsync_frc: asp_ctrl::AspBclkOutputControl::Normal,
+                fsync_mstr: master_select(self.fsync_role),
+            }
+            .serialize(),
+            asp_ctrl::AspControl3 {
+                num: asp_ctrl::Num::_1,
+                dout_hiz_ctrl: asp_ctrl::AspDoutTristateControl::Mode00,
+            }
+            .serialize(),
+            asp_ctrl::AspDataControl1 {
+                num: asp_ctrl::Num::_1,
+                tx_data_width_bits: self.word_bits,
+            }
+            .serialize(),
+            asp_ctrl::AspDataControl5 {
+                num: asp_ctrl::Num::_1,
+                rx_data_width_bits: self.word_bits,
+            }
+            .serialize(),
+            asp_ctrl::Asp1Enables1 {
+                rx8_enabled: self.rx_channels >= 8,
+                rx7_enabled: self.rx_channels >= 7,
+                rx6_enabled: self.rx_channels >= 6,
+                rx5_enabled: self.rx_channels >= 5,
+                rx4_enabled: self.rx_channels >= 4,
+                rx3_enabled: self.rx_channels >= 3,
+                rx2_enabled: self.rx_channels >= 2,
+                rx1_enabled: self.rx_channels >= 1,
+                tx8_enabled: self.tx_channels >= 8,
+                tx7_enabled: self.tx_channels >= 7,
+                tx6_enabled: self.tx_channels >= 6,
+                tx5_enabled: self.tx_channels >= 5,
+                tx4_enabled: self.tx_channels >= 4,
+                tx3_enabled: self.tx_channels >= 3,
+                tx2_enabled: self.tx_channels >= 2,
+                tx1_enabled: self.tx_channels >= 1,
+            }
+            .serialize(),
+        ]
+    }
+}
+
+impl Default for AspConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn check_channels(format: AspFormat, channels: u8) -> Result<(), AspError> {
+    let max = match format {
+        AspFormat::I2s | AspFormat::LeftJustified => 2,
+        AspFormat::Dsp => MAX_TDM_CHANNELS,
+    };
+
+    if !(1..=max).contains(&channels) {
+        return Err(AspError::ChannelsOutOfRange);
+    }
+
+    Ok(())
+}
+
+// the clock pins are outputs when the codec drives them
+const fn direction(role: ClockRole) -> gpio_ctrl::Direction {
+    match role {
+        ClockRole::Master => gpio_ctrl::Direction::Output,
+        ClockRole::Slave => gpio_ctrl::Direction::Input,
+    }
+}
+
+const fn master_select(role: ClockRole) -> asp_ctrl::AspBclkMasterSelect {
+    match role {
+        ClockRole::Master => asp_ctrl::AspBclkMasterSelect::MasterMode,
+        ClockRole::Slave => asp_ctrl::AspBclkMasterSelect::SlaveMode,
+    }
+}
+
+/// configures ASP1 (the channels should be disabled while the format changes)
+pub async fn configure<E>(bus: &mut impl Bus<E>, config: &AspConfig) -> Result<(), DspError<E>> {
+    reg_conf_write(bus, &config.registers()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::config::ASP1_ENABLE;
+
+    fn enables(rx_channels: u8, tx_channels: u8) -> [u32; 2] {
+        asp_ctrl::Asp1Enables1 {
+            rx8_enabled: rx_channels >= 8,
+            rx7_enabled: rx_channels >= 7,
+            rx6_enabled: rx_channels >= 6,
+            rx5_enabled: rx_channels >= 5,
+            rx4_enabled: rx_channels >= 4,
+            rx3_enabled: rx_channels >= 3,
+            rx2_enabled: rx_channels >= 2,
+            rx1_enabled: rx_channels >= 1,
+            tx8_enabled: tx_channels >= 8,
+            tx7_enabled: tx_channels >= 7,
+            tx6_enabled: tx_channels >= 6,
+            tx5_enabled: tx_channels >= 5,
+            tx4_enabled: tx_channels >= 4,
+            tx3_enabled: tx_channels >= 3,
+            tx2_enabled: tx_channels >= 2,
+            tx1_enabled: tx_channels >= 1,
+        }
+        .serialize()
+    }
+
+    #[test]
+    fn default_is_the_legacy_slave_i2s_format() {
+        let registers = AspConfig::new().registers();
+        assert_eq!(registers, ASP1_ENABLE);
+
+        // BCLK and FSYNC are inputs driven by the nRF
+        let bclk_input = gpio_ctrl1(
+            gpio_ctrl::Num::_3(gpio_ctrl::PinFunction::AlternateFunction),
+            gpio_ctrl::Direction::Input,
+        );
+        let fsync_input = gpio_ctrl1(
+            gpio_ctrl::Num::_4(gpio_ctrl::PinFunction::AlternateFunction),
+            gpio_ctrl::Direction::Input,
+        );
+        assert!(registers.contains(&bclk_input));
+        assert!(registers.contains(&fsync_input));
+
+        let control2 = asp_ctrl::AspControl2 {
+            num: asp_ctrl::Num::_1,
+            rx_width: 16,
+            tx_width: 16,
+            format: asp_ctrl::AspFormat::I2sMode,
+            bclk_invert: false,
+            bclk_frc: asp_ctrl::AspBclkOutputControl::Normal,
+            bclk_mstr: asp_ctrl::AspBclkMasterSelect::SlaveMode,
+            fsync_invert: false,
+            fsync_frc: asp_ctrl::AspBclkOutputControl::Normal,
+            fsync_mstr: asp_ctrl::AspBclkMasterSelect::SlaveMode,
+        }
+        .serialize();
+        assert!(registers.contains(&control2));
+
+        let tx_width = asp_ctrl::AspDataControl1 {
+            num: asp_ctrl::Num::_1,
+            tx_data_width_bits: 32,
+        }
+        .serialize();
+        let rx_width = asp_ctrl::AspDataControl5 {
+            num: asp_ctrl::Num::_1,
+            rx_data_width_bits: 32,
+        }
+        .serialize();
+        assert!(registers.contains(&tx_width));
+        assert!(registers.contains(&rx_width));
+
+        assert!(registers.contains(&enables(2, 2)));
+        assert_eq!(AspConfig::new().frame_bits(), 32);
+    }
+
+    #[test]
+    fn tdm_master_sets_the_slots_and_drives_the_clocks() {
+        let mut config = AspConfig::new();
+        config
+            .set_format(AspFormat::Dsp)
+            .unwrap()
+            .set_channels(8, 4)
+            .unwrap()
+            .set_widths(24, 32)
+            .unwrap()
+            .set_roles(ClockRole::Master, ClockRole::Master);
+        let registers = config.registers();
+
+        let bclk_output = gpio_ctrl1(
+            gpio_ctrl::Num::_3(gpio_ctrl::PinFunction::AlternateFunction),
+            gpio_ctrl::Direction::Output,
+        );
+        assert!(registers.contains(&bclk_output));
+        assert!(registers.contains(&enables(8, 4)));
+        assert_eq!(config.frame_bits(), 8 * 32);
+    }
+
+    #[test]
+    fn widths_out_of_range_are_rejected() {
+        let mut config = AspConfig::new();
+        assert_eq!(
+            config.set_widths(MIN_WORD_BITS - 1, 16).err(),
+            Some(AspError::WordWidthOutOfRange)
+        );
+        assert_eq!(
+            config.set_widths(MAX_WORD_BITS + 1, 64).err(),
+            Some(AspError::WordWidthOutOfRange)
+        );
+        assert_eq!(
+            config.set_widths(24, 16).err(),
+            Some(AspError::SlotWidthOutOfRange)
+        );
+        assert_eq!(
+            config.set_widths(24, MAX_SLOT_BITS + 1).err(),
+            Some(AspError::SlotWidthOutOfRange)
+        );
+
+        // a rejected change leaves the config as it was
+        assert_eq!(config, AspConfig::new());
+    }
+
+    #[test]
+    fn channels_are_checked_against_the_format() {
+        let mut config = AspConfig::new();
+        assert_eq!(
+            config.set_channels(0, 2).err(),
+            Some(AspError::ChannelsOutOfRange)
+        );
+        assert_eq!(
+            config.set_channels(2, 3).err(),
+            Some(AspError::ChannelsOutOfRange)
+        );
+
+        config.set_format(AspFormat::Dsp).unwrap();
+        assert_eq!(
+            config.set_channels(MAX_TDM_CHANNELS + 1, 2).err(),
+            Some(AspError::ChannelsOutOfRange)
+        );
+        config.set_channels(4, 2).unwrap();
+
+        // four channels do not fit in an I2S frame
+        assert_eq!(
+            config.set_format(AspFormat::I2s).err(),
+            Some(AspError::ChannelsOutOfRange)
+        );
+        assert_eq!(config.format(), AspFormat::Dsp);
+    }
+}
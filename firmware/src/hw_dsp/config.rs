@@ -1,6 +1,6 @@
+use super::asp::AspConfig;
 use cs47l63::registers::{
-    audio_serial_port::asp_ctrl,
-    clocking_sample_rates::{clock, fll, sample_rate},
+    clocking_sample_rates::{clock, fll},
     digital_core::{compression, equalizer, mixers},
     gpio::gpio_ctrl,
     input_signal_path::{
@@ -16,50 +16,15 @@ use cs47l63::registers::{
 
 pub const SOFT_RESET: [[u32; 2]; 1] = [reset::SoftReset::new().serialize()];
 
-pub const CLOCK_CONFIGURATION: [[u32; 2]; 9] = [
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_3,
-        select: sample_rate::Select::_16kHz,
-    }
-    .serialize(),
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_2,
-        select: sample_rate::Select::_24kHz,
-    }
-    .serialize(),
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_1,
-        select: sample_rate::Select::_48kHz,
-    }
-    .serialize(),
-    clock::SystemClock1 {
-        frac: clock::Fraction::MultipleOf6M144Hz,
-        freq: clock::Freqency::_49p152MHz,
-        enabled: true,
-        src: clock::Source::FLL1_45to50MHz,
-    }
-    .serialize(),
+// the sample rates and SYSCLK depend on the sample rate so they are written by `SampleRateConfig::codec_registers`
+// and FLL1 depends on the reference clock frequency so it is configured separately (see the `fll` module)
+pub const CLOCK_CONFIGURATION: [[u32; 2]; 2] = [
     clock::AsyncClock1 {
         freq: clock::Freqency::_49p152MHz,
         enabled: true,
         src: clock::Source::FLL1_45to50MHz,
     }
     .serialize(),
-    fll::Fll1Control2 {
-        lock_detect_threshold: 8,
-        lock_detect: true,
-        phase_detect: false,
-        ref_detect: true,
-        divider: fll::ReferenceClockDivider::_1,
-        source: fll::ReferenceClockSource::MCLK1,
-        multiplier: 8,
-    }
-    .serialize(),
-    fll::Fll1Control3 {
-        lambda: 1,
-        theta: 0,
-    }
-    .serialize(),
     fll::FllGpioClock {
         num: fll::Num::_1,
         source: fll::GpioClockSource::Fll,
@@ -67,12 +32,6 @@ pub const CLOCK_CONFIGURATION: [[u32; 2]; 9] = [
         enabled: true,
     }
     .serialize(),
-    fll::Fll1Control1 {
-        control_update: false,
-        hold: false,
-        enabled: true,
-    }
-    .serialize(),
 ];
 
 pub const GPIO_CONFIGURATION: [[u32; 2]; 4] = [
@@ -94,101 +53,10 @@ pub const GPIO_CONFIGURATION: [[u32; 2]; 4] = [
     ),
 ];
 
-// audio serial port setup
-pub const ASP1_ENABLE: [[u32; 2]; 14] = [
-    // enable ASP1 GPIOs
-    gpio_ctrl1(
-        gpio_ctrl::Num::_1(gpio_ctrl::PinFunction::AlternateFunction),
-        gpio_ctrl::Direction::Output,
-    ),
-    gpio_ctrl1(
-        gpio_ctrl::Num::_2(gpio_ctrl::PinFunction::AlternateFunction),
-        gpio_ctrl::Direction::Input,
-    ),
-    gpio_ctrl1(
-        gpio_ctrl::Num::_3(gpio_ctrl::PinFunction::AlternateFunction),
-        gpio_ctrl::Direction::Input,
-    ),
-    gpio_ctrl1(
-        gpio_ctrl::Num::_4(gpio_ctrl::PinFunction::AlternateFunction),
-        gpio_ctrl::Direction::Input,
-    ),
-    gpio_ctrl1(
-        gpio_ctrl::Num::_5(gpio_ctrl::ExtendedPinFunction::ButtonDetectInputOrLogicLevelOutput),
-        gpio_ctrl::Direction::Output,
-    ),
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_1,
-        select: sample_rate::Select::_48kHz,
-    }
-    .serialize(),
-    // disable unused sample rates
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_2,
-        select: sample_rate::Select::None,
-    }
-    .serialize(),
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_3,
-        select: sample_rate::Select::None,
-    }
-    .serialize(),
-    sample_rate::SampleRate {
-        num: sample_rate::Num::_4,
-        select: sample_rate::Select::None,
-    }
-    .serialize(),
-    // set ASP1 in slave mode and 16 bit per channel
-    asp_ctrl::AspControl2 {
-        num: asp_ctrl::Num::_1,
-        rx_width: 0x10,
-        tx_width: 0x10,
-        format: asp_ctrl::AspFormat::I2sMode,
-        bclk_invert: false,
-        bclk_frc: asp_ctrl::AspBclkOutputControl::Normal,
-        bclk_mstr: asp_ctrl::AspBclkMasterSelect::SlaveMode,
-        fsync_invert: false,
-        fsync_frc: asp_ctrl::AspBclkOutputControl::Normal,
-        fsync_mstr: asp_ctrl::AspBclkMasterSelect::SlaveMode,
-    }
-    .serialize(),
-    asp_ctrl::AspControl3 {
-        num: asp_ctrl::Num::_1,
-        dout_hiz_ctrl: asp_ctrl::AspDoutTristateControl::Mode00,
-    }
-    .serialize(),
-    asp_ctrl::AspDataControl1 {
-        num: asp_ctrl::Num::_1,
-        tx_data_width_bits: 32,
-    }
-    .serialize(),
-    asp_ctrl::AspDataControl5 {
-        num: asp_ctrl::Num::_1,
-        rx_data_width_bits: 32,
-    }
-    .serialize(),
-    asp_ctrl::Asp1Enables1 {
-        rx8_enabled: false,
-        rx7_enabled: false,
-        rx6_enabled: false,
-        rx5_enabled: false,
-        rx4_enabled: false,
-        rx3_enabled: false,
-        rx2_enabled: true,
-        rx1_enabled: true,
-        tx8_enabled: false,
-        tx7_enabled: false,
-        tx6_enabled: false,
-        tx5_enabled: false,
-        tx4_enabled: false,
-        tx3_enabled: false,
-        tx2_enabled: true,
-        tx1_enabled: true,
-    }
-    .serialize(),
-];
+// audio serial port setup, the default of `dsp::default_conf_enable` (I2S slave, 16 bit slots, two channels each way)
+pub const ASP1_ENABLE: [[u32; 2]; 9] = AspConfig::new().registers();
 
-const fn gpio_ctrl1(num: gpio_ctrl::Num, direction: gpio_ctrl::Direction) -> [u32; 2] {
+pub(crate) const fn gpio_ctrl1(num: gpio_ctrl::Num, direction: gpio_ctrl::Direction) -> [u32; 2] {
     gpio_ctrl::GpioCtrl1 {
         num,
         direction,
@@ -388,7 +256,8 @@ pub const PDM_MIC_ENABLE_CONFIGURE: [[u32; 2]; 10] = [
     .serialize(),
 ];
 
-pub const PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH: [[u32; 2]; 12] = [
+// the mic half of the passthrough configuration, the inputs are enabled and routed by `routing::pdm_mic_drc_eq`
+pub const PDM_MIC_CONFIGURE_PASSTHOUGH: [[u32; 2]; 7] = [
     // set MICBIASes
     voltage::Ldo2Crtl1 {
         output_voltage_select: voltage::Ldo2OutputVoltageSelect::_2p4V,
@@ -417,14 +286,6 @@ pub const PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH: [[u32; 2]; 12] = [
         mic_bias_1a_enabled: false,
     }
     .serialize(),
-    // enable IN1L and IN1R
-    input_signal_path_enable::InputControl {
-        in2_left_enable: false,
-        in2_right_enable: false,
-        in1_left_enable: true,
-        in1_right_enable: true,
-    }
-    .serialize(),
     // enable PDM mic as digital input
     input_signal_path_config::Input1Control1 {
         oversample_rate_control:
@@ -452,37 +313,6 @@ pub const PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH: [[u32; 2]; 12] = [
         volume_update: true,
     }
     .serialize(),
-    // send MicL + MicR -> Drc1 -> Eq1 -> Out1L
-    mixers::InputSource {
-        reg: mixers::ImportSourceReg::Drc1LInput1,
-        input_volume: 0x40, // 0db
-        status_enabled: false,
-        source_select: mixers::InputSourceSelect::In1LSignalPath,
-    }
-    .serialize(),
-    mixers::InputSource {
-        reg: mixers::ImportSourceReg::Drc1LInput2,
-        input_volume: 0x40, // 0db
-        status_enabled: false,
-        source_select: mixers::InputSourceSelect::In1RSignalPath,
-    }
-    .serialize(),
-    mixers::InputSource {
-        reg: mixers::ImportSourceReg::Eq1Input1,
-        input_volume: 0x40, // 0db
-        status_enabled: false,
-        source_select: mixers::InputSourceSelect::Drc1Left,
-    }
-    .serialize(),
-    mixers::InputSource {
-        reg: mixers::ImportSourceReg::Out1LInput1,
-        //input_volume: 0x30, // -16db
-        //input_volume: 0x38, // -8db
-        input_volume: 0x40, // 0db
-        status_enabled: false,
-        source_select: mixers::InputSourceSelect::Eq1,
-    }
-    .serialize(),
 ];
 
 // use default frequency bands and increase the volume
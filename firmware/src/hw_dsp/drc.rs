@@ -0,0 +1,721 @@
+use cs47l63::registers::digital_core::compression;
+
+// Dynamic range compressor settings in real units (dB, ratios and times) instead of raw register codes.
+//
+// The static curve of the compressor has two knees:
+// - above knee 1 the signal is compressed with the upper ratio (knee 1 input is the compression threshold)
+// - between knee 2 and knee 1 the signal is compressed with the lower ratio
+// - below knee 2 the optional noise gate expands the signal
+// Knee levels are in 1.5dB steps and are rounded to the nearest step.
+// Attack and decay times are rounded to the nearest time supported by the device.
+// Each DRC is stereo: the settings apply to both channels and the left and right channels are enabled separately.
+
+/// Knee 1 input range in dB
+pub const KNEE1_INPUT_MIN_DB: f32 = -45.0;
+pub const KNEE1_INPUT_MAX_DB: f32 = 0.0;
+
+/// Knee 1 output range in dB
+pub const KNEE1_OUTPUT_MIN_DB: f32 = -30.0;
+pub const KNEE1_OUTPUT_MAX_DB: f32 = 0.0;
+
+/// Knee 2 (noise gate) input range in dB
+pub const KNEE2_INPUT_MIN_DB: f32 = -82.5;
+pub const KNEE2_INPUT_MAX_DB: f32 = -36.0;
+
+/// Knee 2 (noise gate) output range in dB
+pub const KNEE2_OUTPUT_MIN_DB: f32 = -76.5;
+pub const KNEE2_OUTPUT_MAX_DB: f32 = -30.0;
+
+const KNEE_STEP_DB: f32 = 1.5;
+
+// knee 2 levels written when the noise gate is disabled (the device ignores them), as in `config::COMPRESSION_ENABLE_CONFIGURE`
+const UNUSED_KNEE2_INPUT_DB: f32 = -36.0;
+const UNUSED_KNEE2_OUTPUT_DB: f32 = -57.0;
+
+// supported times in microseconds, in register order
+const ATTACK_TIMES_US: [u32; 11] = [
+    182, 363, 726, 1450, 2900, 5800, 11600, 23200, 46400, 92800, 185600,
+];
+const DECAY_TIMES_US: [u32; 12] = [
+    1450, 2900, 5800, 11600, 23250, 46500, 93000, 186000, 372000, 743000, 1490000, 2970000,
+];
+const QUICK_RELEASE_DECAY_TIMES_US: [u32; 3] = [725, 1450, 5800];
+
+/// One of the two stereo compressors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Drc {
+    Drc1,
+    Drc2,
+}
+
+/// Compression ratio (input:output) of a region of the static curve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Ratio {
+    /// 1:1 (no compression)
+    R1,
+    R2,
+    R4,
+    R8,
+    /// 16:1 (upper region only)
+    R16,
+    /// the output does not rise at all (limiter)
+    Infinite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct NoiseGate {
+    /// knee 2 input level, below this the signal is expanded (-82.5dB to -36dB)
+    pub threshold_db: f32,
+    /// knee 2 output level (-76.5dB to -30dB)
+    pub output_db: f32,
+    /// expansion ratio (1:1, 1:2, 1:4 or 1:8)
+    pub expansion: u8,
+    /// minimum gain applied by the noise gate, -36dB to +36dB in 6dB steps
+    pub min_gain_db: i8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct QuickRelease {
+    /// how far (12dB, 18dB, 24dB or 30dB) the signal must drop below the average to trigger a quick release
+    pub threshold_db: u8,
+    pub decay_us: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct DrcSettings {
+    /// compression threshold (0dB to -45dB)
+    pub knee1_input_db: f32,
+    /// output level at the threshold (0dB to -30dB)
+    pub knee1_output_db: f32,
+    /// ratio above knee 1
+    pub upper_ratio: Ratio,
+    /// ratio between knee 2 and knee 1
+    pub lower_ratio: Ratio,
+    pub noise_gate: Option<NoiseGate>,
+    /// 182us to 185.6ms
+    pub attack_us: u32,
+    /// 1.45ms to 2.97s
+    pub decay_us: u32,
+    /// 12dB, 18dB, 24dB or 36dB
+    pub max_gain_db: i8,
+    /// 0dB, -12dB, -18dB, -24dB or -36dB
+    pub min_gain_db: i8,
+    /// cannot be used together with quick release
+    pub anticlip: bool,
+    /// cannot be used together with anticlip
+    pub quick_release: Option<QuickRelease>,
+}
+
+impl Default for DrcSettings {
+    /// the settings used by `config::COMPRESSION_ENABLE_CONFIGURE`
+    fn default() -> Self {
+        Self {
+            knee1_input_db: -21.0,
+            knee1_output_db: -16.5,
+            upper_ratio: Ratio::R8,
+            lower_ratio: Ratio::R2,
+            noise_gate: None,
+            attack_us: 726,
+            decay_us: 23250,
+            max_gain_db: 12,
+            min_gain_db: -24,
+            anticlip: true,
+            quick_release: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DrcError {
+    /// A knee level is outside of its range
+    KneeOutOfRange,
+    /// Knee 2 must be below knee 1
+    KneeOrder,
+    /// The ratio is not supported in this region of the curve
+    RatioNotSupported(Ratio),
+    /// Attack or decay time outside of the supported range
+    TimeOutOfRange(u32),
+    MaxGainNotSupported(i8),
+    MinGainNotSupported(i8),
+    NoiseGateMinGainNotSupported(i8),
+    NoiseGateExpansionNotSupported(u8),
+    QuickReleaseThresholdNotSupported(u8),
+    /// Anticlip and quick release cannot both be enabled
+    AnticlipWithQuickRelease,
+}
+
+// the register code of a knee level, 0 is the top of the range and each step is 1.5dB lower
+fn knee_code(level_db: f32, min_db: f32, max_db: f32) -> Result<u8, DrcError> {
+    if !(min_db..=max_db).contains(&level_db) {
+        return Err(DrcError::KneeOutOfRange);
+    }
+
+    Ok(libm::roundf((max_db - level_db) / KNEE_STEP_DB) as u8)
+}
+
+// index of the nearest supported time (on a log scale) or an error if outside of the supported range
+fn time_index(time_us: u32, times_us: &[u32]) -> Result<usize, DrcError> {
+    let (first, last) = (times_us[0], times_us[times_us.len() - 1]);
+    if time_us < first || time_us > last {
+        return Err(DrcError::TimeOutOfRange(time_us));
+    }
+
+    let distance = |t: u32| libm::fabsf(libm::logf(t as f32 / time_us as f32));
+    let mut nearest = 0;
+    for (index, t) in times_us.iter().enumerate() {
+        if distance(*t) < distance(times_us[nearest]) {
+            nearest = index;
+        }
+    }
+
+    Ok(nearest)
+}
+
+impl DrcSettings {
+    /// checks the ranges of all settings and that they can be used together
+    pub fn validate(&self) -> Result<(), DrcError> {
+        self.control2()?;
+        self.control3()?;
+        self.control4()?;
+        Ok(())
+    }
+
+    /// the four DrcControl registers of the DRC (send them with `dsp::reg_conf_write`)
+    /// with the left and right channels enabled as requested
+    pub fn registers(&self, drc: Drc, left: bool, right: bool) -> Result<[[u32; 2]; 4], DrcError> {
+        let (control1, control2, control3, control4) = control_regs(drc);
+
+        Ok([
+            compression::DrcControl1 {
+                reg: control1,
+                left_enabled: left,
+                right_enabled: right,
+            }
+            .serialize(),
+            self.control2()?.serialize(control2),
+            self.control3()?.serialize(control3),
+            self.control4()?.serialize(control4),
+        ])
+    }
+
+    fn control2(&self) -> Result<Control2, DrcError> {
+        if self.anticlip && self.quick_release.is_some() {
+            return Err(DrcError::AnticlipWithQuickRelease);
+        }
+
+        let max_gain = match self.max_gain_db {
+            12 => compression::MaxGain::_12db,
+            18 => compression::MaxGain::_18db,
+            24 => compression::MaxGain::_24db,
+            36 => compression::MaxGain::_36db,
+            other => return Err(DrcError::MaxGainNotSupported(other)),
+        };
+
+        let min_gain = match self.min_gain_db {
+            0 => compression::MinGain::_0db,
+            -12 => compression::MinGain::Minus12db,
+            -18 => compression::MinGain::Minus18db,
+            -24 => compression::MinGain::Minus24db,
+            -36 => compression::MinGain::Minus36db,
+            other => return Err(DrcError::MinGainNotSupported(other)),
+        };
+
+        Ok(Control2 {
+            anticlip: self.anticlip,
+            quick_release: self.quick_release.is_some(),
+            noise_gate: self.noise_gate.is_some(),
+            max_gain,
+            min_gain,
+            attack: attack_rate(time_index(self.attack_us, &ATTACK_TIMES_US)?),
+            decay: decay_rate(time_index(self.decay_us, &DECAY_TIMES_US)?),
+        })
+    }
+
+    fn control3(&self) -> Result<Control3, DrcError> {
+        let upper = match self.upper_ratio {
+            Ratio::R1 => compression::CompressorSlopeUpperRegion::_1,
+            Ratio::R2 => compression::CompressorSlopeUpperRegion::_1div2,
+            Ratio::R4 => compression::CompressorSlopeUpperRegion::_1div4,
+            Ratio::R8 => compression::CompressorSlopeUpperRegion::_1div8,
+            Ratio::R16 => compression::CompressorSlopeUpperRegion::_1div16,
+            Ratio::Infinite => compression::CompressorSlopeUpperRegion::_0,
+        };
+
+        let lower = match self.lower_ratio {
+            Ratio::R1 => compression::CompressorSlopeLowerRegion::_1,
+            Ratio::R2 => compression::CompressorSlopeLowerRegion::_1div2,
+            Ratio::R4 => compression::CompressorSlopeLowerRegion::_1div4,
+            Ratio::R8 => compression::CompressorSlopeLowerRegion::_1div8,
+            Ratio::Infinite => compression::CompressorSlopeLowerRegion::_0,
+            other => return Err(DrcError::RatioNotSupported(other)),
+        };
+
+        // the noise gate and quick release fields are ignored by the device when these features are disabled
+        let (noise_gate_slope, noise_gate_min_gain) = match self.noise_gate {
+            Some(noise_gate) => (
+                noise_gate_slope(noise_gate.expansion)?,
+                noise_gate_min_gain(noise_gate.min_gain_db)?,
+            ),
+            None => (
+                compression::NoiseGateSlope::_4,
+                compression::NoiseGateMinGain::_12db,
+            ),
+        };
+
+        let (quick_release_threshold, quick_release_decay) = match self.quick_release {
+            Some(quick_release) => (
+                quick_release_threshold(quick_release.threshold_db)?,
+                quick_release_decay(time_index(
+                    quick_release.decay_us,
+                    &QUICK_RELEASE_DECAY_TIMES_US,
+                )?),
+            ),
+            None => (
+                compression::QuickReleaseThreshold::_18db,
+                compression::QuickReleaseDecayRate::_1_45ms,
+            ),
+        };
+
+        Ok(Control3 {
+            upper,
+            lower,
+            quick_release_decay,
+            quick_release_threshold,
+            noise_gate_slope,
+            noise_gate_min_gain,
+        })
+    }
+
+    fn control4(&self) -> Result<Control4, DrcError> {
+        let knee1_input = knee_code(self.knee1_input_db, KNEE1_INPUT_MIN_DB, KNEE1_INPUT_MAX_DB)?;
+        let knee1_output = knee_code(
+            self.knee1_output_db,
+            KNEE1_OUTPUT_MIN_DB,
+            KNEE1_OUTPUT_MAX_DB,
+        )?;
+
+        let (knee2_input, knee2_output) = match self.noise_gate {
+            Some(noise_gate) => {
+                if noise_gate.threshold_db >= self.knee1_input_db
+                    || noise_gate.output_db >= self.knee1_output_db
+                {
+                    return Err(DrcError::KneeOrder);
+                }
+
+                (
+                    knee_code(
+                        noise_gate.threshold_db,
+                        KNEE2_INPUT_MIN_DB,
+                        KNEE2_INPUT_MAX_DB,
+                    )?,
+                    knee_code(
+                        noise_gate.output_db,
+                        KNEE2_OUTPUT_MIN_DB,
+                        KNEE2_OUTPUT_MAX_DB,
+                    )?,
+                )
+            }
+            None => (
+                knee_code(
+                    UNUSED_KNEE2_INPUT_DB,
+                    KNEE2_INPUT_MIN_DB,
+                    KNEE2_INPUT_MAX_DB,
+                )?,
+                knee_code(
+                    UNUSED_KNEE2_OUTPUT_DB,
+                    KNEE2_OUTPUT_MIN_DB,
+                    KNEE2_OUTPUT_MAX_DB,
+                )?,
+            ),
+        };
+
+        Ok(Control4 {
+            knee1_input,
+            knee1_output,
+            knee2_input,
+            knee2_output,
+        })
+    }
+}
+
+// validated register fields, serialized once the register of the DRC is known
+struct Control2 {
+    anticlip: bool,
+    quick_release: bool,
+    noise_gate: bool,
+    max_gain: compression::MaxGain,
+    min_gain: compression::MinGain,
+    attack: compression::GainAttackRate,
+    decay: compression::GainDecayRate,
+}
+
+impl Control2 {
+    fn serialize(self, reg: compression::DrcControl2Reg) -> [u32; 2] {
+        compression::DrcControl2 {
+            reg,
+            anticlip_enabled: self.anticlip,
+            quick_release_enabled: self.quick_release,
+            knee2_output_enabled: self.noise_gate,
+            signal_detect_enabled: false,
+            signal_detect_mode: compression::SignalDetectMode::PeakThreshold, // ignored
+            knee2_input_enabled: self.noise_gate,
+            signal_detect_peak_threshold: compression::SignalDetectPeakThreshold::_12db, // ignored
+            signal_detect_rms_threshold: 0,                                              // ignored
+            max_gain: self.max_gain,
+            min_gain: self.min_gain,
+            gain_decay_rate: self.decay,
+            gain_attack_rate: self.attack,
+        }
+        .serialize()
+    }
+}
+
+struct Control3 {
+    upper: compression::CompressorSlopeUpperRegion,
+    lower: compression::CompressorSlopeLowerRegion,
+    quick_release_decay: compression::QuickReleaseDecayRate,
+    quick_release_threshold: compression::QuickReleaseThreshold,
+    noise_gate_slope: compression::NoiseGateSlope,
+    noise_gate_min_gain: compression::NoiseGateMinGain,
+}
+
+impl Control3 {
+    fn serialize(self, reg: compression::DrcControl3Reg) -> [u32; 2] {
+        compression::DrcControl3 {
+            reg,
+            compressor_slope_lower: self.lower,
+            compressor_slope_upper: self.upper,
+            quick_release_decay_rate: self.quick_release_decay,
+            quick_release_threshold: self.quick_release_threshold,
+            noise_gate_slope: self.noise_gate_slope,
+            noise_gate_min_gain: self.noise_gate_min_gain,
+        }
+        .serialize()
+    }
+}
+
+struct Control4 {
+    knee1_input: u8,
+    knee1_output: u8,
+    knee2_input: u8,
+    knee2_output: u8,
+}
+
+impl Control4 {
+    fn serialize(self, reg: compression::DrcControl4Reg) -> [u32; 2] {
+        compression::DrcControl4 {
+            reg,
+            knee1_output_level: self.knee1_output,
+            knee1_input_level: self.knee1_input,
+            knee2_output_level: self.knee2_output,
+            knee2_input_level: self.knee2_input,
+        }
+        .serialize()
+    }
+}
+
+fn control_regs(
+    drc: Drc,
+) -> (
+    compression::DrcControl1Reg,
+    compression::DrcControl2Reg,
+    compression::DrcControl3Reg,
+    compression::DrcControl4Reg,
+) {
+    match drc {
+        Drc::Drc1 => (
+            compression::DrcControl1Reg::Drc1Control1,
+            compression::DrcControl2Reg::Drc1Control2,
+            compression::DrcControl3Reg::Drc1Control3,
+            compression::DrcControl4Reg::Drc1Control4,
+        ),
+        Drc::Drc2 => (
+            compression::DrcControl1Reg::Drc2Control1,
+            compression::DrcControl2Reg::Drc2Control2,
+            compression::DrcControl3Reg::Drc2Control3,
+            compression::DrcControl4Reg::Drc2Control4,
+        ),
+    }
+}
+
+// in the order of ATTACK_TIMES_US
+fn attack_rate(index: usize) -> compression::GainAttackRate {
+    use compression::GainAttackRate::*;
+
+    match index {
+        0 => _182us,
+        1 => _363us,
+        2 => _726us,
+        3 => _1_45ms,
+        4 => _2_9ms,
+        5 => _5_8ms,
+        6 => _11_6ms,
+        7 => _23_2ms,
+        8 => _46_4ms,
+        9 => _92_8ms,
+        _ => _185_6ms,
+    }
+}
+
+// in the order of DECAY_TIMES_US
+fn decay_rate(index: usize) -> compression::GainDecayRate {
+    use compression::GainDecayRate::*;
+
+    match index {
+        0 => _1_45ms,
+        1 => _2_9ms,
+        2 => _5_8ms,
+        3 => _11_6ms,
+        4 => _23_25ms,
+        5 => _46_5ms,
+        6 => _93ms,
+        7 => _186ms,
+        8 => _372ms,
+        9 => _743ms,
+        10 => _1_49s,
+        _ => _2_97s,
+    }
+}
+
+// in the order of QUICK_RELEASE_DECAY_TIMES_US
+fn quick_release_decay(index: usize) -> compression::QuickReleaseDecayRate {
+    match index {
+        0 => compression::QuickReleaseDecayRate::_0_725ms,
+        1 => compression::QuickReleaseDecayRate::_1_45ms,
+        _ => compression::QuickReleaseDecayRate::_5_8ms,
+    }
+}
+
+fn quick_release_threshold(
+    threshold_db: u8,
+) -> Result<compression::QuickReleaseThreshold, DrcError> {
+    match threshold_db {
+        12 => Ok(compression::QuickReleaseThreshold::_12db),
+        18 => Ok(compression::QuickReleaseThreshold::_18db),
+        24 => Ok(compression::QuickReleaseThreshold::_24db),
+        30 => Ok(compression::QuickReleaseThreshold::_30db),
+        other => Err(DrcError::QuickReleaseThresholdNotSupported(other)),
+    }
+}
+
+fn noise_gate_slope(expansion: u8) -> Result<compression::NoiseGateSlope, DrcError> {
+    match expansion {
+        1 => Ok(compression::NoiseGateSlope::_1),
+        2 => Ok(compression::NoiseGateSlope::_2),
+        4 => Ok(compression::NoiseGateSlope::_4),
+        8 => Ok(compression::NoiseGateSlope::_8),
+        other => Err(DrcError::NoiseGateExpansionNotSupported(other)),
+    }
+}
+
+fn noise_gate_min_gain(min_gain_db: i8) -> Result<compression::NoiseGateMinGain, DrcError> {
+    use compression::NoiseGateMinGain::*;
+
+    match min_gain_db {
+        -36 => Ok(Minus36db),
+        -30 => Ok(Minus30db),
+        -24 => Ok(Minus24db),
+        -18 => Ok(Minus18db),
+        -12 => Ok(Minus12db),
+        -6 => Ok(Minus6db),
+        0 => Ok(_0db),
+        6 => Ok(_6db),
+        12 => Ok(_12db),
+        18 => Ok(_18db),
+        24 => Ok(_24db),
+        30 => Ok(_30db),
+        36 => Ok(_36db),
+        other => Err(DrcError::NoiseGateMinGainNotSupported(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::config::COMPRESSION_ENABLE_CONFIGURE;
+
+    fn registers(settings: DrcSettings) -> Result<[[u32; 2]; 4], DrcError> {
+        settings.registers(Drc::Drc1, true, false)
+    }
+
+    fn noise_gate() -> NoiseGate {
+        NoiseGate {
+            threshold_db: -60.0,
+            output_db: -66.0,
+            expansion: 2,
+            min_gain_db: -12,
+        }
+    }
+
+    #[test]
+    fn default_matches_the_preset() {
+        assert_eq!(
+            registers(DrcSettings::default()),
+            Ok(COMPRESSION_ENABLE_CONFIGURE)
+        );
+    }
+
+    #[test]
+    fn levels_and_times_are_rounded_to_the_nearest_step() {
+        let rounded = DrcSettings {
+            knee1_input_db: -21.6,
+            attack_us: 800,
+            decay_us: 20000,
+            ..DrcSettings::default()
+        };
+        assert_eq!(registers(rounded), registers(DrcSettings::default()));
+    }
+
+    #[test]
+    fn out_of_range_settings_are_rejected() {
+        let default = DrcSettings::default();
+        let cases = [
+            (
+                DrcSettings {
+                    knee1_input_db: -46.0,
+                    ..default
+                },
+                DrcError::KneeOutOfRange,
+            ),
+            (
+                DrcSettings {
+                    knee1_output_db: 1.0,
+                    ..default
+                },
+                DrcError::KneeOutOfRange,
+            ),
+            (
+                DrcSettings {
+                    noise_gate: Some(NoiseGate {
+                        threshold_db: -90.0,
+                        ..noise_gate()
+                    }),
+                    ..default
+                },
+                DrcError::KneeOutOfRange,
+            ),
+            (
+                DrcSettings {
+                    knee1_input_db: -40.0,
+                    noise_gate: Some(NoiseGate {
+                        threshold_db: -39.0,
+                        ..noise_gate()
+                    }),
+                    ..default
+                },
+                DrcError::KneeOrder,
+            ),
+            (
+                DrcSettings {
+                    lower_ratio: Ratio::R16,
+                    ..default
+                },
+                DrcError::RatioNotSupported(Ratio::R16),
+            ),
+            (
+                DrcSettings {
+                    attack_us: 100,
+                    ..default
+                },
+                DrcError::TimeOutOfRange(100),
+            ),
+            (
+                DrcSettings {
+                    decay_us: 3_000_000,
+                    ..default
+                },
+                DrcError::TimeOutOfRange(3_000_000),
+            ),
+            (
+                DrcSettings {
+                    max_gain_db: 30,
+                    ..default
+                },
+                DrcError::MaxGainNotSupported(30),
+            ),
+            (
+                DrcSettings {
+                    min_gain_db: -6,
+                    ..default
+                },
+                DrcError::MinGainNotSupported(-6),
+            ),
+            (
+                DrcSettings {
+                    noise_gate: Some(NoiseGate {
+                        min_gain_db: 5,
+                        ..noise_gate()
+                    }),
+                    ..default
+                },
+                DrcError::NoiseGateMinGainNotSupported(5),
+            ),
+            (
+                DrcSettings {
+                    noise_gate: Some(NoiseGate {
+                        expansion: 3,
+                        ..noise_gate()
+                    }),
+                    ..default
+                },
+                DrcError::NoiseGateExpansionNotSupported(3),
+            ),
+            (
+                DrcSettings {
+                    anticlip: false,
+                    quick_release: Some(QuickRelease {
+                        threshold_db: 20,
+                        decay_us: 1450,
+                    }),
+                    ..default
+                },
+                DrcError::QuickReleaseThresholdNotSupported(20),
+            ),
+            (
+                DrcSettings {
+                    quick_release: Some(QuickRelease {
+                        threshold_db: 18,
+                        decay_us: 1450,
+                    }),
+                    ..default
+                },
+                DrcError::AnticlipWithQuickRelease,
+            ),
+        ];
+
+        for (settings, error) in cases {
+            assert_eq!(settings.validate(), Err(error));
+            assert_eq!(registers(settings), Err(error));
+        }
+    }
+
+    #[test]
+    fn noise_gate_and_quick_release_are_enabled_when_set() {
+        let settings = DrcSettings {
+            anticlip: false,
+            noise_gate: Some(noise_gate()),
+            quick_release: Some(QuickRelease {
+                threshold_db: 24,
+                decay_us: 725,
+            }),
+            ..DrcSettings::default()
+        };
+        assert_eq!(settings.validate(), Ok(()));
+
+        let [_, control2, control3, control4] = registers(settings).unwrap();
+        assert_ne!(control2, COMPRESSION_ENABLE_CONFIGURE[1]);
+        assert_ne!(control3, COMPRESSION_ENABLE_CONFIGURE[2]);
+        assert_eq!(
+            control4[1],
+            compression::DrcControl4 {
+                reg: compression::DrcControl4Reg::Drc1Control4,
+                knee1_output_level: 0x0B,
+                knee1_input_level: 0x0E,
+                // -66dB and -60dB
+                knee2_output_level: 0x18,
+                knee2_input_level: 0x10,
+            }
+            .serialize()[1]
+        );
+    }
+}
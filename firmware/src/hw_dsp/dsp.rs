@@ -1,123 +1,230 @@
 #![allow(unused_imports)]
 
+use super::asp::AspConfig;
 use super::config::{
     ASP1_ENABLE, CLOCK_CONFIGURATION, COMPRESSION_ENABLE_CONFIGURE, EQUALIZER_ENABLE_CONFIGURE,
     FLL_DISABLE, FLL_ENABLE, GPIO_CONFIGURATION, OUTPUT_ENABLE_BASIC, OUTPUT_ENABLE_COMPRESSION,
-    OUTPUT_ENABLE_EQ, OUTPUT_ENABLE_PASSTHOUGH, PDM_MIC_ENABLE_CONFIGURE,
-    PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH, SOFT_RESET,
+    OUTPUT_ENABLE_EQ, OUTPUT_ENABLE_PASSTHOUGH, PDM_MIC_CONFIGURE_PASSTHOUGH,
+    PDM_MIC_ENABLE_CONFIGURE, SOFT_RESET,
+};
+use super::error::DspError;
+use super::fll;
+use super::reg_list::RegList;
+use super::routing::{self, MAX_REGISTERS};
+use super::verify::{reg_conf_write_verified, DeviceBus, VerifyReport};
+use crate::sample_rate::SampleRateConfig;
+use cs47l63::{
+    driver,
+    hw_interface::Bus,
+    spec::{CS47L63_BOOT_DONE_EINT1_MASK, CS47L63_DEVID, CS47L63_IRQ1_EINT_2, CS47L63_REVID},
 };
-use super::shared_bus::{BusImpl, SharedBus};
-use cs47l63::{driver, hw_interface::Bus, registers::output_signal_path::volume_ctrl};
-use embassy_nrf::spim;
-use embassy_time::{Duration, Timer};
 
-/// resets and initialises the device
-/// this assumes that the user has driven the reset pin low, then waited 4ms then high again
-pub async fn reset(shared_bus: &SharedBus) -> Result<(), spim::Error> {
-    // hard reset with patch
-    let mut bus = shared_bus.borrow().await;
-    driver::reset(&mut bus).await?;
-    drop(bus);
+/// The device id of the CS47L63
+pub const DEVICE_ID: u32 = 0x47A63;
 
-    // soft reset
-    reg_conf_write(shared_bus, &SOFT_RESET).await?;
-    Timer::after(Duration::from_micros(3000)).await;
-    Ok(())
+/// The device id is in the lower 24 bits of the DEVID register
+pub const DEVICE_ID_MASK: u32 = 0xFF_FFFF;
+
+// the REVID register holds the alpha (silicon) revision in bits 7:4 and the metal revision in bits 3:0
+const AREVID_SHIFT: u32 = 4;
+const REVID_FIELD_MASK: u32 = 0xF;
+
+// number of times the device id is read (1ms apart) before giving up on a codec that has just been released from reset
+const PROBE_ATTEMPTS: u32 = 5;
+
+// number of times the boot done flag is polled (1ms apart) after a soft reset
+const BOOT_POLL_ATTEMPTS: u32 = 10;
+
+/// Identity of the codec read by `probe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct CodecInfo {
+    pub device_id: u32,
+    /// silicon revision (0 is revision A)
+    pub alpha_revision: u8,
+    pub metal_revision: u8,
+}
+
+impl CodecInfo {
+    /// the revision letter, e.g. 'B' for an alpha revision of 1
+    pub fn revision_letter(&self) -> char {
+        (b'A' + self.alpha_revision) as char
+    }
+}
+
+/// checks that a CS47L63 is answering on the bus and reads its revision
+/// call this after the reset pin has been released and before any configuration is written
+/// a bus that reads back all zeros or all ones means nothing answered (e.g. a disconnected codec)
+pub async fn probe<E>(bus: &mut impl Bus<E>) -> Result<CodecInfo, DspError<E>> {
+    for attempt in 0..PROBE_ATTEMPTS {
+        if attempt > 0 {
+            bus.delay_ms(1).await;
+        }
+
+        let device_id = bus.read(CS47L63_DEVID).await.map_err(DspError::Bus)?;
+        if device_id == 0 || device_id == u32::MAX {
+            continue;
+        }
+
+        if device_id & DEVICE_ID_MASK != DEVICE_ID {
+            return Err(DspError::WrongDeviceId { found: device_id });
+        }
+
+        let revid = bus.read(CS47L63_REVID).await.map_err(DspError::Bus)?;
+        return Ok(CodecInfo {
+            device_id: device_id & DEVICE_ID_MASK,
+            alpha_revision: ((revid >> AREVID_SHIFT) & REVID_FIELD_MASK) as u8,
+            metal_revision: (revid & REVID_FIELD_MASK) as u8,
+        });
+    }
+
+    Err(DspError::NotResponding)
 }
 
 /// resets and initialises the device
 /// this assumes that the user has driven the reset pin low, then waited 4ms then high again
-pub async fn reset_spi(bus: &mut impl Bus<spim::Error>) -> Result<(), spim::Error> {
+/// works with any bus: a borrowed `SharedBus`, an owned `SpiBusInnerFixed` or a `PowerGatedBus`
+/// the soft reset at the end invalidates the register cache of a `SharedBus`
+pub async fn reset<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
     // hard reset with patch
-    driver::reset(bus).await?;
+    driver::reset(bus).await.map_err(DspError::Bus)?;
 
     // soft reset
-    reg_conf_write_spi(bus, &SOFT_RESET).await?;
-    Timer::after(Duration::from_micros(3000)).await;
-    Ok(())
+    reg_conf_write(bus, &SOFT_RESET).await?;
+    bus.delay_ms(3).await;
+    wait_for_boot(bus).await
 }
 
-pub async fn default_conf_enable(shared_bus: &SharedBus) -> Result<(), spim::Error> {
-    reg_conf_write(shared_bus, &CLOCK_CONFIGURATION).await?;
-    reg_conf_write(shared_bus, &GPIO_CONFIGURATION).await?;
-    reg_conf_write(shared_bus, &OUTPUT_ENABLE_PASSTHOUGH).await?;
-    reg_conf_write(shared_bus, &PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH).await?;
-    reg_conf_write(shared_bus, &EQUALIZER_ENABLE_CONFIGURE).await?;
-    reg_conf_write(shared_bus, &COMPRESSION_ENABLE_CONFIGURE).await?;
-
-    // fll toggle
-    reg_conf_write(shared_bus, &FLL_DISABLE).await?;
-    Timer::after(Duration::from_micros(1000)).await;
-    reg_conf_write(shared_bus, &FLL_ENABLE).await?;
-    Ok(())
-}
+// the codec raises the boot done interrupt flag once it has booted
+async fn wait_for_boot<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+    for _ in 0..BOOT_POLL_ATTEMPTS {
+        let status = bus.read(CS47L63_IRQ1_EINT_2).await.map_err(DspError::Bus)?;
+        if status & CS47L63_BOOT_DONE_EINT1_MASK != 0 {
+            // the flag is cleared by writing 1 to it
+            bus.write(CS47L63_IRQ1_EINT_2, CS47L63_BOOT_DONE_EINT1_MASK)
+                .await
+                .map_err(DspError::Bus)?;
+            return Ok(());
+        }
+
+        bus.delay_ms(1).await;
+    }
 
-pub async fn default_conf_enable_spi(bus: &mut impl Bus<spim::Error>) -> Result<(), spim::Error> {
-    reg_conf_write_spi(bus, &CLOCK_CONFIGURATION).await?;
-    reg_conf_write_spi(bus, &GPIO_CONFIGURATION).await?;
-    reg_conf_write_spi(bus, &OUTPUT_ENABLE_PASSTHOUGH).await?;
-    reg_conf_write_spi(bus, &PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH).await?;
-    reg_conf_write_spi(bus, &EQUALIZER_ENABLE_CONFIGURE).await?;
-    reg_conf_write_spi(bus, &COMPRESSION_ENABLE_CONFIGURE).await?;
-
-    // fll toggle
-    reg_conf_write_spi(bus, &FLL_DISABLE).await?;
-    Timer::after(Duration::from_micros(1000)).await;
-    reg_conf_write_spi(bus, &FLL_ENABLE).await?;
-    Ok(())
+    Err(DspError::BootFailed)
 }
 
-pub async fn volume_mute<E>(bus: &mut impl Bus<E>, mute: bool) -> Result<(), E> {
-    let mut out_vol: volume_ctrl::Out1LVolume1 =
-        bus.read(volume_ctrl::Out1LVolume1::REG).await?.into();
-    out_vol.mute = mute;
-    out_vol.update = true;
-    let [reg, val] = out_vol.serialize();
-    bus.write(reg, val).await?;
-    Ok(())
+// the configuration applied by default_conf_enable after the clocks and sample rates
+const DEFAULT_CONFIGURATION: [&[[u32; 2]]; 3] = [
+    &GPIO_CONFIGURATION,
+    &OUTPUT_ENABLE_PASSTHOUGH,
+    &PDM_MIC_CONFIGURE_PASSTHOUGH,
+];
+
+// applied after the default routing
+const DEFAULT_PROCESSING: [&[[u32; 2]]; 2] =
+    [&EQUALIZER_ENABLE_CONFIGURE, &COMPRESSION_ENABLE_CONFIGURE];
+
+/// the mixer routing applied by default_conf_enable (MicL + MicR -> Drc1 -> Eq1 -> Out1L)
+pub fn default_routing<E>() -> Result<RegList<MAX_REGISTERS>, DspError<E>> {
+    routing::pdm_mic_drc_eq()
+        .and_then(|graph| graph.registers())
+        .map_err(DspError::Routing)
 }
 
-pub async fn volume_adjust<E>(bus: &mut impl Bus<E>, adjustment_db: i32) -> Result<i32, E> {
-    const MAX_VOLUME_DB: i32 = 64;
-    const MAX_VOLUME_REG_VAL: i32 = 0x80;
+/// configures the codec for streaming at the sample rate given with the audio serial port set up as `asp`
+/// (`AspConfig::new()` matches the nRF I2S as master, check other settings with `AspConfig::check_nrf`)
+/// the FLL is started last and `DspError::FllLockFailed` means everything else was configured but the I2S master clock is not running
+pub async fn default_conf_enable<E>(
+    bus: &mut impl Bus<E>,
+    rate: &SampleRateConfig,
+    asp: &AspConfig,
+) -> Result<(), DspError<E>> {
+    reg_conf_write(bus, &CLOCK_CONFIGURATION).await?;
+    reg_conf_write(bus, &rate.codec_registers()).await?;
+    reg_conf_write(bus, &asp.registers()).await?;
+    for config in DEFAULT_CONFIGURATION {
+        reg_conf_write(bus, config).await?;
+    }
 
-    let mut out_vol: volume_ctrl::Out1LVolume1 =
-        bus.read(volume_ctrl::Out1LVolume1::REG).await?.into();
+    reg_conf_write(bus, &default_routing()?).await?;
+    for config in DEFAULT_PROCESSING {
+        reg_conf_write(bus, config).await?;
+    }
 
-    // The adjustment is in dB, 1 bit equals 0.5 dB,
-    // so multiply by 2 to get increments of 1 dB
-    let volume = (out_vol.volume as i32) + (adjustment_db * 2);
+    // SYSCLK comes from the FLL
+    fll::start(bus, rate.fll()).await
+}
 
-    if volume < 0 {
-        return Ok(-64);
-    } else if volume > MAX_VOLUME_REG_VAL {
-        return Ok(0);
+/// same as default_conf_enable but every register is read back after it is written
+/// returns a report of all the registers that did not read back the value written
+/// use `VerifyReport::result` to turn mismatches into a `DspError::VerificationMismatch`
+/// when using a `SharedBus` pass `bus.uncached()` so that the value is read back from the device rather than the cache
+pub async fn default_conf_enable_verified<E>(
+    bus: &mut impl DeviceBus<E>,
+    rate: &SampleRateConfig,
+    asp: &AspConfig,
+) -> Result<VerifyReport, DspError<E>> {
+    let mut report = VerifyReport::new();
+    reg_conf_write_verified(bus, &CLOCK_CONFIGURATION, &mut report).await?;
+    reg_conf_write_verified(bus, &rate.codec_registers(), &mut report).await?;
+    reg_conf_write_verified(bus, &asp.registers(), &mut report).await?;
+    for config in DEFAULT_CONFIGURATION {
+        reg_conf_write_verified(bus, config, &mut report).await?;
     }
 
-    out_vol.volume = volume as u8;
-    out_vol.update = true;
-    let [reg, val] = out_vol.serialize();
-    bus.write(reg, val).await?;
-    Ok(volume / 2 - MAX_VOLUME_DB)
+    reg_conf_write_verified(bus, &default_routing()?, &mut report).await?;
+    for config in DEFAULT_PROCESSING {
+        reg_conf_write_verified(bus, config, &mut report).await?;
+    }
+
+    reg_conf_write_verified(bus, &FLL_DISABLE, &mut report).await?;
+    reg_conf_write_verified(bus, &rate.fll().registers(), &mut report).await?;
+    reg_conf_write_verified(bus, &FLL_ENABLE, &mut report).await?;
+    fll::wait_for_lock(bus).await?;
+    Ok(report)
 }
 
-async fn reg_conf_write_spi(
-    bus: &mut impl Bus<spim::Error>,
+/// sends a list of serialized register writes to the device
+/// e.g. one of the arrays in the `config` module or the output of `routing::RoutingGraph::registers`
+/// consecutive registers are coalesced into block writes (the register address auto increments)
+pub async fn reg_conf_write<E>(
+    bus: &mut impl Bus<E>,
     config: &[[u32; 2]],
-) -> Result<(), spim::Error> {
-    for [reg, value] in config {
-        bus.write(*reg, *value).await?;
+) -> Result<(), DspError<E>> {
+    let mut remaining = config;
+    while !remaining.is_empty() {
+        let (run, rest) = remaining.split_at(contiguous_run(remaining, MAX_BLOCK_REGS));
+
+        if let [[reg, value]] = run {
+            bus.write(*reg, *value).await.map_err(DspError::Bus)?;
+        } else {
+            let mut bytes = [0; MAX_BLOCK_REGS * 4];
+            for (chunk, [_, value]) in bytes.chunks_exact_mut(4).zip(run) {
+                chunk.copy_from_slice(&value.to_be_bytes());
+            }
+
+            bus.write_block(run[0][0], &bytes[..run.len() * 4])
+                .await
+                .map_err(DspError::Bus)?;
+        }
+
+        remaining = rest;
     }
 
     Ok(())
 }
 
-async fn reg_conf_write(shared_bus: &SharedBus, config: &[[u32; 2]]) -> Result<(), spim::Error> {
-    let mut bus = shared_bus.borrow().await;
+// registers are 32 bits wide so consecutive register addresses are 4 apart
+const REG_STRIDE: u32 = 4;
+
+// maximum number of registers sent in a single block write
+const MAX_BLOCK_REGS: usize = 16;
 
-    for [reg, value] in config {
-        bus.write(*reg, *value).await?;
+/// the number of registers at the start of the config with consecutive addresses (at least 1 and at most max)
+pub fn contiguous_run(config: &[[u32; 2]], max: usize) -> usize {
+    let mut len = 1;
+    while len < config.len() && len < max && config[len][0] == config[len - 1][0] + REG_STRIDE {
+        len += 1;
     }
 
-    Ok(())
+    len.min(config.len())
 }
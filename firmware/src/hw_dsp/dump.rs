@@ -0,0 +1,277 @@
+use super::config::{
+    ASP1_ENABLE, CLOCK_CONFIGURATION, COMPRESSION_ENABLE_CONFIGURE, EQUALIZER_ENABLE_CONFIGURE,
+    FLL_DISABLE, FLL_ENABLE, GPIO_CONFIGURATION, OUTPUT_ENABLE_BASIC, OUTPUT_ENABLE_COMPRESSION,
+    OUTPUT_ENABLE_EQ, OUTPUT_ENABLE_PASSTHOUGH, PDM_MIC_CONFIGURE_PASSTHOUGH,
+    PDM_MIC_ENABLE_CONFIGURE,
+};
+use super::dsp::default_routing;
+use super::error::DspError;
+use super::verify::{check, VerifyReport};
+use crate::sample_rate::SampleRateConfig;
+use cs47l63::{
+    hw_interface::Bus,
+    spec::{
+        CS47L63_DEVID, CS47L63_IRQ1_EINT_1, CS47L63_IRQ1_EINT_2, CS47L63_IRQ1_STATUS, CS47L63_REVID,
+    },
+};
+use defmt::{info, warn};
+
+// Register dumps for debugging a misbehaving board.
+// `dump` reads every register touched by the presets in the `config` module, the default routing and the FLL settings plus the core status registers.
+// `diff` compares the device against a single preset and reports the registers that differ.
+// Every line is logged with a fixed prefix so that it can be grepped out of the defmt log:
+//   DUMP <reg> <val>
+//   DIFF <preset> <reg> <expected> <actual>
+// When using a `SharedBus` pass `bus.uncached()` so that the registers are read from the device rather than the cache.
+
+/// A named configuration, e.g. from the `config` module
+#[derive(Debug, Clone, Copy)]
+pub struct Preset<'a> {
+    pub name: &'static str,
+    pub registers: &'a [[u32; 2]],
+}
+
+/// All presets that leave state in the device (the soft reset is write only)
+pub const PRESETS: [Preset<'static>; 13] = [
+    Preset {
+        name: "CLOCK_CONFIGURATION",
+        registers: &CLOCK_CONFIGURATION,
+    },
+    Preset {
+        name: "GPIO_CONFIGURATION",
+        registers: &GPIO_CONFIGURATION,
+    },
+    Preset {
+        name: "ASP1_ENABLE",
+        registers: &ASP1_ENABLE,
+    },
+    Preset {
+        name: "OUTPUT_ENABLE_EQ",
+        registers: &OUTPUT_ENABLE_EQ,
+    },
+    Preset {
+        name: "OUTPUT_ENABLE_PASSTHOUGH",
+        registers: &OUTPUT_ENABLE_PASSTHOUGH,
+    },
+    Preset {
+        name: "OUTPUT_ENABLE_BASIC",
+        registers: &OUTPUT_ENABLE_BASIC,
+    },
+    Preset {
+        name: "OUTPUT_ENABLE_COMPRESSION",
+        registers: &OUTPUT_ENABLE_COMPRESSION,
+    },
+    Preset {
+        name: "FLL_DISABLE",
+        registers: &FLL_DISABLE,
+    },
+    Preset {
+        name: "FLL_ENABLE",
+        registers: &FLL_ENABLE,
+    },
+    Preset {
+        name: "PDM_MIC_ENABLE_CONFIGURE",
+        registers: &PDM_MIC_ENABLE_CONFIGURE,
+    },
+    Preset {
+        name: "PDM_MIC_CONFIGURE_PASSTHOUGH",
+        registers: &PDM_MIC_CONFIGURE_PASSTHOUGH,
+    },
+    Preset {
+        name: "EQUALIZER_ENABLE_CONFIGURE",
+        registers: &EQUALIZER_ENABLE_CONFIGURE,
+    },
+    Preset {
+        name: "COMPRESSION_ENABLE_CONFIGURE",
+        registers: &COMPRESSION_ENABLE_CONFIGURE,
+    },
+];
+
+/// Status registers included in every dump
+pub const STATUS_REGISTERS: [u32; 5] = [
+    CS47L63_DEVID,
+    CS47L63_REVID,
+    CS47L63_IRQ1_STATUS,
+    CS47L63_IRQ1_EINT_1,
+    CS47L63_IRQ1_EINT_2,
+];
+
+/// Looks up a preset by the name of its const in the `config` module
+pub fn preset(name: &str) -> Option<&'static Preset<'static>> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// The FLL1 settings for a sample rate, built from `SampleRateConfig::fll().registers()`
+/// they depend on the rate so they are not one of the `PRESETS`
+pub fn fll_preset(registers: &[[u32; 2]; 2]) -> Preset<'_> {
+    Preset {
+        name: "FLL_SETTINGS",
+        registers,
+    }
+}
+
+// presets share registers (e.g. the output mixers), each register is only dumped the first time it appears
+fn is_first_occurrence(preset_index: usize, entry_index: usize) -> bool {
+    let reg = PRESETS[preset_index].registers[entry_index][0];
+    let earlier_presets = PRESETS[..preset_index]
+        .iter()
+        .flat_map(|preset| preset.registers.iter());
+    let earlier_entries = PRESETS[preset_index].registers[..entry_index].iter();
+
+    !earlier_presets
+        .chain(earlier_entries)
+        .any(|[r, _]| *r == reg)
+}
+
+/// reads and logs the status registers, every register touched by the presets and the rate and FLL settings for the sample rate
+/// returns the number of registers dumped
+pub async fn dump<E>(bus: &mut impl Bus<E>, rate: &SampleRateConfig) -> Result<usize, DspError<E>> {
+    info!("DUMP BEGIN");
+    let mut count = 0;
+
+    for reg in STATUS_REGISTERS {
+        dump_reg(bus, reg).await?;
+        count += 1;
+    }
+
+    for (preset_index, preset) in PRESETS.iter().enumerate() {
+        for (entry_index, [reg, _]) in preset.registers.iter().enumerate() {
+            if is_first_occurrence(preset_index, entry_index) {
+                dump_reg(bus, *reg).await?;
+                count += 1;
+            }
+        }
+    }
+
+    // the default routing, the sample rates and the FLL settings are generated at runtime so they are not presets
+    let routing = default_routing()?;
+    let rates = rate.codec_registers();
+    let fll = rate.fll().registers();
+    let generated = routing
+        .as_slice()
+        .iter()
+        .chain(rates.iter())
+        .chain(fll.iter());
+    for (index, [reg, _]) in generated.clone().enumerate() {
+        let in_presets = PRESETS
+            .iter()
+            .flat_map(|preset| preset.registers.iter())
+            .any(|[r, _]| r == reg);
+        if !in_presets && !generated.clone().take(index).any(|[r, _]| r == reg) {
+            dump_reg(bus, *reg).await?;
+            count += 1;
+        }
+    }
+
+    info!("DUMP END {=usize}", count);
+    Ok(count)
+}
+
+async fn dump_reg<E>(bus: &mut impl Bus<E>, reg: u32) -> Result<(), DspError<E>> {
+    let val = bus.read(reg).await.map_err(DspError::Bus)?;
+    info!("DUMP {=u32:#010x} {=u32:#010x}", reg, val);
+    Ok(())
+}
+
+/// reads every register in the preset and compares it to the value the preset writes
+/// self clearing and status bits are ignored (see `verify::ignore_mask`)
+/// differences are logged and recorded in the report
+pub async fn diff<E, const N: usize>(
+    bus: &mut impl Bus<E>,
+    preset: &Preset<'_>,
+    report: &mut VerifyReport<N>,
+) -> Result<(), DspError<E>> {
+    for (index, [reg, expected]) in preset.registers.iter().enumerate() {
+        // a register written more than once by the preset ends up with the last value written
+        if preset.registers[index + 1..].iter().any(|[r, _]| r == reg) {
+            continue;
+        }
+
+        let actual = bus.read(*reg).await.map_err(DspError::Bus)?;
+        if let Some(mismatch) = check(*reg, *expected, actual) {
+            warn!(
+                "DIFF {=str} {=u32:#010x} {=u32:#010x} {=u32:#010x}",
+                preset.name, mismatch.reg, mismatch.expected, mismatch.actual
+            );
+            report.record(mismatch);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::sim::{SimCodec, Transaction};
+    use crate::hw_dsp::verify::Mismatch;
+    use crate::sample_rate::SampleRate;
+    use cs47l63::spec::CS47L63_GPIO1_CTRL1;
+    use embassy_futures::block_on;
+
+    // the nRF master clock used for 48kHz (32MHz / 21 with 32 clocks per frame)
+    fn rate_48k() -> SampleRateConfig {
+        SampleRateConfig::with_clock(SampleRate::_48kHz, 1_523_809, 32).unwrap()
+    }
+
+    fn reads<'a>(sim: &'a SimCodec<256, 512>) -> impl Iterator<Item = u32> + 'a {
+        sim.transactions()
+            .filter_map(|transaction| match transaction {
+                Transaction::Read { reg, .. } => Some(*reg),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn dump_reads_each_preset_register_once() {
+        let mut sim: SimCodec<256, 512> = SimCodec::new();
+        let count = block_on(dump(&mut sim, &rate_48k())).unwrap();
+
+        assert_eq!(reads(&sim).count(), count);
+        assert_eq!(sim.num_transactions(), count);
+
+        for preset in PRESETS.iter() {
+            for [reg, _] in preset.registers {
+                let times = reads(&sim).filter(|r| r == reg).count();
+                assert_eq!(times, 1, "{} {:#x}", preset.name, reg);
+            }
+        }
+
+        // status, routing, rate and FLL registers are not read twice either
+        for (index, reg) in reads(&sim).enumerate() {
+            assert!(!reads(&sim).take(index).any(|r| r == reg), "{:#x}", reg);
+        }
+        for reg in STATUS_REGISTERS {
+            assert!(reads(&sim).any(|r| r == reg), "{:#x}", reg);
+        }
+    }
+
+    #[test]
+    fn diff_only_checks_the_last_value_of_a_register_written_twice() {
+        let registers = [[CS47L63_GPIO1_CTRL1, 0x1], [CS47L63_GPIO1_CTRL1, 0x2]];
+        let preset = Preset {
+            name: "TEST",
+            registers: &registers,
+        };
+
+        let mut sim: SimCodec<256, 512> = SimCodec::new();
+        sim.set(CS47L63_GPIO1_CTRL1, 0x2).unwrap();
+        let mut report: VerifyReport = VerifyReport::new();
+        block_on(diff(&mut sim, &preset, &mut report)).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(reads(&sim).count(), 1);
+
+        // the first value is reported as a mismatch against the last one
+        sim.set(CS47L63_GPIO1_CTRL1, 0x1).unwrap();
+        let mut report: VerifyReport = VerifyReport::new();
+        block_on(diff(&mut sim, &preset, &mut report)).unwrap();
+        assert_eq!(
+            report.mismatches(),
+            &[Mismatch {
+                reg: CS47L63_GPIO1_CTRL1,
+                expected: 0x2,
+                actual: 0x1,
+            }]
+        );
+    }
+}
@@ -0,0 +1,611 @@
+use super::dsp::reg_conf_write;
+use super::error::DspError;
+use super::reg_list::RegList;
+use core::f32::consts::PI;
+use cs47l63::{hw_interface::Bus, registers::digital_core::equalizer};
+
+// Runtime configuration of the four 5 band parametric equalizers (EQ1-EQ4).
+//
+// Each band has a gain of -12dB to +12dB in 1dB steps. Band 1 is a low shelf, bands 2-4 are peaking filters and band 5 is a high shelf.
+// The shape of each band is set by a set of coefficients (A, B, C and PG) in signed 4.12 fixed point.
+// These are calculated from a center (or corner) frequency and bandwidth using the Regalia-Mitra allpass form:
+//   shelf: a = (1 - tan(w0/2)) / (1 + tan(w0/2))
+//          low shelf  A = a, B = 1/4,         PG = 4(1 - a)
+//          high shelf A = a, B = (1 + a) / 4, PG = 4
+//   peak:  k2 = (1 - tan(bw/2)) / (1 + tan(bw/2))
+//          A = (1 + k2)cos(w0), B = -k2, C = 1/4, PG = 4(1 - k2)
+// where w0 and bw are the center frequency and bandwidth in radians per sample.
+// Bands without custom coefficients keep whatever coefficients the device currently has.
+
+/// Number of bands in each equalizer
+pub const NUM_BANDS: usize = 5;
+
+/// Band gain range in dB
+pub const BAND_GAIN_MIN_DB: i8 = -12;
+pub const BAND_GAIN_MAX_DB: i8 = 12;
+
+/// Enough for the two gain registers and the coefficients of every band
+pub const MAX_REGISTERS: usize = 2 + 3 * (NUM_BANDS - 1) + 2;
+
+// 1.0 in 4.12 fixed point
+const COEFF_ONE: f32 = 4096.0;
+
+/// One of the four equalizers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EqBlock {
+    Eq1,
+    Eq2,
+    Eq3,
+    Eq4,
+}
+
+impl EqBlock {
+    pub const ALL: [EqBlock; 4] = [EqBlock::Eq1, EqBlock::Eq2, EqBlock::Eq3, EqBlock::Eq4];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Band {
+    /// low shelf
+    Band1,
+    /// peak
+    Band2,
+    /// peak
+    Band3,
+    /// peak
+    Band4,
+    /// high shelf
+    Band5,
+}
+
+impl Band {
+    pub const ALL: [Band; NUM_BANDS] = [
+        Band::Band1,
+        Band::Band2,
+        Band::Band3,
+        Band::Band4,
+        Band::Band5,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EqError {
+    /// Band gains must be between -12dB and +12dB
+    GainOutOfRange(i8),
+    /// The frequency must be above 0Hz and below half the sample rate
+    FrequencyOutOfRange,
+    /// The bandwidth must be above 0Hz and below half the sample rate
+    BandwidthOutOfRange,
+    /// The coefficients would make the filter unstable (e.g. the bandwidth is too narrow)
+    Unstable,
+}
+
+/// The coefficients of a single band in 4.12 fixed point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct BandCoefficients {
+    pub a: i16,
+    pub b: i16,
+    pub c: i16,
+    pub pg: i16,
+}
+
+fn to_fixed(val: f32) -> i16 {
+    libm::roundf(val * COEFF_ONE).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+// frequency in radians per sample
+fn to_radians(freq_hz: f32, sample_rate_hz: u32) -> Option<f32> {
+    let nyquist = sample_rate_hz as f32 / 2.0;
+    if freq_hz > 0.0 && freq_hz < nyquist {
+        Some(2.0 * PI * freq_hz / sample_rate_hz as f32)
+    } else {
+        None
+    }
+}
+
+// (1 - tan(w/2)) / (1 + tan(w/2))
+fn allpass_coeff(w: f32) -> f32 {
+    let t = libm::tanf(w / 2.0);
+    (1.0 - t) / (1.0 + t)
+}
+
+impl BandCoefficients {
+    /// low shelf (band 1) with a corner frequency
+    pub fn low_shelf(corner_hz: f32, sample_rate_hz: u32) -> Result<Self, EqError> {
+        let w0 = to_radians(corner_hz, sample_rate_hz).ok_or(EqError::FrequencyOutOfRange)?;
+        let a = allpass_coeff(w0);
+        Self {
+            a: to_fixed(a),
+            b: to_fixed(0.25),
+            c: 0,
+            pg: to_fixed(4.0 * (1.0 - a)),
+        }
+        .checked_shelf()
+    }
+
+    /// high shelf (band 5) with a corner frequency
+    pub fn high_shelf(corner_hz: f32, sample_rate_hz: u32) -> Result<Self, EqError> {
+        let w0 = to_radians(corner_hz, sample_rate_hz).ok_or(EqError::FrequencyOutOfRange)?;
+        let a = allpass_coeff(w0);
+        Self {
+            a: to_fixed(a),
+            b: to_fixed((1.0 + a) / 4.0),
+            c: 0,
+            pg: to_fixed(4.0),
+        }
+        .checked_shelf()
+    }
+
+    /// peaking filter (bands 2-4) with a center frequency and bandwidth (the bandwidth is the center frequency / Q)
+    pub fn peak(center_hz: f32, bandwidth_hz: f32, sample_rate_hz: u32) -> Result<Self, EqError> {
+        let w0 = to_radians(center_hz, sample_rate_hz).ok_or(EqError::FrequencyOutOfRange)?;
+        let bw = to_radians(bandwidth_hz, sample_rate_hz).ok_or(EqError::BandwidthOutOfRange)?;
+        let k2 = allpass_coeff(bw);
+        Self {
+            a: to_fixed((1.0 + k2) * libm::cosf(w0)),
+            b: to_fixed(-k2),
+            c: to_fixed(0.25),
+            pg: to_fixed(4.0 * (1.0 - k2)),
+        }
+        .checked_peak()
+    }
+
+    /// the coefficients for the shape of the given band (the bandwidth is ignored for the shelving bands 1 and 5)
+    pub fn for_band(
+        band: Band,
+        center_hz: f32,
+        bandwidth_hz: f32,
+        sample_rate_hz: u32,
+    ) -> Result<Self, EqError> {
+        match band {
+            Band::Band1 => Self::low_shelf(center_hz, sample_rate_hz),
+            Band::Band2 | Band::Band3 | Band::Band4 => {
+                Self::peak(center_hz, bandwidth_hz, sample_rate_hz)
+            }
+            Band::Band5 => Self::high_shelf(center_hz, sample_rate_hz),
+        }
+    }
+
+    // the first order allpass is stable when |a| < 1
+    fn checked_shelf(self) -> Result<Self, EqError> {
+        if (self.a as i32).abs() < COEFF_ONE as i32 {
+            Ok(self)
+        } else {
+            Err(EqError::Unstable)
+        }
+    }
+
+    // the second order allpass is stable when |B| < 1 and |A / (1 - B)| < 1
+    fn checked_peak(self) -> Result<Self, EqError> {
+        let one = COEFF_ONE as i32;
+        let (a, b) = (self.a as i32, self.b as i32);
+        if b.abs() < one && a.abs() < one - b {
+            Ok(self)
+        } else {
+            Err(EqError::Unstable)
+        }
+    }
+}
+
+/// Settings of a single band
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct EqBand {
+    pub gain_db: i8,
+    /// a disabled band is set to 0dB so that it has no effect
+    pub enabled: bool,
+    /// None keeps the coefficients currently in the device
+    pub coefficients: Option<BandCoefficients>,
+}
+
+/// Settings of all the bands of an equalizer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EqConfig {
+    bands: [EqBand; NUM_BANDS],
+}
+
+impl EqConfig {
+    /// all bands enabled at 0dB with the coefficients currently in the device
+    pub const fn new() -> Self {
+        Self {
+            bands: [EqBand {
+                gain_db: 0,
+                enabled: true,
+                coefficients: None,
+            }; NUM_BANDS],
+        }
+    }
+
+    pub fn band(&self, band: Band) -> &EqBand {
+        &self.bands[band.index()]
+    }
+
+    pub fn gain(&mut self, band: Band, gain_db: i8) -> Result<&mut Self, EqError> {
+        if !(BAND_GAIN_MIN_DB..=BAND_GAIN_MAX_DB).contains(&gain_db) {
+            return Err(EqError::GainOutOfRange(gain_db));
+        }
+
+        self.bands[band.index()].gain_db = gain_db;
+        Ok(self)
+    }
+
+    pub fn enable(&mut self, band: Band, enabled: bool) -> &mut Self {
+        self.bands[band.index()].enabled = enabled;
+        self
+    }
+
+    /// sets custom coefficients for the band (see `BandCoefficients::for_band`)
+    pub fn frequency(
+        &mut self,
+        band: Band,
+        center_hz: f32,
+        bandwidth_hz: f32,
+        sample_rate_hz: u32,
+    ) -> Result<&mut Self, EqError> {
+        let coefficients =
+            BandCoefficients::for_band(band, center_hz, bandwidth_hz, sample_rate_hz)?;
+        self.bands[band.index()].coefficients = Some(coefficients);
+        Ok(self)
+    }
+
+    // disabled bands are flat
+    fn band_gain(&self, band: Band) -> equalizer::EqBandGain {
+        let band = self.band(band);
+        band_gain(if band.enabled { band.gain_db } else { 0 })
+    }
+
+    /// the gain and coefficient registers for the equalizer (this does not enable it, see `apply`)
+    pub fn registers(&self, eq: EqBlock) -> RegList<MAX_REGISTERS> {
+        let mut regs = RegList::new();
+
+        // MAX_REGISTERS has room for every band so this cannot fail
+        let mut push = |reg_val| {
+            let _ = regs.push(reg_val);
+        };
+        let (gain1_reg, gain2_reg) = gain_regs(eq);
+
+        push(
+            equalizer::EqGain1 {
+                reg: gain1_reg,
+                band1_gain: self.band_gain(Band::Band1),
+                band2_gain: self.band_gain(Band::Band2),
+                band3_gain: self.band_gain(Band::Band3),
+                band4_gain: self.band_gain(Band::Band4),
+            }
+            .serialize(),
+        );
+        push(
+            equalizer::EqGain2 {
+                reg: gain2_reg,
+                band5_gain: self.band_gain(Band::Band5),
+            }
+            .serialize(),
+        );
+
+        for band in Band::ALL {
+            if let Some(coeffs) = self.band(band).coefficients {
+                let (coeff1_reg, coeff2_reg, pg_reg) = coeff_regs(eq, band);
+
+                // the registers hold the two's complement bit patterns
+                push(
+                    equalizer::EqBandCoeff1 {
+                        reg: coeff1_reg,
+                        a: coeffs.a as u16,
+                        b: coeffs.b as u16,
+                    }
+                    .serialize(),
+                );
+                if let Some(coeff2_reg) = coeff2_reg {
+                    push(
+                        equalizer::EqBandCoeff2 {
+                            reg: coeff2_reg,
+                            c: coeffs.c as u16,
+                        }
+                        .serialize(),
+                    );
+                }
+                push(
+                    equalizer::EqBandPg {
+                        reg: pg_reg,
+                        pg: coeffs.pg as u16,
+                    }
+                    .serialize(),
+                );
+            }
+        }
+
+        regs
+    }
+}
+
+impl Default for EqConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn band_gain(gain_db: i8) -> equalizer::EqBandGain {
+    use equalizer::EqBandGain::*;
+
+    match gain_db {
+        i8::MIN..=-12 => Minus12db,
+        -11 => Minus11db,
+        -10 => Minus10db,
+        -9 => Minus09db,
+        -8 => Minus08db,
+        -7 => Minus07db,
+        -6 => Minus06db,
+        -5 => Minus05db,
+        -4 => Minus04db,
+        -3 => Minus03db,
+        -2 => Minus02db,
+        -1 => Minus01db,
+        0 => ZeroDb,
+        1 => Plus01db,
+        2 => Plus02db,
+        3 => Plus03db,
+        4 => Plus04db,
+        5 => Plus05db,
+        6 => Plus06db,
+        7 => Plus07db,
+        8 => Plus08db,
+        9 => Plus09db,
+        10 => Plus10db,
+        11 => Plus11db,
+        12..=i8::MAX => Plus12db,
+    }
+}
+
+fn gain_regs(eq: EqBlock) -> (equalizer::EqGain1Reg, equalizer::EqGain2Reg) {
+    match eq {
+        EqBlock::Eq1 => (
+            equalizer::EqGain1Reg::Eq1Gain1,
+            equalizer::EqGain2Reg::Eq1Gain2,
+        ),
+        EqBlock::Eq2 => (
+            equalizer::EqGain1Reg::Eq2Gain1,
+            equalizer::EqGain2Reg::Eq2Gain2,
+        ),
+        EqBlock::Eq3 => (
+            equalizer::EqGain1Reg::Eq3Gain1,
+            equalizer::EqGain2Reg::Eq3Gain2,
+        ),
+        EqBlock::Eq4 => (
+            equalizer::EqGain1Reg::Eq4Gain1,
+            equalizer::EqGain2Reg::Eq4Gain2,
+        ),
+    }
+}
+
+// COEFF1, COEFF2 (band 5 has no C coefficient) and PG registers of a band
+fn coeff_regs(
+    eq: EqBlock,
+    band: Band,
+) -> (
+    equalizer::EqBandCoeff1Reg,
+    Option<equalizer::EqBandCoeff2Reg>,
+    equalizer::EqBandPgReg,
+) {
+    use equalizer::{EqBandCoeff1Reg as Coeff1, EqBandCoeff2Reg as Coeff2, EqBandPgReg as Pg};
+
+    let regs = match eq {
+        EqBlock::Eq1 => [
+            (
+                Coeff1::Eq1Band1Coeff1,
+                Some(Coeff2::Eq1Band1Coeff2),
+                Pg::Eq1Band1Pg,
+            ),
+            (
+                Coeff1::Eq1Band2Coeff1,
+                Some(Coeff2::Eq1Band2Coeff2),
+                Pg::Eq1Band2Pg,
+            ),
+            (
+                Coeff1::Eq1Band3Coeff1,
+                Some(Coeff2::Eq1Band3Coeff2),
+                Pg::Eq1Band3Pg,
+            ),
+            (
+                Coeff1::Eq1Band4Coeff1,
+                Some(Coeff2::Eq1Band4Coeff2),
+                Pg::Eq1Band4Pg,
+            ),
+            (Coeff1::Eq1Band5Coeff1, None, Pg::Eq1Band5Pg),
+        ],
+        EqBlock::Eq2 => [
+            (
+                Coeff1::Eq2Band1Coeff1,
+                Some(Coeff2::Eq2Band1Coeff2),
+                Pg::Eq2Band1Pg,
+            ),
+            (
+                Coeff1::Eq2Band2Coeff1,
+                Some(Coeff2::Eq2Band2Coeff2),
+                Pg::Eq2Band2Pg,
+            ),
+            (
+                Coeff1::Eq2Band3Coeff1,
+                Some(Coeff2::Eq2Band3Coeff2),
+                Pg::Eq2Band3Pg,
+            ),
+            (
+                Coeff1::Eq2Band4Coeff1,
+                Some(Coeff2::Eq2Band4Coeff2),
+                Pg::Eq2Band4Pg,
+            ),
+            (Coeff1::Eq2Band5Coeff1, None, Pg::Eq2Band5Pg),
+        ],
+        EqBlock::Eq3 => [
+            (
+                Coeff1::Eq3Band1Coeff1,
+                Some(Coeff2::Eq3Band1Coeff2),
+                Pg::Eq3Band1Pg,
+            ),
+            (
+                Coeff1::Eq3Band2Coeff1,
+                Some(Coeff2::Eq3Band2Coeff2),
+                Pg::Eq3Band2Pg,
+            ),
+            (
+                Coeff1::Eq3Band3Coeff1,
+                Some(Coeff2::Eq3Band3Coeff2),
+                Pg::Eq3Band3Pg,
+            ),
+            (
+                Coeff1::Eq3Band4Coeff1,
+                Some(Coeff2::Eq3Band4Coeff2),
+                Pg::Eq3Band4Pg,
+            ),
+            (Coeff1::Eq3Band5Coeff1, None, Pg::Eq3Band5Pg),
+        ],
+        EqBlock::Eq4 => [
+            (
+                Coeff1::Eq4Band1Coeff1,
+                Some(Coeff2::Eq4Band1Coeff2),
+                Pg::Eq4Band1Pg,
+            ),
+            (
+                Coeff1::Eq4Band2Coeff1,
+                Some(Coeff2::Eq4Band2Coeff2),
+                Pg::Eq4Band2Pg,
+            ),
+            (
+                Coeff1::Eq4Band3Coeff1,
+                Some(Coeff2::Eq4Band3Coeff2),
+                Pg::Eq4Band3Pg,
+            ),
+            (
+                Coeff1::Eq4Band4Coeff1,
+                Some(Coeff2::Eq4Band4Coeff2),
+                Pg::Eq4Band4Pg,
+            ),
+            (Coeff1::Eq4Band5Coeff1, None, Pg::Eq4Band5Pg),
+        ],
+    };
+
+    regs[band.index()]
+}
+
+// the enable bit of each equalizer in EqControl1
+fn enable_mask(eq: EqBlock) -> u32 {
+    equalizer::EqControl1 {
+        eq1_enabled: eq == EqBlock::Eq1,
+        eq2_enabled: eq == EqBlock::Eq2,
+        eq3_enabled: eq == EqBlock::Eq3,
+        eq4_enabled: eq == EqBlock::Eq4,
+    }
+    .serialize()[1]
+}
+
+/// enables or disables a single equalizer leaving the others as they are
+pub async fn enable<E>(
+    bus: &mut impl Bus<E>,
+    eq: EqBlock,
+    enabled: bool,
+) -> Result<(), DspError<E>> {
+    let [reg, _] = equalizer::EqControl1 {
+        eq1_enabled: false,
+        eq2_enabled: false,
+        eq3_enabled: false,
+        eq4_enabled: false,
+    }
+    .serialize();
+
+    let val = bus.read(reg).await.map_err(DspError::Bus)?;
+    let val = if enabled {
+        val | enable_mask(eq)
+    } else {
+        val & !enable_mask(eq)
+    };
+
+    bus.write(reg, val).await.map_err(DspError::Bus)
+}
+
+/// writes the band gains and coefficients of the equalizer and enables it
+/// the equalizer still needs to be routed (see `routing::RoutingGraph`) to have an effect
+pub async fn apply<E>(
+    bus: &mut impl Bus<E>,
+    eq: EqBlock,
+    config: &EqConfig,
+) -> Result<(), DspError<E>> {
+    reg_conf_write(bus, &config.registers(eq)).await?;
+    enable(bus, eq, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cs47l63::spec::{
+        CS47L63_EQ3_BAND2_COEFF1, CS47L63_EQ3_BAND2_COEFF2, CS47L63_EQ3_BAND2_PG,
+        CS47L63_EQ3_BAND5_COEFF1, CS47L63_EQ3_BAND5_PG,
+    };
+
+    #[test]
+    fn coefficients_are_written_to_the_band_registers_of_the_equalizer() {
+        let mut config = EqConfig::new();
+        config
+            .frequency(Band::Band2, 300.0, 600.0, 48_000)
+            .unwrap()
+            .frequency(Band::Band5, 6_900.0, 0.0, 48_000)
+            .unwrap();
+        let peak = config.band(Band::Band2).coefficients.unwrap();
+        let shelf = config.band(Band::Band5).coefficients.unwrap();
+
+        let regs = config.registers(EqBlock::Eq3);
+        let coeffs = &regs[2..];
+        assert_eq!(
+            coeffs,
+            [
+                [
+                    CS47L63_EQ3_BAND2_COEFF1,
+                    (peak.b as u16 as u32) << 16 | peak.a as u16 as u32
+                ],
+                [CS47L63_EQ3_BAND2_COEFF2, 0x0400],
+                [CS47L63_EQ3_BAND2_PG, peak.pg as u16 as u32],
+                // band 5 has no C coefficient
+                [
+                    CS47L63_EQ3_BAND5_COEFF1,
+                    (shelf.b as u16 as u32) << 16 | shelf.a as u16 as u32
+                ],
+                [CS47L63_EQ3_BAND5_PG, 0x4000],
+            ]
+        );
+    }
+
+    #[test]
+    fn peak_coefficients_are_stable_and_in_range() {
+        // B is negative so the upper half of COEFF1 holds its two's complement
+        let peak = BandCoefficients::peak(300.0, 600.0, 48_000).unwrap();
+        assert!(peak.b < 0);
+        assert_eq!(peak.c, 0x0400);
+
+        assert_eq!(
+            BandCoefficients::peak(300.0, 0.01, 48_000),
+            Err(EqError::Unstable)
+        );
+        assert_eq!(
+            BandCoefficients::peak(24_000.0, 600.0, 48_000),
+            Err(EqError::FrequencyOutOfRange)
+        );
+        assert_eq!(
+            BandCoefficients::low_shelf(0.0, 48_000),
+            Err(EqError::FrequencyOutOfRange)
+        );
+    }
+
+    #[test]
+    fn gains_are_checked() {
+        let mut config = EqConfig::new();
+        assert_eq!(
+            config.gain(Band::Band1, 13).err(),
+            Some(EqError::GainOutOfRange(13))
+        );
+        config
+            .gain(Band::Band1, -12)
+            .unwrap()
+            .enable(Band::Band2, false);
+        assert_eq!(config.registers(EqBlock::Eq1).len(), 2);
+    }
+}
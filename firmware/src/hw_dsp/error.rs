@@ -0,0 +1,33 @@
+use super::routing::RoutingError;
+use super::wdrc::WdrcError;
+
+// Errors returned by the bring-up, control and event handling functions in `hw_dsp`.
+// The variants separate the failures that the application may want to handle differently:
+// a bus error may be retried, a codec that does not respond or does not boot needs a reset
+// and a wrong device id or a verification mismatch is a fault to report.
+
+// the error of the nRF spi bus, there is no bus when running on the host
+#[cfg(feature = "nrf")]
+type DefaultBusError = embassy_nrf::spim::Error;
+#[cfg(not(feature = "nrf"))]
+type DefaultBusError = core::convert::Infallible;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DspError<E = DefaultBusError> {
+    /// A bus transaction failed (e.g. an spi error)
+    Bus(E),
+    /// The codec did not answer on the bus (the device id read back as all zeros or all ones)
+    NotResponding,
+    /// A device answered but it is not a CS47L63
+    WrongDeviceId { found: u32 },
+    /// The codec did not report boot done after a reset
+    BootFailed,
+    /// The FLL did not lock
+    FllLockFailed,
+    /// Registers did not read back the values written to them
+    VerificationMismatch { count: usize },
+    /// A routing graph is invalid or does not fit in its register list
+    Routing(RoutingError),
+    /// A WDRC description is invalid for the sample rate
+    Wdrc(WdrcError),
+}
@@ -0,0 +1,259 @@
+use super::drc::Drc;
+use super::error::DspError;
+use super::fll;
+#[cfg(feature = "nrf")]
+use super::health::HealthFault;
+use cs47l63::{
+    hw_interface::Bus,
+    spec::{
+        CS47L63_BOOT_DONE_EINT1_MASK, CS47L63_CTRLIF_ERR_MASK1_MASK, CS47L63_IRQ1_EINT_1,
+        CS47L63_IRQ1_EINT_10, CS47L63_IRQ1_EINT_11, CS47L63_IRQ1_EINT_12, CS47L63_IRQ1_EINT_13,
+        CS47L63_IRQ1_EINT_14, CS47L63_IRQ1_EINT_15, CS47L63_IRQ1_EINT_17, CS47L63_IRQ1_EINT_18,
+        CS47L63_IRQ1_EINT_2, CS47L63_IRQ1_EINT_3, CS47L63_IRQ1_EINT_5, CS47L63_IRQ1_EINT_6,
+        CS47L63_IRQ1_EINT_7, CS47L63_IRQ1_EINT_9, CS47L63_IRQ1_MASK_1,
+        CS47L63_SYSCLK_ERR_MASK1_MASK, CS47L63_SYSCLK_FAIL_MASK1_MASK,
+    },
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::{PubSubChannel, Subscriber},
+};
+
+// Typed codec interrupts delivered to any number of subscriber tasks.
+//
+// The codec latches each interrupt source in an IRQ1_EINT_n register (write 1 to clear) and asserts its IRQ pin
+// for every source that is not masked in the matching IRQ1_MASK_n register.
+// All sources are masked after a reset so enable the ones of interest with `set_enabled`.
+//
+// Call `handle_irq` from the task that waits on the IRQ pin. It reads and clears the latched sources and publishes
+// a `CodecEvent` for each one to the `CodecEvents` channel. Subscribers that fall behind miss the oldest events.
+// The health monitor publishes its results to the same channel.
+//
+// The EINT bits of a source are in the same position as its MASK bits so the crate's MASK1 masks are used where
+// there is no EINT1 mask. The crate has neither for the rest so they are defined here.
+
+const FLL1_LOCK_EINT1_MASK: u32 = 0x0000_0001;
+const FLL1_REFLOST_EINT1_MASK: u32 = 0x0000_0100;
+const MICDET1_EINT1_MASK: u32 = 0x0000_0001;
+const OUT1L_SC_EINT1_MASK: u32 = 0x0000_0001;
+const DRC1_SIG_DET_EINT1_MASK: u32 = 0x0001_0000;
+const DRC2_SIG_DET_EINT1_MASK: u32 = 0x0002_0000;
+
+// every IRQ1_EINT_n register, the IRQ1_MASK_n registers are at the same offsets
+const EINT_REGISTERS: [u32; 15] = [
+    CS47L63_IRQ1_EINT_1,
+    CS47L63_IRQ1_EINT_2,
+    CS47L63_IRQ1_EINT_3,
+    CS47L63_IRQ1_EINT_5,
+    CS47L63_IRQ1_EINT_6,
+    CS47L63_IRQ1_EINT_7,
+    CS47L63_IRQ1_EINT_9,
+    CS47L63_IRQ1_EINT_10,
+    CS47L63_IRQ1_EINT_11,
+    CS47L63_IRQ1_EINT_12,
+    CS47L63_IRQ1_EINT_13,
+    CS47L63_IRQ1_EINT_14,
+    CS47L63_IRQ1_EINT_15,
+    CS47L63_IRQ1_EINT_17,
+    CS47L63_IRQ1_EINT_18,
+];
+
+/// Number of events a subscriber can fall behind before missing events
+pub const EVENT_CAPACITY: usize = 8;
+
+/// Maximum number of tasks subscribed to codec events
+pub const MAX_SUBSCRIBERS: usize = 4;
+
+/// The channel codec events are published to, usually a static shared between tasks
+pub type CodecEvents =
+    PubSubChannel<CriticalSectionRawMutex, CodecEvent, EVENT_CAPACITY, MAX_SUBSCRIBERS, 1>;
+
+/// A task receiving codec events, see `CodecEvents::subscriber`
+pub type CodecEventSubscriber<'a> =
+    Subscriber<'a, CriticalSectionRawMutex, CodecEvent, EVENT_CAPACITY, MAX_SUBSCRIBERS, 1>;
+
+/// A codec interrupt source that can be enabled or masked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum IrqSource {
+    BootDone,
+    SysclkFail,
+    SysclkError,
+    ControlInterfaceError,
+    FllLock,
+    FllReferenceLost,
+    MicDetect,
+    OutputShortCircuit,
+    DrcSignalDetect(Drc),
+}
+
+impl IrqSource {
+    pub const ALL: [IrqSource; 10] = [
+        Self::BootDone,
+        Self::SysclkFail,
+        Self::SysclkError,
+        Self::ControlInterfaceError,
+        Self::FllLock,
+        Self::FllReferenceLost,
+        Self::MicDetect,
+        Self::OutputShortCircuit,
+        Self::DrcSignalDetect(Drc::Drc1),
+        Self::DrcSignalDetect(Drc::Drc2),
+    ];
+
+    /// codec clocking and output problems, the sources enabled by the streaming binaries
+    pub const CLOCK_AND_OUTPUT: &'static [IrqSource] = &[
+        Self::SysclkFail,
+        Self::SysclkError,
+        Self::ControlInterfaceError,
+        Self::FllLock,
+        Self::FllReferenceLost,
+        Self::OutputShortCircuit,
+    ];
+
+    // the IRQ1_EINT_n register and bit of the source
+    fn eint(&self) -> (u32, u32) {
+        match self {
+            Self::BootDone => (CS47L63_IRQ1_EINT_2, CS47L63_BOOT_DONE_EINT1_MASK),
+            Self::SysclkFail => (CS47L63_IRQ1_EINT_1, CS47L63_SYSCLK_FAIL_MASK1_MASK),
+            Self::SysclkError => (CS47L63_IRQ1_EINT_1, CS47L63_SYSCLK_ERR_MASK1_MASK),
+            Self::ControlInterfaceError => (CS47L63_IRQ1_EINT_1, CS47L63_CTRLIF_ERR_MASK1_MASK),
+            Self::FllLock => (CS47L63_IRQ1_EINT_6, FLL1_LOCK_EINT1_MASK),
+            Self::FllReferenceLost => (CS47L63_IRQ1_EINT_6, FLL1_REFLOST_EINT1_MASK),
+            Self::MicDetect => (CS47L63_IRQ1_EINT_7, MICDET1_EINT1_MASK),
+            Self::OutputShortCircuit => (CS47L63_IRQ1_EINT_9, OUT1L_SC_EINT1_MASK),
+            Self::DrcSignalDetect(Drc::Drc1) => (CS47L63_IRQ1_EINT_9, DRC1_SIG_DET_EINT1_MASK),
+            Self::DrcSignalDetect(Drc::Drc2) => (CS47L63_IRQ1_EINT_9, DRC2_SIG_DET_EINT1_MASK),
+        }
+    }
+
+    fn mask_reg(&self) -> u32 {
+        mask_reg(self.eint().0)
+    }
+
+    fn event(&self, fll_locked: bool) -> CodecEvent {
+        match self {
+            Self::BootDone => CodecEvent::BootDone,
+            Self::SysclkFail => CodecEvent::SysclkFail,
+            Self::SysclkError => CodecEvent::SysclkError,
+            Self::ControlInterfaceError => CodecEvent::ControlInterfaceError,
+            Self::FllLock if fll_locked => CodecEvent::FllLocked,
+            Self::FllLock => CodecEvent::FllUnlocked,
+            Self::FllReferenceLost => CodecEvent::FllReferenceLost,
+            Self::MicDetect => CodecEvent::MicDetect,
+            Self::OutputShortCircuit => CodecEvent::OutputShortCircuit,
+            Self::DrcSignalDetect(drc) => CodecEvent::DrcSignalDetect(*drc),
+        }
+    }
+}
+
+// the IRQ1_MASK_n register is at the same offset from IRQ1_MASK_1 as IRQ1_EINT_n is from IRQ1_EINT_1
+fn mask_reg(eint_reg: u32) -> u32 {
+    eint_reg - CS47L63_IRQ1_EINT_1 + CS47L63_IRQ1_MASK_1
+}
+
+/// Something that happened on the codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CodecEvent {
+    /// the codec finished booting after a reset
+    BootDone,
+    /// SYSCLK stopped (e.g. the FLL reference clock went away)
+    SysclkFail,
+    /// a block was enabled at a sample rate SYSCLK cannot support
+    SysclkError,
+    /// an invalid register access over spi
+    ControlInterfaceError,
+    /// FLL1 gained lock
+    FllLocked,
+    /// FLL1 lost lock
+    FllUnlocked,
+    /// the FLL1 reference clock stopped
+    FllReferenceLost,
+    /// a microphone was plugged in or removed
+    MicDetect,
+    /// the output short circuit protection was triggered (overload)
+    OutputShortCircuit,
+    /// the signal into the compressor crossed its signal detect threshold (a sign of clipping when anticlip is on)
+    DrcSignalDetect(Drc),
+    /// a health check failed (published by `health::HealthMonitor`)
+    #[cfg(feature = "nrf")]
+    HealthFault(HealthFault),
+    /// the codec was reset and reconfigured after a health check failed
+    Recovered { attempts: u8 },
+    /// the codec could not be recovered, it is checked again at the next interval
+    RecoveryFailed { attempts: u8 },
+}
+
+/// enables (unmasks) or masks an interrupt source
+/// masked sources do not assert the IRQ pin and are not reported by `handle_irq`
+pub async fn set_enabled<E>(
+    bus: &mut impl Bus<E>,
+    source: IrqSource,
+    enabled: bool,
+) -> Result<(), DspError<E>> {
+    let (_, bit) = source.eint();
+    let reg = source.mask_reg();
+    let mask = bus.read(reg).await.map_err(DspError::Bus)?;
+    let mask = if enabled { mask & !bit } else { mask | bit };
+    bus.write(reg, mask).await.map_err(DspError::Bus)
+}
+
+/// enables (unmasks) every source in the list, e.g. `IrqSource::CLOCK_AND_OUTPUT`
+pub async fn enable_all<E>(
+    bus: &mut impl Bus<E>,
+    sources: &[IrqSource],
+) -> Result<(), DspError<E>> {
+    for source in sources {
+        set_enabled(bus, *source, true).await?;
+    }
+
+    Ok(())
+}
+
+/// returns true if the interrupt source is enabled
+pub async fn is_enabled<E>(bus: &mut impl Bus<E>, source: IrqSource) -> Result<bool, DspError<E>> {
+    let (_, bit) = source.eint();
+    let mask = bus.read(source.mask_reg()).await.map_err(DspError::Bus)?;
+    Ok(mask & bit == 0)
+}
+
+/// handles the codec interrupt (call this when the IRQ pin is asserted)
+/// clears every enabled source that fired and publishes an event for it
+/// enabled sources that are not an `IrqSource` are cleared without an event so that the IRQ pin is released
+/// returns the number of events published
+pub async fn handle_irq<E>(
+    bus: &mut impl Bus<E>,
+    events: &CodecEvents,
+) -> Result<usize, DspError<E>> {
+    let publisher = events.immediate_publisher();
+    let mut count = 0;
+
+    for reg in EINT_REGISTERS {
+        let eint = bus.read(reg).await.map_err(DspError::Bus)?;
+        if eint == 0 {
+            continue;
+        }
+
+        let mask = bus.read(mask_reg(reg)).await.map_err(DspError::Bus)?;
+        let fired = eint & !mask;
+        if fired == 0 {
+            continue;
+        }
+
+        // write 1 to clear
+        bus.write(reg, fired).await.map_err(DspError::Bus)?;
+
+        for source in IrqSource::ALL {
+            let (source_reg, bit) = source.eint();
+            if source_reg != reg || fired & bit == 0 {
+                continue;
+            }
+
+            // the lock interrupt fires on both edges so the status tells which one it was
+            let fll_locked = source == IrqSource::FllLock && fll::is_locked(bus).await?;
+            publisher.publish_immediate(source.event(fll_locked));
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
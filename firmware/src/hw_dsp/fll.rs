@@ -0,0 +1,327 @@
+use super::config::{FLL_DISABLE, FLL_ENABLE};
+use super::dsp::reg_conf_write;
+use super::error::DspError;
+use cs47l63::{
+    hw_interface::Bus,
+    registers::clocking_sample_rates::fll,
+    spec::{CS47L63_FLL1_CONTROL1, CS47L63_IRQ1_STS_6},
+};
+
+// FLL1 generates SYSCLK from a reference clock:
+//
+// Fout = Fref / divider * (N + theta / lambda)
+//
+// The reference is divided down to at most 13.5MHz and the output has to be in the 45MHz to 50MHz range used by SYSCLK.
+// When the output is not an integer multiple of the divided reference the fractional part is approximated by theta / lambda
+// (both 16 bit) which means the actual output can be very slightly off the target, use `FllSettings::output_hz` to check.
+//
+// `hold` lets the FLL free-run at its current output so that SYSCLK keeps going while the reference clock is stopped.
+
+/// Lowest FLL output frequency usable as SYSCLK
+pub const MIN_OUTPUT_HZ: u32 = 45_000_000;
+
+/// Highest FLL output frequency usable as SYSCLK
+pub const MAX_OUTPUT_HZ: u32 = 50_000_000;
+
+const MAX_DIVIDED_REF_HZ: u32 = 13_500_000;
+const MIN_DIVIDED_REF_HZ: u32 = 32_000;
+const MAX_N: u32 = 0x3FF;
+const DIVIDERS: [u8; 4] = [1, 2, 4, 8];
+const LOCK_DETECT_THRESHOLD: u32 = 8;
+const LOCK_POLL_ATTEMPTS: usize = 20;
+
+/// FLL1_LOCK_STS1 in IRQ1_STS_6 (the cs47l63 crate does not export the status bits)
+pub const FLL1_LOCK_STS1_MASK: u32 = 0x0000_0001;
+
+// FLL1_HOLD in FLL1_CONTROL1
+const FLL1_HOLD_MASK: u32 = 0x0000_0002;
+
+/// The clock the FLL locks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FllSource {
+    /// the MCLK1 pin (driven by the nRF I2S master clock)
+    Mclk1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FllError {
+    /// The target output is outside of MIN_OUTPUT_HZ to MAX_OUTPUT_HZ
+    OutputOutOfRange,
+    /// The reference clock is too fast or too slow for any of the dividers
+    ReferenceOutOfRange,
+    /// The output needs an integer multiplier (N) outside of 1 to 1023
+    MultiplierOutOfRange,
+}
+
+/// FLL1 settings for a particular reference clock and output frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct FllSettings {
+    pub source: FllSource,
+    pub ref_hz: u32,
+    /// reference clock divider (1, 2, 4 or 8)
+    pub divider: u8,
+    /// integer part of the multiplier
+    pub n: u16,
+    /// fractional part of the multiplier is theta / lambda
+    pub theta: u16,
+    pub lambda: u16,
+}
+
+impl FllSettings {
+    /// derives the FLL settings that multiply a reference clock up to the output frequency
+    pub fn calculate(source: FllSource, ref_hz: u32, output_hz: u32) -> Result<Self, FllError> {
+        if !(MIN_OUTPUT_HZ..=MAX_OUTPUT_HZ).contains(&output_hz) {
+            return Err(FllError::OutputOutOfRange);
+        }
+
+        // the smallest divider that brings the reference into range
+        let divider = DIVIDERS
+            .into_iter()
+            .find(|divider| ref_hz / *divider as u32 <= MAX_DIVIDED_REF_HZ)
+            .ok_or(FllError::ReferenceOutOfRange)?;
+        if ref_hz / (divider as u32) < MIN_DIVIDED_REF_HZ {
+            return Err(FllError::ReferenceOutOfRange);
+        }
+
+        // output * divider / ref = n + remainder / ref
+        let scaled = output_hz as u64 * divider as u64;
+        let mut n = (scaled / ref_hz as u64) as u32;
+        let (mut theta, mut lambda) = fraction(scaled % ref_hz as u64, ref_hz as u64);
+
+        // the fraction was rounded up to a whole number
+        if theta == lambda {
+            n += 1;
+            (theta, lambda) = (0, 1);
+        }
+
+        if !(1..=MAX_N).contains(&n) {
+            return Err(FllError::MultiplierOutOfRange);
+        }
+
+        Ok(Self {
+            source,
+            ref_hz,
+            divider,
+            n: n as u16,
+            theta,
+            lambda,
+        })
+    }
+
+    /// the frequency the FLL actually generates with these settings
+    pub fn output_hz(&self) -> f32 {
+        let multiplier = self.n as f64 + self.theta as f64 / self.lambda as f64;
+        (self.ref_hz as f64 / self.divider as f64 * multiplier) as f32
+    }
+
+    /// the FLL1 control registers (write these while the FLL is disabled)
+    pub fn registers(&self) -> [[u32; 2]; 2] {
+        [
+            fll::Fll1Control2 {
+                lock_detect_threshold: LOCK_DETECT_THRESHOLD,
+                lock_detect: true,
+                phase_detect: false,
+                ref_detect: true,
+                divider: reference_divider(self.divider),
+                source: match self.source {
+                    FllSource::Mclk1 => fll::ReferenceClockSource::MCLK1,
+                },
+                multiplier: self.n as u32,
+            }
+            .serialize(),
+            fll::Fll1Control3 {
+                lambda: self.lambda,
+                theta: self.theta,
+            }
+            .serialize(),
+        ]
+    }
+}
+
+fn reference_divider(divider: u8) -> fll::ReferenceClockDivider {
+    match divider {
+        1 => fll::ReferenceClockDivider::_1,
+        2 => fll::ReferenceClockDivider::_2,
+        4 => fll::ReferenceClockDivider::_4,
+        _ => fll::ReferenceClockDivider::_8,
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+
+    a
+}
+
+// the closest fraction to num / den (num < den) with a denominator that fits in 16 bits
+fn fraction(num: u64, den: u64) -> (u16, u16) {
+    let divisor = gcd(num, den);
+    let (num, den) = (num / divisor, den / divisor);
+    if den <= u16::MAX as u64 {
+        return (num as u16, den as u16);
+    }
+
+    // walk the convergents of the continued fraction until the denominator gets too big
+    let (mut h0, mut h1, mut k0, mut k1) = (0, 1, 1, 0);
+    let (mut n, mut d) = (num, den);
+    while d != 0 {
+        let a = n / d;
+        let (h2, k2) = (a * h1 + h0, a * k1 + k0);
+        if k2 > u16::MAX as u64 {
+            break;
+        }
+
+        (h0, h1, k0, k1) = (h1, h2, k1, k2);
+        (n, d) = (d, n % d);
+    }
+
+    (h1 as u16, k1 as u16)
+}
+
+/// returns true if FLL1 reports that it is locked to its reference
+pub async fn is_locked<E>(bus: &mut impl Bus<E>) -> Result<bool, DspError<E>> {
+    let status = bus.read(CS47L63_IRQ1_STS_6).await.map_err(DspError::Bus)?;
+    Ok(status & FLL1_LOCK_STS1_MASK != 0)
+}
+
+/// returns true while FLL1 is held by `hold`, its lock status means nothing then
+pub async fn is_held<E>(bus: &mut impl Bus<E>) -> Result<bool, DspError<E>> {
+    let control1 = bus
+        .read(CS47L63_FLL1_CONTROL1)
+        .await
+        .map_err(DspError::Bus)?;
+    Ok(control1 & FLL1_HOLD_MASK != 0)
+}
+
+/// polls the FLL1 lock status for up to 20ms
+pub async fn wait_for_lock<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+    for _ in 0..LOCK_POLL_ATTEMPTS {
+        if is_locked(bus).await? {
+            return Ok(());
+        }
+
+        bus.delay_ms(1).await;
+    }
+
+    Err(DspError::FllLockFailed)
+}
+
+/// (re)starts FLL1 with the settings given and waits for it to lock
+/// the reference clock has to be running, e.g. the I2S stream has to be started when locking to the nRF master clock
+pub async fn start<E>(bus: &mut impl Bus<E>, settings: &FllSettings) -> Result<(), DspError<E>> {
+    reg_conf_write(bus, &FLL_DISABLE).await?;
+    reg_conf_write(bus, &settings.registers()).await?;
+    reg_conf_write(bus, &FLL_ENABLE).await?;
+    wait_for_lock(bus).await
+}
+
+/// holds FLL1 at its current output (free-running) or lets it track its reference again
+/// hold it before stopping the reference clock (e.g. the nRF I2S) to keep SYSCLK running without it and release it once
+/// the reference is back. The output drifts slowly while held and the FLL may report that it lost lock.
+pub async fn hold<E>(bus: &mut impl Bus<E>, held: bool) -> Result<(), DspError<E>> {
+    let control1 = fll::Fll1Control1 {
+        control_update: false,
+        hold: held,
+        enabled: true,
+    };
+    reg_conf_write(bus, &[control1.serialize()]).await?;
+    if held {
+        Ok(())
+    } else {
+        wait_for_lock(bus).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the nRF master clocks (32MHz / 15 and 32MHz / 21)
+    const MCK_32M_DIV15_HZ: u32 = 2_133_333;
+    const MCK_32M_DIV21_HZ: u32 = 1_523_809;
+
+    // SYSCLK follows the nRF frame clock, e.g. 4096 times 2.133333MHz / 192 for 11.025kHz
+    fn check_settings(ref_hz: u32, output_hz: u32) -> FllSettings {
+        let settings = FllSettings::calculate(FllSource::Mclk1, ref_hz, output_hz).unwrap();
+        assert!(ref_hz / settings.divider as u32 <= MAX_DIVIDED_REF_HZ);
+        assert!((1..=MAX_N).contains(&(settings.n as u32)));
+        assert!(settings.lambda > 0 && settings.theta < settings.lambda);
+
+        // Fout = Fref / divider * (N + theta / lambda)
+        let multiplier = settings.n as f64 + settings.theta as f64 / settings.lambda as f64;
+        let actual_hz = ref_hz as f64 / settings.divider as f64 * multiplier;
+        assert!((MIN_OUTPUT_HZ as f64..=MAX_OUTPUT_HZ as f64).contains(&actual_hz));
+        assert!(
+            (actual_hz - output_hz as f64).abs() < 1.0,
+            "{actual_hz} instead of {output_hz}"
+        );
+        assert_eq!(settings.output_hz(), actual_hz as f32);
+        settings
+    }
+
+    #[test]
+    fn master_clocks_of_the_binaries_are_multiplied_up_to_sysclk() {
+        // 11.025kHz (the rate the binaries use)
+        let settings = check_settings(MCK_32M_DIV15_HZ, MCK_32M_DIV15_HZ / 192 * 4096);
+        assert_eq!(settings.divider, 1);
+        assert_eq!(settings.n, 21);
+
+        // 16kHz, 24kHz and 48kHz
+        check_settings(MCK_32M_DIV21_HZ, MCK_32M_DIV21_HZ / 96 * 3072);
+        check_settings(MCK_32M_DIV21_HZ, MCK_32M_DIV21_HZ / 64 * 2048);
+        check_settings(MCK_32M_DIV21_HZ, MCK_32M_DIV21_HZ / 32 * 1024);
+    }
+
+    #[test]
+    fn integer_multiples_have_no_fraction() {
+        let settings = check_settings(12_288_000, 49_152_000);
+        assert_eq!((settings.divider, settings.n), (1, 4));
+        assert_eq!((settings.theta, settings.lambda), (0, 1));
+
+        // divided down to 12.288MHz
+        let settings = check_settings(24_576_000, 49_152_000);
+        assert_eq!((settings.divider, settings.n), (2, 4));
+    }
+
+    #[test]
+    fn fractions_fit_in_16_bits() {
+        assert_eq!(fraction(2, 6), (1, 3));
+        assert_eq!(fraction(0, MCK_32M_DIV21_HZ as u64), (0, 1));
+
+        // 0.14159265 needs a denominator of 10^8 without approximation
+        let (theta, lambda) = fraction(14_159_265, 100_000_000);
+        let error = theta as f64 / lambda as f64 - 0.14159265;
+        assert!(error.abs() < 1e-9, "{theta} / {lambda}");
+    }
+
+    #[test]
+    fn out_of_range_settings_are_rejected() {
+        let calculate =
+            |ref_hz, output_hz| FllSettings::calculate(FllSource::Mclk1, ref_hz, output_hz);
+
+        assert_eq!(
+            calculate(MCK_32M_DIV21_HZ, MIN_OUTPUT_HZ - 1),
+            Err(FllError::OutputOutOfRange)
+        );
+        assert_eq!(
+            calculate(MCK_32M_DIV21_HZ, MAX_OUTPUT_HZ + 1),
+            Err(FllError::OutputOutOfRange)
+        );
+        assert_eq!(
+            calculate(MIN_DIVIDED_REF_HZ - 1, MIN_OUTPUT_HZ),
+            Err(FllError::ReferenceOutOfRange)
+        );
+        assert_eq!(
+            calculate(MAX_DIVIDED_REF_HZ * 8 + 8, MIN_OUTPUT_HZ),
+            Err(FllError::ReferenceOutOfRange)
+        );
+
+        // a reference this slow needs a multiplier above 1023
+        assert_eq!(
+            calculate(MIN_DIVIDED_REF_HZ, MIN_OUTPUT_HZ),
+            Err(FllError::MultiplierOutOfRange)
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use super::error::DspError;
+use cs47l63::{hw_interface::Bus, registers::gpio::gpio_ctrl, spec::CS47L63_GPIO_STATUS1};
+
+// Runtime control of the codec GPIOs, e.g. for load switches, LEDs or buttons connected to the codec.
+//
+// Each GPIO is configured by its GPIOn_CTRL1 register (function, direction, pull, drive strength, debounce and output level).
+// `GPIO_CONFIGURATION` and `ASP1_ENABLE` set them up at startup, the functions here change them at any time after that.
+// GPIO1 to GPIO4 are the ASP1 pins so configuring one of them here disconnects it from the audio serial port.
+// The level of a pin is read from the GPIO status register which is never written so it is always read from the device.
+
+// supported debounce times in microseconds, in register order
+const DEBOUNCE_TIMES_US: [u32; 11] = [
+    100, 1500, 3000, 6000, 12000, 24000, 48000, 96000, 192000, 384000, 768000,
+];
+
+/// One of the twelve codec GPIOs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CodecGpio {
+    Gpio1,
+    Gpio2,
+    Gpio3,
+    Gpio4,
+    Gpio5,
+    Gpio6,
+    Gpio7,
+    Gpio8,
+    Gpio9,
+    Gpio10,
+    Gpio11,
+    Gpio12,
+}
+
+impl CodecGpio {
+    /// the GPIO number (1 to 12)
+    pub const fn number(&self) -> u8 {
+        *self as u8 + 1
+    }
+
+    // the register selector with the pin used as a plain input or output
+    const fn num(&self) -> gpio_ctrl::Num {
+        use gpio_ctrl::Num;
+        const GPIO: gpio_ctrl::PinFunction =
+            gpio_ctrl::PinFunction::ButtonDetectInputOrLogicLevelOutput;
+        const EXTENDED_GPIO: gpio_ctrl::ExtendedPinFunction =
+            gpio_ctrl::ExtendedPinFunction::ButtonDetectInputOrLogicLevelOutput;
+
+        match self {
+            Self::Gpio1 => Num::_1(GPIO),
+            Self::Gpio2 => Num::_2(GPIO),
+            Self::Gpio3 => Num::_3(GPIO),
+            Self::Gpio4 => Num::_4(GPIO),
+            Self::Gpio5 => Num::_5(EXTENDED_GPIO),
+            Self::Gpio6 => Num::_6(EXTENDED_GPIO),
+            Self::Gpio7 => Num::_7(EXTENDED_GPIO),
+            Self::Gpio8 => Num::_8(EXTENDED_GPIO),
+            Self::Gpio9 => Num::_9(EXTENDED_GPIO),
+            Self::Gpio10 => Num::_10(EXTENDED_GPIO),
+            Self::Gpio11 => Num::_11(EXTENDED_GPIO),
+            Self::Gpio12 => Num::_12(EXTENDED_GPIO),
+        }
+    }
+
+    // the GPIO status register has the level of GPIOn in bit n - 1
+    const fn status_bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+    /// both pull up and pull down enabled, the pin holds its last driven level
+    BusKeeper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DriveStrength {
+    _4mA,
+    _8mA,
+}
+
+/// The settings of a GPIO
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct GpioConfig {
+    pub direction: Direction,
+    pub pull: Pull,
+    pub drive_strength: DriveStrength,
+    /// input debounce time in microseconds (100us to 768ms, rounded to the nearest supported time), None to disable
+    pub debounce_us: Option<u32>,
+    /// the level driven when the pin is an output
+    pub output_level: bool,
+}
+
+impl GpioConfig {
+    /// a push-pull output driving the level given
+    pub const fn output(level: bool) -> Self {
+        Self {
+            direction: Direction::Output,
+            pull: Pull::None,
+            drive_strength: DriveStrength::_8mA,
+            debounce_us: None,
+            output_level: level,
+        }
+    }
+
+    /// an input with the pull given and no debounce
+    pub const fn input(pull: Pull) -> Self {
+        Self {
+            direction: Direction::Input,
+            pull,
+            drive_strength: DriveStrength::_8mA,
+            debounce_us: None,
+            output_level: false,
+        }
+    }
+
+    /// the GPIOn_CTRL1 register value for the pin (e.g. to record it in a `health::ActiveConfig`)
+    pub fn register(&self, gpio: CodecGpio) -> [u32; 2] {
+        let (pull_up_en, pull_down_en) = match self.pull {
+            Pull::None => (false, false),
+            Pull::Up => (true, false),
+            Pull::Down => (false, true),
+            Pull::BusKeeper => (true, true),
+        };
+
+        gpio_ctrl::GpioCtrl1 {
+            num: gpio.num(),
+            direction: match self.direction {
+                Direction::Input => gpio_ctrl::Direction::Input,
+                Direction::Output => gpio_ctrl::Direction::Output,
+            },
+            pull_up_en,
+            pull_down_en,
+            drive_strength: match self.drive_strength {
+                DriveStrength::_4mA => gpio_ctrl::DriveStrength::_4mA,
+                DriveStrength::_8mA => gpio_ctrl::DriveStrength::_8mA,
+            },
+            debounce_time: debounce_time(self.debounce_us.unwrap_or(0)),
+            output_level: self.output_level,
+            output_config: gpio_ctrl::OutputConfig::Cmos,
+            debounce_en: self.debounce_us.is_some(),
+            output_polarity: gpio_ctrl::OutputPolarity::NoninvertedActiveHigh,
+        }
+        .serialize()
+    }
+}
+
+// the nearest supported debounce time
+fn debounce_time(us: u32) -> gpio_ctrl::DebounceTime {
+    let index = DEBOUNCE_TIMES_US
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, time)| time.abs_diff(us))
+        .map(|(index, _)| index)
+        .unwrap_or_default();
+
+    match index {
+        0 => gpio_ctrl::DebounceTime::_100us,
+        1 => gpio_ctrl::DebounceTime::_1500us,
+        2 => gpio_ctrl::DebounceTime::_3ms,
+        3 => gpio_ctrl::DebounceTime::_6ms,
+        4 => gpio_ctrl::DebounceTime::_12ms,
+        5 => gpio_ctrl::DebounceTime::_24ms,
+        6 => gpio_ctrl::DebounceTime::_48ms,
+        7 => gpio_ctrl::DebounceTime::_96ms,
+        8 => gpio_ctrl::DebounceTime::_192ms,
+        9 => gpio_ctrl::DebounceTime::_384ms,
+        _ => gpio_ctrl::DebounceTime::_768ms,
+    }
+}
+
+// the output level field is found by serializing with only that field set
+fn output_level_mask(gpio: CodecGpio) -> u32 {
+    let high = GpioConfig {
+        output_level: true,
+        ..GpioConfig::input(Pull::None)
+    };
+    high.register(gpio)[1] & !GpioConfig::input(Pull::None).register(gpio)[1]
+}
+
+/// reconfigures a GPIO
+pub async fn configure<E>(
+    bus: &mut impl Bus<E>,
+    gpio: CodecGpio,
+    config: &GpioConfig,
+) -> Result<(), DspError<E>> {
+    let [reg, val] = config.register(gpio);
+    bus.write(reg, val).await.map_err(DspError::Bus)
+}
+
+/// sets the level driven by a GPIO configured as an output without changing its other settings
+pub async fn set_level<E>(
+    bus: &mut impl Bus<E>,
+    gpio: CodecGpio,
+    level: bool,
+) -> Result<(), DspError<E>> {
+    let mask = output_level_mask(gpio);
+    let [reg, _] = GpioConfig::output(level).register(gpio);
+    let val = bus.read(reg).await.map_err(DspError::Bus)?;
+    let val = if level { val | mask } else { val & !mask };
+    bus.write(reg, val).await.map_err(DspError::Bus)
+}
+
+/// reads the level of a GPIO (the input level for inputs and the driven level for outputs)
+pub async fn get_level<E>(bus: &mut impl Bus<E>, gpio: CodecGpio) -> Result<bool, DspError<E>> {
+    let status = bus
+        .read(CS47L63_GPIO_STATUS1)
+        .await
+        .map_err(DspError::Bus)?;
+    Ok(status & gpio.status_bit() != 0)
+}
@@ -0,0 +1,212 @@
+use super::reader::Reader;
+use super::{MemoryType, ParseError};
+
+// Parser for `.bin` coefficient files.
+//
+// The file starts with a header (magic, header length, format version and core version) followed by blocks.
+// Each block has a 20 byte header (offset, type, algorithm id, algorithm version, sample rate and data length)
+// and its data is padded to a multiple of 4 bytes.
+// Memory blocks hold data to write at a byte offset from the register of the start of an algorithm in a DSP memory
+// (the algorithm base addresses are read from the device once the firmware is running),
+// absolute blocks data to write at a register address.
+
+pub const MAGIC: &[u8; 4] = b"WMDR";
+
+const MIN_HEADER_LEN: usize = 16;
+const BLOCK_HEADER_LEN: usize = 20;
+
+const ABSOLUTE: u16 = 0xf000;
+const METADATA: u16 = 0xfc00;
+const NAME_TEXT: u16 = 0xfe00;
+const INFO_TEXT: u16 = 0xff00;
+
+/// What a block holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BlockKind {
+    /// data to write at a byte offset from the register of the start of the algorithm in a DSP memory
+    Memory(MemoryType),
+    /// data to write at a register address
+    Absolute,
+    /// a name, description or other text that is not written to the device
+    Text,
+    Unknown(u16),
+}
+
+/// A block of a coefficient file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Block<'a> {
+    pub kind: BlockKind,
+    /// byte offset from the register of the algorithm base (memory blocks) or register address (absolute blocks)
+    pub offset: u16,
+    /// the algorithm the coefficients are for (memory blocks only)
+    pub algorithm_id: u32,
+    pub algorithm_version: u32,
+    pub sample_rate: u32,
+    pub data: &'a [u8],
+}
+
+/// A parsed `.bin` file borrowing the bytes it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct CoeffFile<'a> {
+    pub version: u32,
+    pub core_version: u32,
+    blocks: &'a [u8],
+}
+
+impl<'a> CoeffFile<'a> {
+    /// checks the header, blocks are parsed as they are iterated over
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(ParseError::BadMagic);
+        }
+
+        let len = reader.le32()? as usize;
+        let version = reader.le32()?;
+        let core_version = reader.le32()?;
+
+        let blocks = bytes
+            .get(len.max(MIN_HEADER_LEN)..)
+            .ok_or(ParseError::Truncated)?;
+
+        Ok(Self {
+            version,
+            core_version,
+            blocks,
+        })
+    }
+
+    /// every block in file order
+    pub fn blocks(&self) -> Blocks<'a> {
+        Blocks {
+            reader: Reader::new(self.blocks),
+        }
+    }
+}
+
+/// Iterates over the blocks of a coefficient file, stops after the first error
+pub struct Blocks<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Result<Block<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_empty() {
+            return None;
+        }
+
+        let block = parse_block(&mut self.reader);
+        if block.is_err() {
+            // skip the rest of the file
+            self.reader = Reader::new(&[]);
+        }
+
+        Some(block)
+    }
+}
+
+fn parse_block<'a>(reader: &mut Reader<'a>) -> Result<Block<'a>, ParseError> {
+    let offset = reader.le16()?;
+    let block_type = reader.le16()?;
+    let algorithm_id = reader.le32()?;
+    let algorithm_version = reader.le32()?;
+    let sample_rate = reader.le32()?;
+    let len = reader.le32()? as usize;
+    let data = reader.bytes(len)?;
+
+    // the last block does not have to be padded
+    let padding = (4 - (BLOCK_HEADER_LEN + len) % 4) % 4;
+    if reader.skip(padding).is_err() {
+        *reader = Reader::new(&[]);
+    }
+
+    let kind = match block_type {
+        ABSOLUTE => BlockKind::Absolute,
+        METADATA | NAME_TEXT | INFO_TEXT => BlockKind::Text,
+        other => match MemoryType::from_type(other) {
+            Some(memory) => BlockKind::Memory(memory),
+            None => BlockKind::Unknown(other),
+        },
+    };
+
+    Ok(Block {
+        kind,
+        offset,
+        algorithm_id,
+        algorithm_version,
+        sample_rate,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a memory block for algorithm 0x1234 with 6 bytes of data, an absolute block and an unpadded text block
+    const TEST_BIN: &[u8] = include_bytes!("fixtures/test.bin");
+
+    #[test]
+    fn parses_the_header() {
+        let coeff = CoeffFile::parse(TEST_BIN).unwrap();
+        assert_eq!(coeff.version, 1);
+        assert_eq!(coeff.core_version, 0x030000);
+    }
+
+    #[test]
+    fn blocks_skip_the_padding() {
+        let coeff = CoeffFile::parse(TEST_BIN).unwrap();
+        let mut blocks = coeff.blocks().map(Result::unwrap);
+
+        let block = blocks.next().unwrap();
+        assert_eq!(block.kind, BlockKind::Memory(MemoryType::Xm));
+        assert_eq!(block.offset, 8);
+        assert_eq!(block.algorithm_id, 0x1234);
+        assert_eq!(block.algorithm_version, 0x010000);
+        assert_eq!(block.data, &[1, 2, 3, 4, 5, 6]);
+
+        let block = blocks.next().unwrap();
+        assert_eq!(block.kind, BlockKind::Absolute);
+        assert_eq!(block.offset, 0x100);
+        assert_eq!(block.data, &2_u32.to_le_bytes());
+
+        // the last block is not padded
+        let block = blocks.next().unwrap();
+        assert_eq!(block.kind, BlockKind::Text);
+        assert_eq!(block.data, b"tuning");
+
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        assert_eq!(
+            CoeffFile::parse(include_bytes!("fixtures/test.wmfw")),
+            Err(ParseError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        assert_eq!(
+            CoeffFile::parse(&TEST_BIN[..12]),
+            Err(ParseError::Truncated)
+        );
+        assert_eq!(
+            CoeffFile::parse(&TEST_BIN[..18]),
+            Err(ParseError::Truncated)
+        );
+    }
+
+    #[test]
+    fn truncated_block_ends_the_blocks() {
+        let coeff = CoeffFile::parse(&TEST_BIN[..TEST_BIN.len() - 1]).unwrap();
+        let mut blocks = coeff.blocks();
+        assert!(blocks.next().unwrap().is_ok());
+        assert!(blocks.next().unwrap().is_ok());
+        assert_eq!(blocks.next(), Some(Err(ParseError::Truncated)));
+        assert_eq!(blocks.next(), None);
+    }
+}
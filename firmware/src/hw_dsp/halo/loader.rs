@@ -0,0 +1,630 @@
+use super::coeff::{BlockKind, CoeffFile};
+use super::wmfw::{RegionKind, Wmfw};
+use super::{MemoryType, ParseError};
+use crate::hw_dsp::error::DspError;
+use cs47l63::{
+    hw_interface::Bus,
+    spec::{
+        CS47L63_DSP1_CCM_CORE_CONTROL, CS47L63_DSP1_CORE_SOFT_RESET, CS47L63_DSP1_MPU_LOCK_CONFIG,
+        CS47L63_DSP1_MPU_WINDOW_ACCESS_0, CS47L63_DSP1_MPU_WINDOW_ACCESS_1,
+        CS47L63_DSP1_MPU_WINDOW_ACCESS_2, CS47L63_DSP1_MPU_WINDOW_ACCESS_3,
+        CS47L63_DSP1_MPU_XMEM_ACCESS_0, CS47L63_DSP1_MPU_XMEM_ACCESS_1,
+        CS47L63_DSP1_MPU_XMEM_ACCESS_2, CS47L63_DSP1_MPU_XMEM_ACCESS_3,
+        CS47L63_DSP1_MPU_XREG_ACCESS_0, CS47L63_DSP1_MPU_XREG_ACCESS_1,
+        CS47L63_DSP1_MPU_XREG_ACCESS_2, CS47L63_DSP1_MPU_XREG_ACCESS_3,
+        CS47L63_DSP1_MPU_YMEM_ACCESS_0, CS47L63_DSP1_MPU_YMEM_ACCESS_1,
+        CS47L63_DSP1_MPU_YMEM_ACCESS_2, CS47L63_DSP1_MPU_YMEM_ACCESS_3,
+        CS47L63_DSP1_MPU_YREG_ACCESS_0, CS47L63_DSP1_MPU_YREG_ACCESS_1,
+        CS47L63_DSP1_MPU_YREG_ACCESS_2, CS47L63_DSP1_MPU_YREG_ACCESS_3, CS47L63_DSP1_PMEM_0,
+        CS47L63_DSP1_XMEM_PACKED_0, CS47L63_DSP1_XMEM_UNPACKED24_0, CS47L63_DSP1_YMEM_PACKED_0,
+        CS47L63_DSP1_YMEM_UNPACKED24_0, CS47L63_DSP_CLOCK1,
+    },
+};
+
+// Downloads firmware and coefficients to the Halo DSP core and controls it while it runs.
+//
+// The core has to be held in reset while it is downloaded to. `Firmware::load` holds the core in reset with the DSP clock
+// running (the memories are only accessible with the clock on), writes every memory and absolute region of the firmware
+// and then reads the algorithm list the firmware placed at the start of X memory.
+// That list has the base address of every algorithm which coefficient files and controls are relative to.
+// Data memory words are 24 bits and are accessed through the unpacked view of the memory where every word has its own
+// 32 bit register, so control values are big endian 32 bit words with the top byte unused.
+//
+// `start` opens the memory protection unit (MPU) to the core, then enables the core and releases it from reset.
+// `stop` holds the core in reset again and disables the DSP clock.
+// SYSCLK has to be running. DSP memory is volatile (see `reg_cache`) so a `SharedBus` never caches it.
+
+/// Maximum number of algorithms in a firmware
+pub const MAX_ALGORITHMS: usize = 16;
+
+// largest write_block, a multiple of the register size
+const MAX_WRITE_BYTES: usize = 256;
+
+// the firmware id header at the start of X memory: core id, block revision, vendor id, firmware id, firmware version,
+// X and Y memory base and size of the firmware itself and the number of algorithms
+const ID_HEADER_WORDS: u32 = 10;
+const NUM_ALGORITHMS_WORD: u32 = 9;
+
+// every algorithm in the list: id, version, X memory base and size, Y memory base and size
+const ALGORITHM_WORDS: u32 = 6;
+
+const WORD_MASK: u32 = 0xFF_FFFF;
+
+// Halo core control bits
+const CORE_EN: u32 = 0x1;
+const CORE_RESET: u32 = 0x200;
+const CORE_SOFT_RESET: u32 = 0x1;
+
+// DSP_CLK_EN in DSP_CLOCK1 (the cs47l63 crate does not export it)
+const DSP_CLK_EN_MASK: u32 = 0x2;
+
+// the MPU configuration is only writeable after the two unlock codes and is locked again by writing 0
+const MPU_UNLOCK_CODES: [u32; 2] = [0x5555, 0xAAAA];
+const MPU_LOCK: u32 = 0;
+
+// every bank of X and Y memory is accessible to the core and no register window or register range is locked
+const MPU_FULL_ACCESS: u32 = 0xFFFF_FFFF;
+const MPU_NO_LOCKED_REGIONS: u32 = 0;
+
+const MPU_ACCESS: [[u32; 2]; 20] = [
+    [CS47L63_DSP1_MPU_XMEM_ACCESS_0, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_YMEM_ACCESS_0, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_WINDOW_ACCESS_0, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XREG_ACCESS_0, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_YREG_ACCESS_0, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XMEM_ACCESS_1, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_YMEM_ACCESS_1, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_WINDOW_ACCESS_1, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XREG_ACCESS_1, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_YREG_ACCESS_1, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XMEM_ACCESS_2, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_YMEM_ACCESS_2, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_WINDOW_ACCESS_2, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XREG_ACCESS_2, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_YREG_ACCESS_2, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XMEM_ACCESS_3, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_YMEM_ACCESS_3, MPU_FULL_ACCESS],
+    [CS47L63_DSP1_MPU_WINDOW_ACCESS_3, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_XREG_ACCESS_3, MPU_NO_LOCKED_REGIONS],
+    [CS47L63_DSP1_MPU_YREG_ACCESS_3, MPU_NO_LOCKED_REGIONS],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum HaloError<E> {
+    /// The firmware or coefficient file is malformed
+    Parse(ParseError),
+    Dsp(DspError<E>),
+    /// The firmware reports more than MAX_ALGORITHMS algorithms
+    TooManyAlgorithms(u32),
+    /// A coefficient block or control refers to an algorithm that is not in the firmware
+    UnknownAlgorithm(u32),
+    /// The algorithm has no control with that name
+    UnknownControl,
+    /// The control is not readable or not writeable
+    NotAccessible,
+    /// The value is not the size of the control
+    LengthMismatch {
+        expected: u32,
+        found: usize,
+    },
+    /// Controls can only be accessed in unpacked data memory
+    UnsupportedMemory,
+}
+
+impl<E> From<ParseError> for HaloError<E> {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl<E> From<DspError<E>> for HaloError<E> {
+    fn from(e: DspError<E>) -> Self {
+        Self::Dsp(e)
+    }
+}
+
+/// An algorithm as placed in memory by the running firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct AlgorithmInfo {
+    pub id: u32,
+    pub version: u32,
+    /// X memory base in words
+    pub xm_base: u32,
+    /// Y memory base in words
+    pub ym_base: u32,
+}
+
+impl AlgorithmInfo {
+    // the base of the algorithm in a data memory in words
+    fn base(&self, memory: MemoryType) -> Option<u32> {
+        match memory {
+            MemoryType::Xm | MemoryType::XmPacked => Some(self.xm_base),
+            MemoryType::Ym | MemoryType::YmPacked => Some(self.ym_base),
+            MemoryType::PmPacked => None,
+        }
+    }
+}
+
+// the register of a word offset into a memory
+fn memory_reg(memory: MemoryType, offset: u32) -> u32 {
+    match memory {
+        MemoryType::PmPacked => CS47L63_DSP1_PMEM_0 + offset * 5,
+        MemoryType::XmPacked => (CS47L63_DSP1_XMEM_PACKED_0 + offset * 3) & !0x3,
+        MemoryType::YmPacked => (CS47L63_DSP1_YMEM_PACKED_0 + offset * 3) & !0x3,
+        MemoryType::Xm => CS47L63_DSP1_XMEM_UNPACKED24_0 + offset * 4,
+        MemoryType::Ym => CS47L63_DSP1_YMEM_UNPACKED24_0 + offset * 4,
+    }
+}
+
+// writes data to consecutive registers starting at reg
+async fn write_data<E>(bus: &mut impl Bus<E>, reg: u32, data: &[u8]) -> Result<(), DspError<E>> {
+    let mut reg = reg;
+    for chunk in data.chunks(MAX_WRITE_BYTES) {
+        bus.write_block(reg, chunk).await.map_err(DspError::Bus)?;
+        reg += chunk.len() as u32;
+    }
+
+    Ok(())
+}
+
+async fn read_word<E>(bus: &mut impl Bus<E>, word: u32) -> Result<u32, DspError<E>> {
+    let val = bus
+        .read(memory_reg(MemoryType::Xm, word))
+        .await
+        .map_err(DspError::Bus)?;
+    Ok(val & WORD_MASK)
+}
+
+async fn set_clock<E>(bus: &mut impl Bus<E>, enabled: bool) -> Result<(), DspError<E>> {
+    let clock = bus.read(CS47L63_DSP_CLOCK1).await.map_err(DspError::Bus)?;
+    let clock = if enabled {
+        clock | DSP_CLK_EN_MASK
+    } else {
+        clock & !DSP_CLK_EN_MASK
+    };
+    bus.write(CS47L63_DSP_CLOCK1, clock)
+        .await
+        .map_err(DspError::Bus)
+}
+
+// disables the core and holds it in reset, the DSP clock is left as it is
+async fn halt<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+    let control = bus
+        .read(CS47L63_DSP1_CCM_CORE_CONTROL)
+        .await
+        .map_err(DspError::Bus)?;
+    bus.write(CS47L63_DSP1_CCM_CORE_CONTROL, control & !CORE_EN)
+        .await
+        .map_err(DspError::Bus)?;
+    bus.write(CS47L63_DSP1_CORE_SOFT_RESET, CORE_SOFT_RESET)
+        .await
+        .map_err(DspError::Bus)
+}
+
+// gives the core access to all of its memory
+async fn configure_mpu<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+    for code in MPU_UNLOCK_CODES {
+        bus.write(CS47L63_DSP1_MPU_LOCK_CONFIG, code)
+            .await
+            .map_err(DspError::Bus)?;
+    }
+
+    for [reg, val] in MPU_ACCESS {
+        bus.write(reg, val).await.map_err(DspError::Bus)?;
+    }
+
+    bus.write(CS47L63_DSP1_MPU_LOCK_CONFIG, MPU_LOCK)
+        .await
+        .map_err(DspError::Bus)
+}
+
+/// starts the core: configures the MPU, enables the DSP clock and the core and then takes the core out of reset
+pub async fn start<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+    configure_mpu(bus).await?;
+    set_clock(bus, true).await?;
+
+    let control = bus
+        .read(CS47L63_DSP1_CCM_CORE_CONTROL)
+        .await
+        .map_err(DspError::Bus)?;
+    bus.write(
+        CS47L63_DSP1_CCM_CORE_CONTROL,
+        control | CORE_RESET | CORE_EN,
+    )
+    .await
+    .map_err(DspError::Bus)?;
+    bus.write(
+        CS47L63_DSP1_CCM_CORE_CONTROL,
+        (control | CORE_EN) & !CORE_RESET,
+    )
+    .await
+    .map_err(DspError::Bus)
+}
+
+/// stops the core, resets it and disables the DSP clock
+pub async fn stop<E>(bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+    halt(bus).await?;
+    set_clock(bus, false).await
+}
+
+/// Firmware downloaded to the core with the algorithm list read back from it
+pub struct Firmware<'a> {
+    wmfw: Wmfw<'a>,
+    algorithms: [Option<AlgorithmInfo>; MAX_ALGORITHMS],
+}
+
+impl<'a> Firmware<'a> {
+    /// holds the core in reset, downloads the firmware and reads the algorithm list
+    /// the core is left in reset with the DSP clock running, call `start` to run it
+    pub async fn load<E>(bus: &mut impl Bus<E>, wmfw: Wmfw<'a>) -> Result<Self, HaloError<E>> {
+        halt(bus).await?;
+        set_clock(bus, true).await?;
+
+        for region in wmfw.regions() {
+            let region = region?;
+            match region.kind {
+                RegionKind::Memory(memory) => {
+                    write_data(bus, memory_reg(memory, region.offset), region.data).await?
+                }
+                RegionKind::Absolute => write_data(bus, region.offset, region.data).await?,
+                RegionKind::AlgorithmData | RegionKind::Text | RegionKind::Unknown(_) => {}
+            }
+        }
+
+        let num_algorithms = read_word(bus, NUM_ALGORITHMS_WORD).await?;
+        if num_algorithms as usize > MAX_ALGORITHMS {
+            return Err(HaloError::TooManyAlgorithms(num_algorithms));
+        }
+
+        let mut algorithms = [None; MAX_ALGORITHMS];
+        for (index, algorithm) in algorithms
+            .iter_mut()
+            .take(num_algorithms as usize)
+            .enumerate()
+        {
+            let word = ID_HEADER_WORDS + index as u32 * ALGORITHM_WORDS;
+            *algorithm = Some(AlgorithmInfo {
+                id: read_word(bus, word).await?,
+                version: read_word(bus, word + 1).await?,
+                xm_base: read_word(bus, word + 2).await?,
+                ym_base: read_word(bus, word + 4).await?,
+            });
+        }
+
+        Ok(Self { wmfw, algorithms })
+    }
+
+    pub fn wmfw(&self) -> &Wmfw<'a> {
+        &self.wmfw
+    }
+
+    /// the algorithms in the order the firmware lists them
+    pub fn algorithms(&self) -> impl Iterator<Item = &AlgorithmInfo> {
+        self.algorithms.iter().flatten()
+    }
+
+    /// finds an algorithm by id
+    pub fn algorithm(&self, id: u32) -> Option<&AlgorithmInfo> {
+        self.algorithms().find(|algorithm| algorithm.id == id)
+    }
+
+    /// writes every memory and absolute block of a coefficient file (the core may be running)
+    pub async fn apply_coefficients<E>(
+        &self,
+        bus: &mut impl Bus<E>,
+        coeff: &CoeffFile<'_>,
+    ) -> Result<(), HaloError<E>> {
+        for block in coeff.blocks() {
+            let block = block?;
+            match block.kind {
+                BlockKind::Memory(memory) => {
+                    let algorithm = self
+                        .algorithm(block.algorithm_id)
+                        .ok_or(HaloError::UnknownAlgorithm(block.algorithm_id))?;
+                    let base = algorithm.base(memory).ok_or(HaloError::UnsupportedMemory)?;
+                    // the block offset is in bytes from the register of the algorithm base
+                    let reg = memory_reg(memory, base) + block.offset as u32;
+                    write_data(bus, reg, block.data).await?;
+                }
+                BlockKind::Absolute => write_data(bus, block.offset as u32, block.data).await?,
+                BlockKind::Text | BlockKind::Unknown(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // the first register of a control and its length in bytes
+    fn control_reg<E>(
+        &self,
+        algorithm_id: u32,
+        name: &str,
+        writing: bool,
+    ) -> Result<(u32, u32), HaloError<E>> {
+        let algorithm = self
+            .algorithm(algorithm_id)
+            .ok_or(HaloError::UnknownAlgorithm(algorithm_id))?;
+        let control = self
+            .wmfw
+            .algorithm(algorithm_id)?
+            .ok_or(HaloError::UnknownAlgorithm(algorithm_id))?
+            .control(name)?
+            .ok_or(HaloError::UnknownControl)?;
+
+        // controls without flags can be read and written
+        let accessible = control.flags == 0
+            || if writing {
+                control.is_writeable()
+            } else {
+                control.is_readable()
+            };
+        if !accessible {
+            return Err(HaloError::NotAccessible);
+        }
+
+        let memory = match control.memory {
+            Some(memory @ (MemoryType::Xm | MemoryType::Ym)) => memory,
+            _ => return Err(HaloError::UnsupportedMemory),
+        };
+        let base = algorithm.base(memory).ok_or(HaloError::UnsupportedMemory)?;
+        Ok((
+            memory_reg(memory, base + control.offset as u32),
+            control.len,
+        ))
+    }
+
+    /// reads the value of a control into `value` which has to be the size of the control
+    pub async fn read_control<E>(
+        &self,
+        bus: &mut impl Bus<E>,
+        algorithm_id: u32,
+        name: &str,
+        value: &mut [u8],
+    ) -> Result<(), HaloError<E>> {
+        let (reg, len) = self.control_reg::<E>(algorithm_id, name, false)?;
+        if value.len() != len as usize || !value.chunks_exact(4).remainder().is_empty() {
+            return Err(HaloError::LengthMismatch {
+                expected: len,
+                found: value.len(),
+            });
+        }
+
+        for (word_reg, bytes) in (reg..).step_by(4).zip(value.chunks_exact_mut(4)) {
+            let word = bus.read(word_reg).await.map_err(DspError::Bus)?;
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// writes the value of a control, `value` has to be the size of the control
+    pub async fn write_control<E>(
+        &self,
+        bus: &mut impl Bus<E>,
+        algorithm_id: u32,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), HaloError<E>> {
+        let (reg, len) = self.control_reg::<E>(algorithm_id, name, true)?;
+        if value.len() != len as usize || !value.chunks_exact(4).remainder().is_empty() {
+            return Err(HaloError::LengthMismatch {
+                expected: len,
+                found: value.len(),
+            });
+        }
+
+        write_data(bus, reg, value).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::sim::{SimCodec, SimError, Transaction};
+    use embassy_futures::block_on;
+
+    // see the comments in the tests of `wmfw` and `coeff`
+    const TEST_WMFW: &[u8] = include_bytes!("fixtures/test.wmfw");
+    const TEST_BIN: &[u8] = include_bytes!("fixtures/test.bin");
+
+    const ALGORITHM_ID: u32 = 0x1234;
+    const XM_BASE: u32 = 0x100;
+    const YM_BASE: u32 = 0x200;
+
+    // the algorithm list the firmware would place at the start of X memory
+    fn seed_algorithms(sim: &mut SimCodec, algorithms: &[AlgorithmInfo]) {
+        let mut set_word = |word: u32, val: u32| {
+            sim.set(memory_reg(MemoryType::Xm, word), val).unwrap();
+        };
+
+        set_word(NUM_ALGORITHMS_WORD, algorithms.len() as u32);
+        for (index, algorithm) in algorithms.iter().enumerate() {
+            let word = ID_HEADER_WORDS + index as u32 * ALGORITHM_WORDS;
+            set_word(word, algorithm.id);
+            set_word(word + 1, algorithm.version);
+            set_word(word + 2, algorithm.xm_base);
+            set_word(word + 4, algorithm.ym_base);
+        }
+    }
+
+    fn load(sim: &mut SimCodec) -> Firmware<'static> {
+        seed_algorithms(
+            sim,
+            &[AlgorithmInfo {
+                id: ALGORITHM_ID,
+                version: 0x010000,
+                xm_base: XM_BASE,
+                ym_base: YM_BASE,
+            }],
+        );
+        block_on(Firmware::load(sim, Wmfw::parse(TEST_WMFW).unwrap())).unwrap()
+    }
+
+    fn block_writes(sim: &SimCodec) -> Vec<(u32, usize)> {
+        sim.transactions()
+            .filter_map(|transaction| match transaction {
+                Transaction::WriteBlock { reg, len } => Some((*reg, *len)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn memory_words_map_to_registers() {
+        // 5 bytes (a 40 bit instruction) per program word
+        assert_eq!(memory_reg(MemoryType::PmPacked, 0), CS47L63_DSP1_PMEM_0);
+        assert_eq!(
+            memory_reg(MemoryType::PmPacked, 2),
+            CS47L63_DSP1_PMEM_0 + 10
+        );
+
+        // 4 data words are packed into 3 registers, rounded down to a register boundary
+        assert_eq!(
+            memory_reg(MemoryType::XmPacked, 1),
+            CS47L63_DSP1_XMEM_PACKED_0
+        );
+        assert_eq!(
+            memory_reg(MemoryType::XmPacked, 4),
+            CS47L63_DSP1_XMEM_PACKED_0 + 12
+        );
+        assert_eq!(
+            memory_reg(MemoryType::XmPacked, 5),
+            CS47L63_DSP1_XMEM_PACKED_0 + 12
+        );
+        assert_eq!(
+            memory_reg(MemoryType::YmPacked, 8),
+            CS47L63_DSP1_YMEM_PACKED_0 + 24
+        );
+
+        // one register per unpacked word
+        assert_eq!(
+            memory_reg(MemoryType::Xm, 3),
+            CS47L63_DSP1_XMEM_UNPACKED24_0 + 12
+        );
+        assert_eq!(
+            memory_reg(MemoryType::Ym, 3),
+            CS47L63_DSP1_YMEM_UNPACKED24_0 + 12
+        );
+    }
+
+    #[test]
+    fn load_writes_every_region_and_reads_the_algorithm_list() {
+        let mut sim: SimCodec = SimCodec::new();
+        let firmware = load(&mut sim);
+
+        assert_eq!(block_writes(&sim), [(CS47L63_DSP1_PMEM_0, 10), (0xB000, 4)]);
+        assert_eq!(sim.get(0xB000), 0x01000000);
+
+        // the core is held in reset with the DSP clock on
+        assert_eq!(sim.get(CS47L63_DSP1_CCM_CORE_CONTROL) & CORE_EN, 0);
+        assert_ne!(sim.get(CS47L63_DSP_CLOCK1) & DSP_CLK_EN_MASK, 0);
+
+        let algorithms: Vec<_> = firmware.algorithms().copied().collect();
+        assert_eq!(
+            algorithms,
+            [AlgorithmInfo {
+                id: ALGORITHM_ID,
+                version: 0x010000,
+                xm_base: XM_BASE,
+                ym_base: YM_BASE,
+            }]
+        );
+    }
+
+    #[test]
+    fn too_many_algorithms_are_rejected() {
+        let mut sim: SimCodec = SimCodec::new();
+        sim.set(
+            memory_reg(MemoryType::Xm, NUM_ALGORITHMS_WORD),
+            MAX_ALGORITHMS as u32 + 1,
+        )
+        .unwrap();
+        assert_eq!(
+            block_on(Firmware::load(&mut sim, Wmfw::parse(TEST_WMFW).unwrap())).err(),
+            Some(HaloError::TooManyAlgorithms(MAX_ALGORITHMS as u32 + 1))
+        );
+    }
+
+    #[test]
+    fn coefficients_are_written_from_the_algorithm_base() {
+        let mut sim: SimCodec = SimCodec::new();
+        let firmware = load(&mut sim);
+        let writes = block_writes(&sim).len();
+
+        let coeff = CoeffFile::parse(TEST_BIN).unwrap();
+        block_on(firmware.apply_coefficients(&mut sim, &coeff)).unwrap();
+
+        // the memory block is 8 bytes (2 words) past the register of the X memory base, the text block is not written
+        assert_eq!(
+            block_writes(&sim)[writes..],
+            [(memory_reg(MemoryType::Xm, XM_BASE) + 8, 6), (0x100, 4),]
+        );
+        assert_eq!(sim.get(memory_reg(MemoryType::Xm, XM_BASE + 2)), 0x01020304);
+        assert_eq!(sim.get(0x100), 0x02000000);
+    }
+
+    #[test]
+    fn coefficients_for_an_unknown_algorithm_are_rejected() {
+        let mut sim: SimCodec = SimCodec::new();
+        seed_algorithms(&mut sim, &[]);
+        let firmware = block_on(Firmware::load(&mut sim, Wmfw::parse(TEST_WMFW).unwrap())).unwrap();
+
+        let coeff = CoeffFile::parse(TEST_BIN).unwrap();
+        assert_eq!(
+            block_on(firmware.apply_coefficients(&mut sim, &coeff)),
+            Err(HaloError::UnknownAlgorithm(ALGORITHM_ID))
+        );
+    }
+
+    #[test]
+    fn controls_are_accessed_at_their_word_offset() {
+        let mut sim: SimCodec = SimCodec::new();
+        let firmware = load(&mut sim);
+
+        // GAIN is the first word of the algorithm in X memory
+        block_on(firmware.write_control(&mut sim, ALGORITHM_ID, "GAIN", &[0, 0x12, 0x34, 0x56]))
+            .unwrap();
+        assert_eq!(sim.get(memory_reg(MemoryType::Xm, XM_BASE)), 0x123456);
+
+        let mut gain = [0; 4];
+        block_on(firmware.read_control(&mut sim, ALGORITHM_ID, "GAIN", &mut gain)).unwrap();
+        assert_eq!(gain, [0, 0x12, 0x34, 0x56]);
+
+        // ENABLE is two read only words 2 words into the algorithm in Y memory
+        sim.set(memory_reg(MemoryType::Ym, YM_BASE + 2), 1).unwrap();
+        sim.set(memory_reg(MemoryType::Ym, YM_BASE + 3), 2).unwrap();
+        let mut enable = [0; 8];
+        block_on(firmware.read_control(&mut sim, ALGORITHM_ID, "ENABLE", &mut enable)).unwrap();
+        assert_eq!(enable, [0, 0, 0, 1, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn control_access_is_checked() {
+        let mut sim: SimCodec = SimCodec::new();
+        let firmware = load(&mut sim);
+
+        let write = |sim: &mut SimCodec, name: &str, value: &[u8]| {
+            block_on(firmware.write_control(sim, ALGORITHM_ID, name, value))
+        };
+        assert_eq!(
+            write(&mut sim, "ENABLE", &[0; 8]),
+            Err(HaloError::<SimError>::NotAccessible)
+        );
+        assert_eq!(
+            write(&mut sim, "GAIN", &[0; 8]),
+            Err(HaloError::LengthMismatch {
+                expected: 4,
+                found: 8
+            })
+        );
+        assert_eq!(
+            write(&mut sim, "MISSING", &[0; 4]),
+            Err(HaloError::UnknownControl)
+        );
+        assert_eq!(
+            block_on(firmware.write_control(&mut sim, 0x5678, "GAIN", &[0; 4])),
+            Err(HaloError::UnknownAlgorithm(0x5678))
+        );
+    }
+}
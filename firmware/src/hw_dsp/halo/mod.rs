@@ -0,0 +1,56 @@
+// Firmware for the Halo DSP core of the CS47L63.
+//
+// Cirrus tools produce a `.wmfw` file with the program and the layout of its algorithms and controls
+// and optional `.bin` files with coefficients (tunings) for those algorithms.
+// `wmfw` and `coeff` parse the files in place (nothing is copied) and only depend on `core` and `defmt`,
+// so they are tested on the host against the sample files in `fixtures`.
+// `loader` downloads parsed files to the codec, starts and stops the core and reads and writes algorithm controls.
+
+pub mod coeff;
+pub mod loader;
+mod reader;
+pub mod wmfw;
+
+/// Why a firmware or coefficient file could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ParseError {
+    /// The file does not start with the expected magic number
+    BadMagic,
+    /// The file ends in the middle of a header, region or block
+    Truncated,
+    /// The firmware is not for a Halo core
+    UnsupportedCore(u8),
+    /// The file format version is not supported
+    UnsupportedVersion(u32),
+    /// A name or description is not valid UTF-8
+    InvalidString,
+}
+
+/// A DSP memory that firmware and coefficients are written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum MemoryType {
+    /// program memory, 5 bytes for every 40 bit word
+    PmPacked,
+    /// X data memory, 3 bytes for every 24 bit word
+    XmPacked,
+    /// Y data memory, 3 bytes for every 24 bit word
+    YmPacked,
+    /// X data memory, one 24 bit word in every 32 bit register
+    Xm,
+    /// Y data memory, one 24 bit word in every 32 bit register
+    Ym,
+}
+
+impl MemoryType {
+    /// the memory of a region or block type, None for anything that is not written to a memory
+    pub fn from_type(region_type: u16) -> Option<Self> {
+        match region_type {
+            0x05 => Some(Self::Xm),
+            0x06 => Some(Self::Ym),
+            0x10 => Some(Self::PmPacked),
+            0x11 => Some(Self::XmPacked),
+            0x12 => Some(Self::YmPacked),
+            _ => None,
+        }
+    }
+}
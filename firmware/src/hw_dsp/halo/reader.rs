@@ -0,0 +1,67 @@
+use super::ParseError;
+
+// A cursor over the bytes of a firmware or coefficient file.
+// Integers are little endian and strings are length prefixed and padded so that the next field is 4 byte aligned.
+
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let end = self.pos.checked_add(len).ok_or(ParseError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(ParseError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<(), ParseError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn le16(&mut self) -> Result<u16, ParseError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn le32(&mut self) -> Result<u32, ParseError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// a string with a one byte length
+    pub fn short_string(&mut self) -> Result<&'a str, ParseError> {
+        let len = self.u8()? as usize;
+        self.string(1, len)
+    }
+
+    /// a string with a two byte length
+    pub fn long_string(&mut self) -> Result<&'a str, ParseError> {
+        let len = self.le16()? as usize;
+        self.string(2, len)
+    }
+
+    fn string(&mut self, prefix_len: usize, len: usize) -> Result<&'a str, ParseError> {
+        let bytes = self.bytes(len)?;
+        let padding = (4 - (prefix_len + len) % 4) % 4;
+        self.skip(padding)?;
+        core::str::from_utf8(bytes).map_err(|_| ParseError::InvalidString)
+    }
+}
@@ -0,0 +1,407 @@
+use super::reader::Reader;
+use super::{MemoryType, ParseError};
+
+// Parser for `.wmfw` firmware files.
+//
+// The file starts with a 12 byte header (magic, header length, revision, core and format version) followed by the
+// memory sizes and a footer, all of which is covered by the header length. After that come the regions, each with an
+// 8 byte header: a word holding the region type in its top byte and the offset in its lower 24 bits, then the data length.
+// Memory regions hold data to write at a word offset into a DSP memory, absolute regions data to write at a register address.
+// Algorithm data regions describe the algorithms in the firmware and their controls (coefficients).
+
+pub const MAGIC: &[u8; 4] = b"WMFP";
+
+/// The core type of the Halo core in the header
+pub const HALO_CORE: u8 = 4;
+
+// versions with length prefixed names in the algorithm data
+const MIN_VERSION: u8 = 2;
+const MAX_VERSION: u8 = 3;
+
+const HEADER_LEN: usize = 12;
+
+const ABSOLUTE: u8 = 0xf0;
+const ALGORITHM_DATA: u8 = 0xf2;
+const METADATA: u8 = 0xfc;
+const NAME_TEXT: u8 = 0xfe;
+const INFO_TEXT: u8 = 0xff;
+
+/// What a region holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RegionKind {
+    /// data to write to a DSP memory at a word offset
+    Memory(MemoryType),
+    /// data to write at a register address
+    Absolute,
+    /// the algorithms and their controls, see `Wmfw::algorithms`
+    AlgorithmData,
+    /// a name, description or other text that is not written to the device
+    Text,
+    Unknown(u8),
+}
+
+/// A region of a firmware file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Region<'a> {
+    pub kind: RegionKind,
+    /// word offset into the memory or register address
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+/// A parsed `.wmfw` file borrowing the bytes it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Wmfw<'a> {
+    pub version: u8,
+    pub revision: u16,
+    regions: &'a [u8],
+}
+
+impl<'a> Wmfw<'a> {
+    /// checks the header, regions are parsed as they are iterated over
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(ParseError::BadMagic);
+        }
+
+        let len = reader.le32()? as usize;
+        let revision = reader.le16()?;
+        let core = reader.u8()?;
+        let version = reader.u8()?;
+
+        if core != HALO_CORE {
+            return Err(ParseError::UnsupportedCore(core));
+        }
+
+        if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+            return Err(ParseError::UnsupportedVersion(version as u32));
+        }
+
+        // the header length covers the memory sizes and the footer as well
+        let regions = bytes
+            .get(len.max(HEADER_LEN)..)
+            .ok_or(ParseError::Truncated)?;
+
+        Ok(Self {
+            version,
+            revision,
+            regions,
+        })
+    }
+
+    /// every region in file order
+    pub fn regions(&self) -> Regions<'a> {
+        Regions {
+            reader: Reader::new(self.regions),
+        }
+    }
+
+    /// the algorithms described by the algorithm data regions
+    pub fn algorithms(&self) -> impl Iterator<Item = Result<Algorithm<'a>, ParseError>> {
+        self.regions()
+            .filter(|region| match region {
+                Ok(region) => region.kind == RegionKind::AlgorithmData,
+                // errors are passed on
+                Err(_) => true,
+            })
+            .map(|region| region.and_then(|region| Algorithm::parse(region.data)))
+    }
+
+    /// finds an algorithm by id
+    pub fn algorithm(&self, id: u32) -> Result<Option<Algorithm<'a>>, ParseError> {
+        for algorithm in self.algorithms() {
+            let algorithm = algorithm?;
+            if algorithm.id == id {
+                return Ok(Some(algorithm));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Iterates over the regions of a firmware file, stops after the first error
+pub struct Regions<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> Iterator for Regions<'a> {
+    type Item = Result<Region<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_empty() {
+            return None;
+        }
+
+        let region = parse_region(&mut self.reader);
+        if region.is_err() {
+            // skip the rest of the file
+            self.reader = Reader::new(&[]);
+        }
+
+        Some(region)
+    }
+}
+
+fn parse_region<'a>(reader: &mut Reader<'a>) -> Result<Region<'a>, ParseError> {
+    let word = reader.le32()?;
+    let len = reader.le32()? as usize;
+    let data = reader.bytes(len)?;
+
+    let region_type = (word >> 24) as u8;
+    let kind = match region_type {
+        ABSOLUTE => RegionKind::Absolute,
+        ALGORITHM_DATA => RegionKind::AlgorithmData,
+        METADATA | NAME_TEXT | INFO_TEXT => RegionKind::Text,
+        other => match MemoryType::from_type(other as u16) {
+            Some(memory) => RegionKind::Memory(memory),
+            None => RegionKind::Unknown(other),
+        },
+    };
+
+    Ok(Region {
+        kind,
+        offset: word & 0xFF_FFFF,
+        data,
+    })
+}
+
+/// An algorithm in the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Algorithm<'a> {
+    pub id: u32,
+    pub name: &'a str,
+    pub description: &'a str,
+    num_controls: u32,
+    controls: &'a [u8],
+}
+
+impl<'a> Algorithm<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, ParseError> {
+        let mut reader = Reader::new(data);
+        let id = reader.le32()?;
+        let name = reader.short_string()?;
+        let description = reader.long_string()?;
+        let num_controls = reader.le32()?;
+
+        Ok(Self {
+            id,
+            name,
+            description,
+            num_controls,
+            controls: &data[reader.pos()..],
+        })
+    }
+
+    /// the controls of the algorithm
+    pub fn controls(&self) -> Controls<'a> {
+        Controls {
+            reader: Reader::new(self.controls),
+            remaining: self.num_controls,
+        }
+    }
+
+    /// finds a control by its (short) name
+    pub fn control(&self, name: &str) -> Result<Option<Control<'a>>, ParseError> {
+        for control in self.controls() {
+            let control = control?;
+            if control.name == name {
+                return Ok(Some(control));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// A control (coefficient) of an algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Control<'a> {
+    pub name: &'a str,
+    /// the memory the control is in, None if it is not in a data memory
+    pub memory: Option<MemoryType>,
+    /// word offset from the start of the algorithm in that memory
+    pub offset: u16,
+    /// length of the value in bytes
+    pub len: u32,
+    pub control_type: u16,
+    pub flags: u16,
+}
+
+impl<'a> Control<'a> {
+    const READABLE: u16 = 0x1;
+    const WRITEABLE: u16 = 0x2;
+    const VOLATILE: u16 = 0x4;
+
+    pub fn is_readable(&self) -> bool {
+        self.flags & Self::READABLE != 0
+    }
+
+    pub fn is_writeable(&self) -> bool {
+        self.flags & Self::WRITEABLE != 0
+    }
+
+    /// the value can be changed by the firmware
+    pub fn is_volatile(&self) -> bool {
+        self.flags & Self::VOLATILE != 0
+    }
+}
+
+/// Iterates over the controls of an algorithm, stops after the first error
+pub struct Controls<'a> {
+    reader: Reader<'a>,
+    remaining: u32,
+}
+
+impl<'a> Iterator for Controls<'a> {
+    type Item = Result<Control<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+        let control = parse_control(&mut self.reader);
+        if control.is_err() {
+            self.remaining = 0;
+        }
+
+        Some(control)
+    }
+}
+
+fn parse_control<'a>(reader: &mut Reader<'a>) -> Result<Control<'a>, ParseError> {
+    let offset = reader.le16()?;
+    let memory_type = reader.le16()?;
+    let block_len = reader.le32()? as usize;
+
+    // the rest of the block is read separately so that unknown trailing fields are skipped
+    let mut block = Reader::new(reader.bytes(block_len)?);
+    let name = block.short_string()?;
+    let _full_name = block.short_string()?;
+    let _description = block.long_string()?;
+    let control_type = block.le16()?;
+    let flags = block.le16()?;
+    let len = block.le32()?;
+
+    Ok(Control {
+        name,
+        memory: MemoryType::from_type(memory_type),
+        offset,
+        len,
+        control_type,
+        flags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a Halo firmware with a program region, an absolute region, one algorithm with two controls and a name
+    const TEST_WMFW: &[u8] = include_bytes!("fixtures/test.wmfw");
+
+    // the offset of the core type in the header
+    const CORE_OFFSET: usize = 10;
+
+    #[test]
+    fn parses_the_header() {
+        let wmfw = Wmfw::parse(TEST_WMFW).unwrap();
+        assert_eq!(wmfw.version, 3);
+        assert_eq!(wmfw.revision, 0x0102);
+    }
+
+    #[test]
+    fn regions_are_returned_in_file_order() {
+        let wmfw = Wmfw::parse(TEST_WMFW).unwrap();
+        let mut regions = wmfw.regions().map(Result::unwrap);
+
+        let region = regions.next().unwrap();
+        assert_eq!(region.kind, RegionKind::Memory(MemoryType::PmPacked));
+        assert_eq!(region.offset, 0);
+        assert_eq!(region.data, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+
+        let region = regions.next().unwrap();
+        assert_eq!(region.kind, RegionKind::Absolute);
+        assert_eq!(region.offset, 0xB000);
+        assert_eq!(region.data, &1_u32.to_le_bytes());
+
+        assert_eq!(regions.next().unwrap().kind, RegionKind::AlgorithmData);
+
+        let region = regions.next().unwrap();
+        assert_eq!(region.kind, RegionKind::Text);
+        assert_eq!(region.data, b"test firmware");
+
+        assert!(regions.next().is_none());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = TEST_WMFW.to_vec();
+        bytes[0] = b'X';
+        assert_eq!(Wmfw::parse(&bytes), Err(ParseError::BadMagic));
+        assert_eq!(
+            Wmfw::parse(include_bytes!("fixtures/test.bin")),
+            Err(ParseError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn other_cores_are_rejected() {
+        let mut bytes = TEST_WMFW.to_vec();
+        bytes[CORE_OFFSET] = 2;
+        assert_eq!(Wmfw::parse(&bytes), Err(ParseError::UnsupportedCore(2)));
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        assert_eq!(Wmfw::parse(&TEST_WMFW[..8]), Err(ParseError::Truncated));
+        assert_eq!(Wmfw::parse(&TEST_WMFW[..20]), Err(ParseError::Truncated));
+    }
+
+    #[test]
+    fn truncated_region_ends_the_regions() {
+        let wmfw = Wmfw::parse(&TEST_WMFW[..TEST_WMFW.len() - 1]).unwrap();
+        let mut regions = wmfw.regions();
+        for _ in 0..3 {
+            assert!(regions.next().unwrap().is_ok());
+        }
+        assert_eq!(regions.next(), Some(Err(ParseError::Truncated)));
+        assert_eq!(regions.next(), None);
+    }
+
+    #[test]
+    fn algorithm_strings_are_padded() {
+        let wmfw = Wmfw::parse(TEST_WMFW).unwrap();
+        let algorithm = wmfw.algorithm(0x1234).unwrap().unwrap();
+        assert_eq!(algorithm.name, "TEST");
+        assert_eq!(algorithm.description, "Test algorithm");
+
+        let names: Vec<&str> = algorithm.controls().map(|c| c.unwrap().name).collect();
+        assert_eq!(names, ["GAIN", "ENABLE"]);
+    }
+
+    #[test]
+    fn controls_are_found_by_name() {
+        let wmfw = Wmfw::parse(TEST_WMFW).unwrap();
+        let algorithm = wmfw.algorithm(0x1234).unwrap().unwrap();
+
+        let gain = algorithm.control("GAIN").unwrap().unwrap();
+        assert_eq!(gain.memory, Some(MemoryType::Xm));
+        assert_eq!(gain.offset, 0);
+        assert_eq!(gain.len, 4);
+        assert!(gain.is_readable() && gain.is_writeable());
+
+        // the trailing fields of the control block are skipped
+        let enable = algorithm.control("ENABLE").unwrap().unwrap();
+        assert_eq!(enable.memory, Some(MemoryType::Ym));
+        assert_eq!(enable.offset, 2);
+        assert_eq!(enable.len, 8);
+        assert!(enable.is_readable() && !enable.is_writeable());
+
+        assert_eq!(algorithm.control("MISSING"), Ok(None));
+        assert_eq!(wmfw.algorithm(0x5678), Ok(None));
+    }
+}
@@ -0,0 +1,301 @@
+use super::asp::AspConfig;
+use super::dsp::{self, DEVICE_ID, DEVICE_ID_MASK};
+use super::error::DspError;
+use super::events::{self, CodecEvent, CodecEvents, IrqSource};
+use super::fll;
+use super::reg_list::{CapacityError, RegList};
+use super::volume::{self, OutputChannel, OutputVolume};
+use crate::sample_rate::SampleRateConfig;
+use cs47l63::{
+    hw_interface::Bus,
+    registers::clocking_sample_rates::clock,
+    spec::{CS47L63_DEVID, CS47L63_SYSTEM_CLOCK1},
+};
+use embassy_nrf::gpio::{Output, Pin};
+use embassy_time::{with_timeout, Duration, TimeoutError, Timer};
+
+// Detects a codec that stopped responding or lost its configuration and brings it back.
+//
+// Loose connections to the codec (see docs/internal/Defects.txt) show up as a device id that reads back wrong,
+// a configuration that was reset behind our back or an FLL that lost lock.
+// `HealthMonitor::poll` checks for all three and on failure pulses the reset pin, resets the codec and reapplies
+// the `ActiveConfig`, up to a limit of attempts. Every spi access is wrapped in a timeout.
+// The lock status is not checked while FLL1 is held, since a held FLL keeps SYSCLK going without its reference
+// (the nRF master clock) and a recovery could not lock it again until the nRF I2S is restarted.
+// Faults and recoveries are published as `CodecEvent`s.
+//
+// The output volume and mute state change at runtime (e.g. from the volume buttons) so they are not part of the
+// `ActiveConfig`. The monitor saves them after every passing check and restores them after a recovery, so a change
+// made after the last passing check is lost.
+//
+// The checks must see the device itself so when using a `SharedBus` pass `bus.uncached()`.
+// The bus is held for the whole recovery so other tasks are blocked until it is done.
+
+// SYSCLK is enabled by the configuration and disabled by a reset so it shows whether the configuration was lost
+const SENTINEL_REG: u32 = CS47L63_SYSTEM_CLOCK1;
+const SENTINEL_MASK: u32 = clock::SystemClock1 {
+    frac: clock::Fraction::MultipleOf6M144Hz,
+    freq: clock::Freqency::_6p144MHz,
+    enabled: true,
+    src: clock::Source::Mclk1,
+}
+.serialize()[1]
+    ^ clock::SystemClock1 {
+        frac: clock::Fraction::MultipleOf6M144Hz,
+        freq: clock::Freqency::_6p144MHz,
+        enabled: false,
+        src: clock::Source::Mclk1,
+    }
+    .serialize()[1];
+
+// how long the reset pin is held low
+const RESET_PULSE_MS: u64 = 24;
+
+/// A bus error or a bus access that took too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BusTimeout<E> {
+    Timeout,
+    Bus(E),
+}
+
+/// A bus where every access fails with `BusTimeout::Timeout` if it does not complete in time
+pub struct TimeoutBus<'b, B> {
+    bus: &'b mut B,
+    timeout: Duration,
+}
+
+impl<'b, B> TimeoutBus<'b, B> {
+    pub fn new(bus: &'b mut B, timeout: Duration) -> Self {
+        Self { bus, timeout }
+    }
+}
+
+fn flatten<T, E>(result: Result<Result<T, E>, TimeoutError>) -> Result<T, BusTimeout<E>> {
+    match result {
+        Ok(result) => result.map_err(BusTimeout::Bus),
+        Err(TimeoutError) => Err(BusTimeout::Timeout),
+    }
+}
+
+impl<'b, E, B: Bus<E>> Bus<BusTimeout<E>> for TimeoutBus<'b, B> {
+    async fn read(&mut self, reg: u32) -> Result<u32, BusTimeout<E>> {
+        flatten(with_timeout(self.timeout, self.bus.read(reg)).await)
+    }
+
+    async fn write(&mut self, reg: u32, val: u32) -> Result<(), BusTimeout<E>> {
+        flatten(with_timeout(self.timeout, self.bus.write(reg, val)).await)
+    }
+
+    async fn write_block(&mut self, reg: u32, bytes: &[u8]) -> Result<(), BusTimeout<E>> {
+        flatten(with_timeout(self.timeout, self.bus.write_block(reg, bytes)).await)
+    }
+
+    async fn delay_ms(&self, millis: u64) {
+        self.bus.delay_ms(millis).await;
+    }
+}
+
+/// The reason a health check failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum HealthFault {
+    /// a bus error, timeout or a device id of all zeros or ones
+    NotResponding,
+    WrongDeviceId {
+        found: u32,
+    },
+    /// the codec was reset without us knowing and lost its configuration
+    ConfigurationLost {
+        found: u32,
+    },
+    FllUnlocked,
+}
+
+/// The configuration reapplied after a recovery
+/// the default configuration at a sample rate and audio serial port format, the interrupt sources to enable and any
+/// extra register writes (e.g. routing or EQ)
+/// the output volumes are not part of it, `HealthMonitor` restores them
+pub struct ActiveConfig<const N: usize = 32> {
+    rate: SampleRateConfig,
+    asp: AspConfig,
+    irq_sources: &'static [IrqSource],
+    extra: RegList<N>,
+}
+
+impl<const N: usize> ActiveConfig<N> {
+    pub fn new(rate: SampleRateConfig, asp: AspConfig, irq_sources: &'static [IrqSource]) -> Self {
+        Self {
+            rate,
+            asp,
+            irq_sources,
+            extra: RegList::new(),
+        }
+    }
+
+    /// adds register writes to apply after the default configuration (a register written twice ends up with the last value)
+    pub fn record(&mut self, config: &[[u32; 2]]) -> Result<&mut Self, CapacityError> {
+        self.extra.extend(config)?;
+        Ok(self)
+    }
+
+    /// configures a codec that has just been reset
+    pub async fn apply<E>(&self, bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+        dsp::default_conf_enable(bus, &self.rate, &self.asp).await?;
+        dsp::reg_conf_write(bus, &self.extra).await?;
+        events::enable_all(bus, self.irq_sources).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct HealthConfig {
+    /// time between checks
+    pub interval: Duration,
+    /// time allowed for a single spi access
+    pub spi_timeout: Duration,
+    /// number of times to reset and reconfigure the codec before giving up until the next check
+    pub max_recovery_attempts: u8,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            spi_timeout: Duration::from_millis(10),
+            max_recovery_attempts: 3,
+        }
+    }
+}
+
+/// checks the device id, the sentinel register and the FLL lock status (unless the FLL is held)
+pub async fn check<E>(bus: &mut impl Bus<E>, spi_timeout: Duration) -> Result<(), HealthFault> {
+    let mut bus = TimeoutBus::new(bus, spi_timeout);
+
+    let device_id = bus
+        .read(CS47L63_DEVID)
+        .await
+        .map_err(|_| HealthFault::NotResponding)?;
+    if device_id == 0 || device_id == u32::MAX {
+        return Err(HealthFault::NotResponding);
+    }
+
+    if device_id & DEVICE_ID_MASK != DEVICE_ID {
+        return Err(HealthFault::WrongDeviceId {
+            found: device_id & DEVICE_ID_MASK,
+        });
+    }
+
+    let found = bus
+        .read(SENTINEL_REG)
+        .await
+        .map_err(|_| HealthFault::NotResponding)?;
+    if found & SENTINEL_MASK == 0 {
+        return Err(HealthFault::ConfigurationLost { found });
+    }
+
+    // a held FLL free-runs without its reference (see `tone::start_without_i2s`) and may report that it lost lock
+    let held = fll::is_held(&mut bus)
+        .await
+        .map_err(|_| HealthFault::NotResponding)?;
+    if !held
+        && !fll::is_locked(&mut bus)
+            .await
+            .map_err(|_| HealthFault::NotResponding)?
+    {
+        return Err(HealthFault::FllUnlocked);
+    }
+
+    Ok(())
+}
+
+/// pulses the reset pin, resets the codec and reapplies the configuration
+pub async fn recover<E, const N: usize>(
+    bus: &mut impl Bus<E>,
+    reset_pin: &mut Output<'_, impl Pin>,
+    active: &ActiveConfig<N>,
+    spi_timeout: Duration,
+) -> Result<(), DspError<BusTimeout<E>>> {
+    reset_pin.set_low();
+    Timer::after(Duration::from_millis(RESET_PULSE_MS)).await;
+    reset_pin.set_high();
+
+    let mut bus = TimeoutBus::new(bus, spi_timeout);
+    dsp::reset(&mut bus).await?;
+    active.apply(&mut bus).await
+}
+
+// the outputs whose volume and mute state is restored after a recovery
+const OUTPUTS: [OutputChannel; 1] = [OutputChannel::Out1L];
+
+/// Checks the codec health and recovers it when needed
+pub struct HealthMonitor<const N: usize = 32> {
+    pub config: HealthConfig,
+    pub active: ActiveConfig<N>,
+    // the output volumes at the last passing check
+    volumes: [Option<OutputVolume>; OUTPUTS.len()],
+}
+
+impl<const N: usize> HealthMonitor<N> {
+    pub fn new(config: HealthConfig, active: ActiveConfig<N>) -> Self {
+        Self {
+            config,
+            active,
+            volumes: [None; OUTPUTS.len()],
+        }
+    }
+
+    async fn save_volumes<E>(&mut self, bus: &mut impl Bus<E>) {
+        let mut bus = TimeoutBus::new(bus, self.config.spi_timeout);
+        for (output, saved) in OUTPUTS.iter().zip(self.volumes.iter_mut()) {
+            // keep the previous value if the read fails, the next check will most likely fail too
+            if let Ok(volume) = volume::save(&mut bus, *output).await {
+                *saved = Some(volume);
+            }
+        }
+    }
+
+    async fn restore_volumes<E>(
+        &self,
+        bus: &mut impl Bus<E>,
+    ) -> Result<(), DspError<BusTimeout<E>>> {
+        let mut bus = TimeoutBus::new(bus, self.config.spi_timeout);
+        for (output, saved) in OUTPUTS.iter().zip(self.volumes.iter()) {
+            if let Some(saved) = saved {
+                volume::restore(&mut bus, *output, *saved).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// runs a health check and recovers the codec if it fails, publishing the fault and the outcome as events
+    /// returns true if the codec is healthy (possibly after a recovery)
+    pub async fn poll<E>(
+        &mut self,
+        bus: &mut impl Bus<E>,
+        reset_pin: &mut Output<'_, impl Pin>,
+        events: &CodecEvents,
+    ) -> bool {
+        let publisher = events.immediate_publisher();
+        let timeout = self.config.spi_timeout;
+
+        let Err(fault) = check(bus, timeout).await else {
+            self.save_volumes(bus).await;
+            return true;
+        };
+        publisher.publish_immediate(CodecEvent::HealthFault(fault));
+
+        for attempts in 1..=self.config.max_recovery_attempts {
+            if recover(bus, reset_pin, &self.active, timeout).await.is_ok()
+                && self.restore_volumes(bus).await.is_ok()
+                && check(bus, timeout).await.is_ok()
+            {
+                publisher.publish_immediate(CodecEvent::Recovered { attempts });
+                return true;
+            }
+        }
+
+        publisher.publish_immediate(CodecEvent::RecoveryFailed {
+            attempts: self.config.max_recovery_attempts,
+        });
+        false
+    }
+}
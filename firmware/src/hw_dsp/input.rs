@@ -0,0 +1,391 @@
+use super::dsp::reg_conf_write;
+use super::error::DspError;
+use super::routing::Input;
+use super::volume::{db_to_reg, reg_to_db};
+use cs47l63::{
+    hw_interface::Bus,
+    registers::input_signal_path::{input_signal_path_config, input_signal_path_control},
+    spec::{
+        CS47L63_IN1L_CONTROL1, CS47L63_IN1R_CONTROL1, CS47L63_IN2L_CONTROL1, CS47L63_IN2R_CONTROL1,
+        CS47L63_INPUT2_CONTROL1,
+    },
+};
+
+// Runtime control of the input signal path gain, e.g. to compensate for mic sensitivity differences between boards.
+// Each input has a digital volume, an analog (PGA) volume and a mute bit in its InControl2 register.
+// The digital volume uses the same encoding as the output volume: 0x80 is 0dB in 0.5dB steps from -64dB to +31.5dB.
+// The analog volume is in bits 7:1 of its field in 1dB steps where 0x40 is 0dB and 0x5F is +31dB (the PGA has no
+// attenuation), so 0x80 in the field is 0dB here too.
+// The analog volume only has an effect when the input is in analog mode (the PDM mics on IN1 are digital).
+// New volumes only take effect once the volume update bit in InputControl3 is written, which every function here does.
+//
+// The mode of an input path selects the kind of mic connected to it: a PDM mic (IN1 only) or an analog mic,
+// either single-ended (between the P pin and ground) or differential (between the P and N pins).
+// The oversample rate code is shared between the modes: in digital mode it selects the PDM clock and in analog mode
+// two of the codes select the power mode of the modulator (1.536MHz for mid power and 3.072MHz for high performance).
+// The crate has no registers for the IN2 oversample rate or the input pin selection so they are defined here.
+
+/// Lowest analog (PGA) volume in dB
+pub const MIN_ANALOG_VOLUME_DB: f32 = 0.0;
+
+/// Highest analog (PGA) volume in dB
+pub const MAX_ANALOG_VOLUME_DB: f32 = 31.0;
+
+const PGA_ZERO_DB_REG_VAL: i32 = 0x40;
+
+// INPUT2_CONTROL1 IN2_OSR, in the same position as IN1_OSR
+const OSR_SHIFT: u32 = 16;
+
+// INnx_CONTROL1 INnx_SRC
+const SRC_SHIFT: u32 = 13;
+const SRC_DIFFERENTIAL: u32 = 0b00;
+const SRC_SINGLE_ENDED: u32 = 0b01;
+
+/// The gain settings of an input
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct InputGain {
+    pub digital_db: f32,
+    pub analog_db: f32,
+    pub mute: bool,
+}
+
+/// An input path, each with a left and right channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum InputPath {
+    In1,
+    In2,
+}
+
+impl InputPath {
+    /// the left and right channels of the path
+    pub const fn channels(&self) -> [Input; 2] {
+        match self {
+            Self::In1 => [Input::In1L, Input::In1R],
+            Self::In2 => [Input::In2L, Input::In2R],
+        }
+    }
+}
+
+/// The kind of mic connected to an input path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum InputMode {
+    /// a PDM mic (IN1 only)
+    Digital,
+    /// an analog mic between the P pin and ground
+    AnalogSingleEnded,
+    /// an analog mic between the P and N pins
+    AnalogDifferential,
+}
+
+/// The oversample rate of an input path
+/// the PDM clock in digital mode, in analog mode only `_1536kHz` (mid power) and `_3072kHz` (high performance) are valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum OversampleRate {
+    _384kHz,
+    _768kHz,
+    _1536kHz,
+    _2048kHz,
+    _2458kHz,
+    _3072kHz,
+    _6144kHz,
+}
+
+impl OversampleRate {
+    const fn control(&self) -> input_signal_path_config::OversampleRateControl {
+        use input_signal_path_config::OversampleRateControl;
+        match self {
+            Self::_384kHz => OversampleRateControl::Digital384kHz,
+            Self::_768kHz => OversampleRateControl::Digital768kHz,
+            Self::_1536kHz => OversampleRateControl::Digital1p536MHzOrAnalogMidPower,
+            Self::_2048kHz => OversampleRateControl::Digital2p048MHz,
+            Self::_2458kHz => OversampleRateControl::Digital2p4576MHz,
+            Self::_3072kHz => OversampleRateControl::Digital3p072MHzOrAnalogHighPerformance,
+            Self::_6144kHz => OversampleRateControl::Digital6p144MHz,
+        }
+    }
+
+    const fn is_analog(&self) -> bool {
+        matches!(self, Self::_1536kHz | Self::_3072kHz)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum InputConfigError {
+    /// Only IN1 supports PDM mics
+    DigitalNotSupported(InputPath),
+    /// The analog modulator only supports the mid power and high performance rates
+    OversampleRateNotSupported(OversampleRate),
+}
+
+/// The mode, oversample rate and analog (PGA) gain of an input path
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct InputConfig {
+    path: InputPath,
+    mode: InputMode,
+    oversample_rate: OversampleRate,
+    analog_db: f32,
+}
+
+impl InputConfig {
+    /// the analog gain is rounded to the nearest dB, clamped to the range 0dB to +31dB and is ignored in digital mode
+    /// the oversample rate is 3.072MHz (high performance in analog mode), see `with_oversample_rate`
+    pub fn new(path: InputPath, mode: InputMode, analog_db: f32) -> Result<Self, InputConfigError> {
+        if mode == InputMode::Digital && path != InputPath::In1 {
+            return Err(InputConfigError::DigitalNotSupported(path));
+        }
+
+        Ok(Self {
+            path,
+            mode,
+            oversample_rate: OversampleRate::_3072kHz,
+            analog_db: pga_reg_to_db(pga_db_to_reg(analog_db)),
+        })
+    }
+
+    /// the PDM mic on IN1 used by the nRF5340 Audio DK and yote
+    pub fn pdm_mic() -> Self {
+        Self {
+            path: InputPath::In1,
+            mode: InputMode::Digital,
+            oversample_rate: OversampleRate::_3072kHz,
+            analog_db: 0.0,
+        }
+    }
+
+    /// changes the oversample rate, e.g. to match the clock a PDM mic supports or to save power in analog mode
+    pub fn with_oversample_rate(
+        mut self,
+        oversample_rate: OversampleRate,
+    ) -> Result<Self, InputConfigError> {
+        if self.mode != InputMode::Digital && !oversample_rate.is_analog() {
+            return Err(InputConfigError::OversampleRateNotSupported(
+                oversample_rate,
+            ));
+        }
+
+        self.oversample_rate = oversample_rate;
+        Ok(self)
+    }
+
+    pub fn path(&self) -> InputPath {
+        self.path
+    }
+
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+
+    pub fn oversample_rate(&self) -> OversampleRate {
+        self.oversample_rate
+    }
+
+    /// the analog gain actually applied
+    pub fn analog_db(&self) -> f32 {
+        self.analog_db
+    }
+
+    /// the mode registers of the path (the analog gain is set by `configure`)
+    pub fn registers(&self) -> [[u32; 2]; 3] {
+        let oversample_rate_control = self.oversample_rate.control();
+        let control1 = match self.path {
+            InputPath::In1 => input_signal_path_config::Input1Control1 {
+                oversample_rate_control,
+                mode: match self.mode {
+                    InputMode::Digital => input_signal_path_config::InputPath1Mode::DigitalMode,
+                    InputMode::AnalogSingleEnded | InputMode::AnalogDifferential => {
+                        input_signal_path_config::InputPath1Mode::AnalogInput
+                    }
+                },
+            }
+            .serialize(),
+            InputPath::In2 => [
+                CS47L63_INPUT2_CONTROL1,
+                (oversample_rate_control as u32) << OSR_SHIFT,
+            ],
+        };
+
+        // the pin selection is ignored in digital mode
+        let source = match self.mode {
+            InputMode::AnalogSingleEnded => SRC_SINGLE_ENDED,
+            InputMode::Digital | InputMode::AnalogDifferential => SRC_DIFFERENTIAL,
+        };
+        let [left, right] = self.path.channels();
+
+        [
+            control1,
+            [control1_reg(left), source << SRC_SHIFT],
+            [control1_reg(right), source << SRC_SHIFT],
+        ]
+    }
+}
+
+// the analog volume field of InControl2 for a gain in dB, rounded to the nearest dB and clamped to the PGA range
+fn pga_db_to_reg(volume_db: f32) -> u8 {
+    let db = libm::roundf(volume_db.clamp(MIN_ANALOG_VOLUME_DB, MAX_ANALOG_VOLUME_DB)) as i32;
+    ((db + PGA_ZERO_DB_REG_VAL) << 1) as u8
+}
+
+fn pga_reg_to_db(volume: u8) -> f32 {
+    ((volume >> 1) as i32 - PGA_ZERO_DB_REG_VAL) as f32
+}
+
+const fn control2(
+    input: input_signal_path_control::Reg,
+    mute: bool,
+    digital_volume: u8,
+    analog_volume: u8,
+) -> [u32; 2] {
+    input_signal_path_control::InControl2 {
+        reg: input,
+        mute,
+        digital_volume,
+        analog_volume,
+    }
+    .serialize()
+}
+
+// the register fields are found by serializing with only that field set
+const MUTE_MASK: u32 = control2(input_signal_path_control::Reg::In1Left, true, 0, 0)[1];
+const DIGITAL_VOLUME_MASK: u32 =
+    control2(input_signal_path_control::Reg::In1Left, false, 0xFF, 0)[1];
+const ANALOG_VOLUME_MASK: u32 =
+    control2(input_signal_path_control::Reg::In1Left, false, 0, 0xFF)[1];
+
+const VOLUME_UPDATE: [u32; 2] = input_signal_path_control::InputControl3 {
+    volume_update: true,
+}
+.serialize();
+
+const fn control_reg(input: Input) -> input_signal_path_control::Reg {
+    match input {
+        Input::In1L => input_signal_path_control::Reg::In1Left,
+        Input::In1R => input_signal_path_control::Reg::In1Right,
+        Input::In2L => input_signal_path_control::Reg::In2Left,
+        Input::In2R => input_signal_path_control::Reg::In2Right,
+    }
+}
+
+fn control2_reg(input: Input) -> u32 {
+    control2(control_reg(input), false, 0, 0)[0]
+}
+
+const fn control1_reg(input: Input) -> u32 {
+    match input {
+        Input::In1L => CS47L63_IN1L_CONTROL1,
+        Input::In1R => CS47L63_IN1R_CONTROL1,
+        Input::In2L => CS47L63_IN2L_CONTROL1,
+        Input::In2R => CS47L63_IN2R_CONTROL1,
+    }
+}
+
+fn get_field(val: u32, mask: u32) -> u8 {
+    ((val & mask) >> mask.trailing_zeros()) as u8
+}
+
+fn set_field(val: u32, mask: u32, field: u8) -> u32 {
+    (val & !mask) | (((field as u32) << mask.trailing_zeros()) & mask)
+}
+
+// read-modify-write of the InControl2 register followed by a volume update
+async fn update<E>(
+    bus: &mut impl Bus<E>,
+    input: Input,
+    modify: impl FnOnce(u32) -> u32,
+) -> Result<(), DspError<E>> {
+    let reg = control2_reg(input);
+    let val = modify(bus.read(reg).await.map_err(DspError::Bus)?);
+    bus.write(reg, val).await.map_err(DspError::Bus)?;
+
+    let [reg, update] = VOLUME_UPDATE;
+    bus.write(reg, update).await.map_err(DspError::Bus)
+}
+
+/// returns the current gain settings of the input
+pub async fn get<E>(bus: &mut impl Bus<E>, input: Input) -> Result<InputGain, DspError<E>> {
+    let val = bus.read(control2_reg(input)).await.map_err(DspError::Bus)?;
+    Ok(InputGain {
+        digital_db: reg_to_db(get_field(val, DIGITAL_VOLUME_MASK)),
+        analog_db: pga_reg_to_db(get_field(val, ANALOG_VOLUME_MASK)),
+        mute: val & MUTE_MASK != 0,
+    })
+}
+
+/// sets the digital volume, analog volume and mute of the input in one go (volumes are clamped as below)
+/// returns the settings actually applied
+pub async fn set<E>(
+    bus: &mut impl Bus<E>,
+    input: Input,
+    gain: InputGain,
+) -> Result<InputGain, DspError<E>> {
+    let digital_volume = db_to_reg(gain.digital_db);
+    let analog_volume = pga_db_to_reg(gain.analog_db);
+    update(bus, input, |val| {
+        let val = set_field(val, DIGITAL_VOLUME_MASK, digital_volume);
+        let val = set_field(val, ANALOG_VOLUME_MASK, analog_volume);
+        if gain.mute {
+            val | MUTE_MASK
+        } else {
+            val & !MUTE_MASK
+        }
+    })
+    .await?;
+
+    Ok(InputGain {
+        digital_db: reg_to_db(digital_volume),
+        analog_db: pga_reg_to_db(analog_volume),
+        mute: gain.mute,
+    })
+}
+
+/// sets the digital volume of the input in dB, clamped to the range -64dB to +31.5dB
+/// returns the volume actually set
+pub async fn set_digital_volume<E>(
+    bus: &mut impl Bus<E>,
+    input: Input,
+    volume_db: f32,
+) -> Result<f32, DspError<E>> {
+    let volume = db_to_reg(volume_db);
+    update(bus, input, |val| {
+        set_field(val, DIGITAL_VOLUME_MASK, volume)
+    })
+    .await?;
+    Ok(reg_to_db(volume))
+}
+
+/// sets the analog (PGA) volume of the input in dB, rounded to the nearest dB and clamped to the range 0dB to +31dB
+/// returns the volume actually set
+pub async fn set_analog_volume<E>(
+    bus: &mut impl Bus<E>,
+    input: Input,
+    volume_db: f32,
+) -> Result<f32, DspError<E>> {
+    let volume = pga_db_to_reg(volume_db);
+    update(bus, input, |val| set_field(val, ANALOG_VOLUME_MASK, volume)).await?;
+    Ok(pga_reg_to_db(volume))
+}
+
+/// mutes or unmutes the input without changing its volume
+pub async fn mute<E>(bus: &mut impl Bus<E>, input: Input, mute: bool) -> Result<(), DspError<E>> {
+    update(bus, input, |val| {
+        if mute {
+            val | MUTE_MASK
+        } else {
+            val & !MUTE_MASK
+        }
+    })
+    .await
+}
+
+/// sets the mode of an input path and, in analog mode, the analog gain of both channels
+/// the path should be disabled (see `routing::RoutingGraph::enable_input`) while its mode is changed
+pub async fn configure<E>(bus: &mut impl Bus<E>, config: &InputConfig) -> Result<(), DspError<E>> {
+    reg_conf_write(bus, &config.registers()).await?;
+
+    if config.mode != InputMode::Digital {
+        for input in config.path.channels() {
+            set_analog_volume(bus, input, config.analog_db).await?;
+        }
+    }
+
+    Ok(())
+}
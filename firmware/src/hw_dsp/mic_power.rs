@@ -0,0 +1,234 @@
+use super::dsp::reg_conf_write;
+use super::error::DspError;
+use cs47l63::{hw_interface::Bus, registers::voltage_regulators::voltage};
+
+// Powers the microphone supplies up and down on demand so that idle modes can turn off mics nobody is listening to.
+//
+// LDO2 supplies the MICBIAS1 regulator, which feeds the three bias outputs 1A, 1B and 1C.
+// Each bias output is switched to either the regulator or VDD_A, and a bias on VDD_A does not need LDO2 or the regulator.
+// Supplies are powered up from LDO2 to the bias outputs, with a settle delay after each step, and powered down in the reverse order.
+// Every supply has discharge enabled so that a disabled output is pulled to ground rather than left floating, which fully resets the mic.
+// The bias outputs share the MicBiasCtrl5 register so `MicPower` keeps track of their state and always writes whole registers.
+
+// how long LDO2 takes to reach its output voltage
+const LDO2_SETTLE_MS: u64 = 2;
+
+// how long the bias regulator takes to reach its output voltage in fast rate mode
+const REGULATOR_SETTLE_MS: u64 = 2;
+
+// how long a PDM mic takes to start up after its supply is switched on
+const MIC_SETTLE_MS: u64 = 10;
+
+/// One of the MICBIAS1 outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum MicBias {
+    Bias1A,
+    Bias1B,
+    Bias1C,
+}
+
+/// What a bias output is switched to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BiasSource {
+    /// the MICBIAS1 regulator (2.2V)
+    Regulator,
+    /// the analog supply
+    VddA,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct BiasState {
+    source: BiasSource,
+    enabled: bool,
+}
+
+/// The mic power state of the codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct MicPower {
+    ldo2: bool,
+    // in MicBias order
+    biases: [BiasState; 3],
+}
+
+impl MicPower {
+    /// everything off, the state after a reset (1B is switched to VDD_A for the PDM mic, the others to the regulator)
+    pub const fn new() -> Self {
+        Self {
+            ldo2: false,
+            biases: [
+                BiasState {
+                    source: BiasSource::Regulator,
+                    enabled: false,
+                },
+                BiasState {
+                    source: BiasSource::VddA,
+                    enabled: false,
+                },
+                BiasState {
+                    source: BiasSource::Regulator,
+                    enabled: false,
+                },
+            ],
+        }
+    }
+
+    /// the state after `PDM_MIC_ENABLE_CONFIGURE` has been written (LDO2 and bias 1B on)
+    pub const fn pdm_mic() -> Self {
+        let mut power = Self::new();
+        power.ldo2 = true;
+        power.biases[1].enabled = true;
+        power
+    }
+
+    /// returns true if the bias output is enabled
+    pub fn is_enabled(&self, bias: MicBias) -> bool {
+        self.biases[bias as usize].enabled
+    }
+
+    /// returns true if LDO2 is on (either requested with `set_ldo2` or needed by a bias on the regulator)
+    pub fn is_ldo2_on(&self) -> bool {
+        self.ldo2 || self.regulator_on()
+    }
+
+    fn regulator_on(&self) -> bool {
+        self.biases
+            .iter()
+            .any(|bias| bias.enabled && bias.source == BiasSource::Regulator)
+    }
+
+    /// enables or disables a bias output, powering its supplies up first or down afterwards as needed
+    pub async fn set_enabled<E>(
+        &mut self,
+        bus: &mut impl Bus<E>,
+        bias: MicBias,
+        enabled: bool,
+    ) -> Result<(), DspError<E>> {
+        let mut new = *self;
+        new.biases[bias as usize].enabled = enabled;
+        self.update(bus, new).await
+    }
+
+    /// switches a bias output to another source (an enabled output stays enabled)
+    pub async fn set_source<E>(
+        &mut self,
+        bus: &mut impl Bus<E>,
+        bias: MicBias,
+        source: BiasSource,
+    ) -> Result<(), DspError<E>> {
+        let mut new = *self;
+        new.biases[bias as usize].source = source;
+        self.update(bus, new).await
+    }
+
+    /// turns LDO2 on or off, it stays on while a bias output on the regulator is enabled
+    pub async fn set_ldo2<E>(
+        &mut self,
+        bus: &mut impl Bus<E>,
+        enabled: bool,
+    ) -> Result<(), DspError<E>> {
+        let mut new = *self;
+        new.ldo2 = enabled;
+        self.update(bus, new).await
+    }
+
+    /// disables every bias output and LDO2
+    pub async fn power_down<E>(&mut self, bus: &mut impl Bus<E>) -> Result<(), DspError<E>> {
+        let mut new = *self;
+        new.ldo2 = false;
+        for bias in new.biases.iter_mut() {
+            bias.enabled = false;
+        }
+
+        self.update(bus, new).await
+    }
+
+    /// the LDO2, MicBiasCtrl1 and MicBiasCtrl5 registers for the current state (e.g. to record in a `health::ActiveConfig`)
+    pub fn registers(&self) -> [[u32; 2]; 3] {
+        [self.ldo2_reg(), self.regulator_reg(), self.biases_reg()]
+    }
+
+    fn ldo2_reg(&self) -> [u32; 2] {
+        voltage::Ldo2Crtl1 {
+            output_voltage_select: voltage::Ldo2OutputVoltageSelect::_2p4V,
+            discharge: true,
+            enabled: self.is_ldo2_on(),
+        }
+        .serialize()
+    }
+
+    fn regulator_reg(&self) -> [u32; 2] {
+        voltage::MicBiasCtrl1 {
+            has_external_capacitor: false,
+            level: voltage::MicBias1VoltageLevel::_2p2V,
+            fast_rate: true,
+            discharge: true,
+            bypass_mode: false,
+            enabled: self.regulator_on(),
+        }
+        .serialize()
+    }
+
+    fn biases_reg(&self) -> [u32; 2] {
+        let source = |bias: MicBias| match self.biases[bias as usize].source {
+            BiasSource::Regulator => voltage::MicBias1Source::MicBiasRegulator,
+            BiasSource::VddA => voltage::MicBias1Source::VddA,
+        };
+
+        voltage::MicBiasCtrl5 {
+            mic_bias_1c_source: source(MicBias::Bias1C),
+            mic_bias_1c_discharge: true,
+            mic_bias_1c_enabled: self.is_enabled(MicBias::Bias1C),
+            mic_bias_1b_source: source(MicBias::Bias1B),
+            mic_bias_1b_discharge: true,
+            mic_bias_1b_enabled: self.is_enabled(MicBias::Bias1B),
+            mic_bias_1a_source: source(MicBias::Bias1A),
+            mic_bias_1a_discharge: true,
+            mic_bias_1a_enabled: self.is_enabled(MicBias::Bias1A),
+        }
+        .serialize()
+    }
+
+    // moves the device from the current state to the new one in a safe order
+    async fn update<E>(&mut self, bus: &mut impl Bus<E>, new: Self) -> Result<(), DspError<E>> {
+        // power up from the supply to the mic
+        if new.is_ldo2_on() && !self.is_ldo2_on() {
+            reg_conf_write(bus, &[new.ldo2_reg()]).await?;
+            bus.delay_ms(LDO2_SETTLE_MS).await;
+        }
+
+        if new.regulator_on() && !self.regulator_on() {
+            reg_conf_write(bus, &[new.regulator_reg()]).await?;
+            bus.delay_ms(REGULATOR_SETTLE_MS).await;
+        }
+
+        if new.biases != self.biases {
+            reg_conf_write(bus, &[new.biases_reg()]).await?;
+            let switched_on = new
+                .biases
+                .iter()
+                .zip(self.biases.iter())
+                .any(|(new, old)| new.enabled && *new != *old);
+            if switched_on {
+                bus.delay_ms(MIC_SETTLE_MS).await;
+            }
+        }
+
+        // power down from the mic to the supply
+        if !new.regulator_on() && self.regulator_on() {
+            reg_conf_write(bus, &[new.regulator_reg()]).await?;
+        }
+
+        if !new.is_ldo2_on() && self.is_ldo2_on() {
+            reg_conf_write(bus, &[new.ldo2_reg()]).await?;
+        }
+
+        *self = new;
+        Ok(())
+    }
+}
+
+impl Default for MicPower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,3 +1,25 @@
+pub mod asp;
 pub mod config;
+pub mod drc;
 pub mod dsp;
+pub mod dump;
+pub mod eq;
+pub mod error;
+pub mod events;
+pub mod fll;
+pub mod gpio;
+pub mod halo;
+#[cfg(feature = "nrf")]
+pub mod health;
+pub mod input;
+pub mod mic_power;
+pub mod reg_cache;
+pub mod reg_list;
+pub mod routing;
+#[cfg(feature = "nrf")]
 pub mod shared_bus;
+pub mod sim;
+pub mod tone;
+pub mod verify;
+pub mod volume;
+pub mod wdrc;
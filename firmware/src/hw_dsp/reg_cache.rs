@@ -0,0 +1,145 @@
+use super::verify::ignore_mask;
+use core::ops::Range;
+use cs47l63::registers::reset;
+
+// A write-through shadow of codec register state.
+// Registers are cached when they are written so that read-modify-write operations (e.g. volume control)
+// do not need to read them back over spi. Registers that have never been written are not cached and
+// registers that can change on their own are never cached.
+
+const CAPACITY: usize = 64;
+
+// interrupt status, mask and write-1-to-clear registers
+const IRQ_REGISTERS: Range<u32> = 0x18000..0x19000;
+
+// halo dsp core memory and control registers
+const DSP_REGISTERS_START: u32 = 0x2000000;
+
+const SOFT_RESET_REG: u32 = reset::SoftReset::new().serialize()[0];
+
+pub struct RegCache {
+    entries: [[u32; 2]; CAPACITY],
+    len: usize,
+    // next entry to replace when the cache is full
+    next: usize,
+}
+
+impl RegCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: [[0; 2]; CAPACITY],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    pub fn get(&self, reg: u32) -> Option<u32> {
+        self.entries[..self.len]
+            .iter()
+            .find(|[r, _]| *r == reg)
+            .map(|[_, val]| *val)
+    }
+
+    /// Updates the cache after a register has been written
+    pub fn write(&mut self, reg: u32, val: u32) {
+        if reg == SOFT_RESET_REG {
+            // all registers return to their defaults
+            self.invalidate();
+            return;
+        }
+
+        if is_volatile(reg) {
+            return;
+        }
+
+        // self clearing bits do not stay set on the device
+        let val = val & !ignore_mask(reg);
+
+        if let Some(entry) = self.entries[..self.len].iter_mut().find(|[r, _]| *r == reg) {
+            entry[1] = val;
+        } else if self.len < CAPACITY {
+            self.entries[self.len] = [reg, val];
+            self.len += 1;
+        } else {
+            self.entries[self.next] = [reg, val];
+            self.next = (self.next + 1) % CAPACITY;
+        }
+    }
+
+    /// Updates the cache after a block of 32 bit big endian words has been written to consecutive registers
+    pub fn write_block(&mut self, reg: u32, bytes: &[u8]) {
+        for (i, word) in bytes.chunks_exact(4).enumerate() {
+            let val = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+            self.write(reg + i as u32 * 4, val);
+        }
+    }
+
+    /// Forgets all cached values, call this whenever the device is reset
+    pub fn invalidate(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
+
+impl Default for RegCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Registers that can change without being written and must always be read from the device
+pub fn is_volatile(reg: u32) -> bool {
+    IRQ_REGISTERS.contains(&reg) || reg >= DSP_REGISTERS_START
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_registers_are_cached() {
+        let mut cache = RegCache::new();
+        assert_eq!(cache.get(0x100), None);
+
+        cache.write(0x100, 1);
+        cache.write_block(0x200, &[0, 0, 0, 2, 0, 0, 0, 3]);
+        assert_eq!(cache.get(0x100), Some(1));
+        assert_eq!(cache.get(0x200), Some(2));
+        assert_eq!(cache.get(0x204), Some(3));
+    }
+
+    #[test]
+    fn volatile_registers_are_not_cached() {
+        let mut cache = RegCache::new();
+        cache.write(0x18004, 1);
+        cache.write(DSP_REGISTERS_START, 1);
+        assert_eq!(cache.get(0x18004), None);
+        assert_eq!(cache.get(DSP_REGISTERS_START), None);
+    }
+
+    #[test]
+    fn soft_reset_and_invalidate_forget_everything() {
+        let mut cache = RegCache::new();
+        cache.write(0x100, 1);
+        cache.write(SOFT_RESET_REG, reset::SoftReset::new().serialize()[1]);
+        assert_eq!(cache.get(0x100), None);
+
+        cache.write(0x100, 1);
+        cache.invalidate();
+        assert_eq!(cache.get(0x100), None);
+    }
+
+    #[test]
+    fn oldest_entries_are_replaced_when_full() {
+        // start above the soft reset register which would invalidate the cache
+        const BASE: u32 = 0x100;
+        let mut cache = RegCache::new();
+        for i in 0..CAPACITY as u32 + 1 {
+            cache.write(BASE + i * 4, i);
+        }
+
+        assert_eq!(cache.get(BASE), None);
+        assert_eq!(cache.get(BASE + 4), Some(1));
+        assert_eq!(cache.get(BASE + CAPACITY as u32 * 4), Some(CAPACITY as u32));
+    }
+}
@@ -0,0 +1,60 @@
+use core::ops::Deref;
+
+// A fixed capacity list of serialized register writes ([reg, value] pairs)
+// Configuration generated at runtime is collected in one of these so that it can be
+// sent to the DSP exactly like the const arrays in the `config` module
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegList<const N: usize> {
+    regs: [[u32; 2]; N],
+    len: usize,
+}
+
+/// Returned when a register list runs out of space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct CapacityError;
+
+impl<const N: usize> RegList<N> {
+    pub const fn new() -> Self {
+        Self {
+            regs: [[0; 2]; N],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, reg_val: [u32; 2]) -> Result<(), CapacityError> {
+        if self.len == N {
+            return Err(CapacityError);
+        }
+
+        self.regs[self.len] = reg_val;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn extend(&mut self, config: &[[u32; 2]]) -> Result<(), CapacityError> {
+        for reg_val in config {
+            self.push(*reg_val)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[[u32; 2]] {
+        &self.regs[..self.len]
+    }
+}
+
+impl<const N: usize> Default for RegList<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for RegList<N> {
+    type Target = [[u32; 2]];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
@@ -0,0 +1,571 @@
+use super::reg_list::{CapacityError, RegList};
+use cs47l63::registers::{
+    digital_core::mixers, input_signal_path::input_signal_path_enable,
+    output_signal_path::volume_ctrl,
+};
+
+// A structured description of the CS47L63 signal path.
+// Instead of hand writing mixer registers, describe which sources feed which processing blocks and sinks
+// and let the graph validate the routing and generate the register writes to send to the DSP.
+//
+// For example, Mic -> Drc1 -> Eq1 -> Out1L:
+//
+// let mut graph = RoutingGraph::new();
+// graph.enable_input(Input::In1L);
+// graph.connect(Source::In1L, Sink::Block(Block::Drc1L), 0)?;
+// graph.connect(Source::Block(Block::Drc1L), Sink::Block(Block::Eq1), 0)?;
+// graph.connect(Source::Block(Block::Eq1), Sink::Out1L, 0)?;
+// let regs = graph.registers()?;
+
+/// Every mixer on the CS47L63 has 4 inputs
+pub const MIXER_INPUTS: usize = 4;
+
+/// Maximum number of routes in a graph (every input of every sink in use)
+pub const MAX_ROUTES: usize = NUM_SINKS * MIXER_INPUTS;
+
+/// Maximum number of register writes generated from a graph
+pub const MAX_REGISTERS: usize = MAX_ROUTES + 2;
+
+// mixer input volume is in 1dB steps where 0x40 is 0dB
+const MIXER_VOLUME_0DB: i32 = 0x40;
+const MIXER_VOLUME_MIN_DB: i8 = -32;
+const MIXER_VOLUME_MAX_DB: i8 = 16;
+
+const NUM_BLOCKS: usize = 8;
+const NUM_SINKS: usize = NUM_BLOCKS + 3;
+
+/// Input signal paths that need to be enabled before they can be used as a source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Input {
+    In1L,
+    In1R,
+    In2L,
+    In2R,
+}
+
+/// Processing blocks that have both a mixer (input) and an output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Block {
+    Eq1,
+    Eq2,
+    Eq3,
+    Eq4,
+    Drc1L,
+    Drc1R,
+    Drc2L,
+    Drc2R,
+}
+
+/// Anything that can be selected as the input of a mixer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Source {
+    In1L,
+    In1R,
+    In2L,
+    In2R,
+    Asp1Rx1,
+    Asp1Rx2,
+    /// the internal generators, see the `tone` module
+    ToneGenerator1,
+    ToneGenerator2,
+    NoiseGenerator,
+    Block(Block),
+}
+
+/// Anything that has a mixer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Sink {
+    Out1L,
+    Asp1Tx1,
+    Asp1Tx2,
+    Block(Block),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RoutingError {
+    /// An input signal path was used as a source before it was enabled
+    SourceNotEnabled(Source),
+    /// A processing block was used as a source but nothing feeds into it
+    DanglingBlock(Block),
+    /// A sink already has all of its mixer inputs in use
+    TooManyInputs(Sink),
+    /// The same source is connected to the same sink more than once
+    DuplicateRoute(Source, Sink),
+    /// A processing block feeds back into itself
+    Loop(Block),
+    /// Mixer input gain must be between -32dB and +16dB
+    GainOutOfRange(i8),
+    /// The generated configuration does not fit in the register list
+    Capacity,
+}
+
+impl From<CapacityError> for RoutingError {
+    fn from(_: CapacityError) -> Self {
+        Self::Capacity
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Route {
+    pub source: Source,
+    pub sink: Sink,
+    pub gain_db: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingGraph {
+    inputs: [bool; 4],
+    routes: [Option<Route>; MAX_ROUTES],
+}
+
+impl RoutingGraph {
+    pub const fn new() -> Self {
+        Self {
+            inputs: [false; 4],
+            routes: [None; MAX_ROUTES],
+        }
+    }
+
+    /// Enables an input signal path so that it can be used as a source
+    pub fn enable_input(&mut self, input: Input) -> &mut Self {
+        self.inputs[input as usize] = true;
+        self
+    }
+
+    /// Connects a source to the next free mixer input of a sink with a mixer gain in dB
+    pub fn connect(
+        &mut self,
+        source: Source,
+        sink: Sink,
+        gain_db: i8,
+    ) -> Result<&mut Self, RoutingError> {
+        if !(MIXER_VOLUME_MIN_DB..=MIXER_VOLUME_MAX_DB).contains(&gain_db) {
+            return Err(RoutingError::GainOutOfRange(gain_db));
+        }
+
+        if let Some(input) = source.input() {
+            if !self.inputs[input as usize] {
+                return Err(RoutingError::SourceNotEnabled(source));
+            }
+        }
+
+        if self
+            .routes()
+            .any(|route| route.source == source && route.sink == sink)
+        {
+            return Err(RoutingError::DuplicateRoute(source, sink));
+        }
+
+        if self.routes().filter(|route| route.sink == sink).count() == MIXER_INPUTS {
+            return Err(RoutingError::TooManyInputs(sink));
+        }
+
+        let free = self.routes.iter_mut().find(|route| route.is_none());
+        // cannot fail because every sink has at most MIXER_INPUTS routes
+        if let Some(free) = free {
+            *free = Some(Route {
+                source,
+                sink,
+                gain_db,
+            });
+        }
+
+        Ok(self)
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter().flatten()
+    }
+
+    /// Checks that every processing block used as a source is fed by something and that there are no loops
+    pub fn validate(&self) -> Result<(), RoutingError> {
+        for route in self.routes() {
+            if let Source::Block(block) = route.source {
+                if !self.routes().any(|r| r.sink == Sink::Block(block)) {
+                    return Err(RoutingError::DanglingBlock(block));
+                }
+            }
+        }
+
+        for block in Block::ALL {
+            if self.feeds(block, Sink::Block(block), NUM_BLOCKS) {
+                return Err(RoutingError::Loop(block));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the graph and generates the register writes to configure it.
+    /// All inputs of every sink used are written so that no stale routing is left behind
+    pub fn registers(&self) -> Result<RegList<MAX_REGISTERS>, RoutingError> {
+        self.validate()?;
+        let mut regs = RegList::new();
+
+        if self.inputs.iter().any(|enabled| *enabled) {
+            regs.push(
+                input_signal_path_enable::InputControl {
+                    in2_left_enable: self.inputs[Input::In2L as usize],
+                    in2_right_enable: self.inputs[Input::In2R as usize],
+                    in1_left_enable: self.inputs[Input::In1L as usize],
+                    in1_right_enable: self.inputs[Input::In1R as usize],
+                }
+                .serialize(),
+            )?;
+        }
+
+        for sink in Sink::ALL {
+            if !self.routes().any(|route| route.sink == sink) {
+                continue;
+            }
+
+            let mut sink_routes = self.routes().filter(|route| route.sink == sink);
+            for slot in 0..MIXER_INPUTS {
+                let input_source = match sink_routes.next() {
+                    Some(route) => mixer_input(sink, slot, route.source, route.gain_db),
+                    None => mixer_input_clear(sink, slot),
+                };
+                regs.push(input_source)?;
+            }
+        }
+
+        if self.routes().any(|route| route.sink == Sink::Out1L) {
+            regs.push(volume_ctrl::OutputEnable1 { enabled: true }.serialize())?;
+        }
+
+        Ok(regs)
+    }
+
+    // true if `block` feeds into `sink` directly or through other blocks
+    fn feeds(&self, block: Block, sink: Sink, depth: usize) -> bool {
+        if depth == 0 {
+            return false;
+        }
+
+        self.routes()
+            .filter(|route| route.source == Source::Block(block))
+            .any(|route| match route.sink {
+                s if s == sink => true,
+                Sink::Block(next) => self.feeds(next, sink, depth - 1),
+                _ => false,
+            })
+    }
+}
+
+impl Default for RoutingGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The default routing written by `dsp::default_conf_enable`
+/// MicL + MicR -> Drc1 -> Eq1 -> Out1L
+pub fn pdm_mic_drc_eq() -> Result<RoutingGraph, RoutingError> {
+    let mut graph = RoutingGraph::new();
+    graph
+        .enable_input(Input::In1L)
+        .enable_input(Input::In1R)
+        .connect(Source::In1L, Sink::Block(Block::Drc1L), 0)?
+        .connect(Source::In1R, Sink::Block(Block::Drc1L), 0)?
+        .connect(Source::Block(Block::Drc1L), Sink::Block(Block::Eq1), 0)?
+        .connect(Source::Block(Block::Eq1), Sink::Out1L, 0)?;
+    graph.validate()?;
+    Ok(graph)
+}
+
+impl Block {
+    pub const ALL: [Block; NUM_BLOCKS] = [
+        Block::Eq1,
+        Block::Eq2,
+        Block::Eq3,
+        Block::Eq4,
+        Block::Drc1L,
+        Block::Drc1R,
+        Block::Drc2L,
+        Block::Drc2R,
+    ];
+}
+
+impl Source {
+    fn input(&self) -> Option<Input> {
+        match self {
+            Source::In1L => Some(Input::In1L),
+            Source::In1R => Some(Input::In1R),
+            Source::In2L => Some(Input::In2L),
+            Source::In2R => Some(Input::In2R),
+            _ => None,
+        }
+    }
+
+    fn select(&self) -> mixers::InputSourceSelect {
+        match self {
+            Source::In1L => mixers::InputSourceSelect::In1LSignalPath,
+            Source::In1R => mixers::InputSourceSelect::In1RSignalPath,
+            Source::In2L => mixers::InputSourceSelect::In2LSignalPath,
+            Source::In2R => mixers::InputSourceSelect::In2RSignalPath,
+            Source::Asp1Rx1 => mixers::InputSourceSelect::Asp1Rx1,
+            Source::Asp1Rx2 => mixers::InputSourceSelect::Asp1Rx2,
+            Source::ToneGenerator1 => mixers::InputSourceSelect::ToneGenerator1,
+            Source::ToneGenerator2 => mixers::InputSourceSelect::ToneGenerator2,
+            Source::NoiseGenerator => mixers::InputSourceSelect::NoiseGenerator,
+            Source::Block(Block::Eq1) => mixers::InputSourceSelect::Eq1,
+            Source::Block(Block::Eq2) => mixers::InputSourceSelect::Eq2,
+            Source::Block(Block::Eq3) => mixers::InputSourceSelect::Eq3,
+            Source::Block(Block::Eq4) => mixers::InputSourceSelect::Eq4,
+            Source::Block(Block::Drc1L) => mixers::InputSourceSelect::Drc1Left,
+            Source::Block(Block::Drc1R) => mixers::InputSourceSelect::Drc1Right,
+            Source::Block(Block::Drc2L) => mixers::InputSourceSelect::Drc2Left,
+            Source::Block(Block::Drc2R) => mixers::InputSourceSelect::Drc2Right,
+        }
+    }
+}
+
+impl Sink {
+    pub const ALL: [Sink; NUM_SINKS] = [
+        Sink::Block(Block::Eq1),
+        Sink::Block(Block::Eq2),
+        Sink::Block(Block::Eq3),
+        Sink::Block(Block::Eq4),
+        Sink::Block(Block::Drc1L),
+        Sink::Block(Block::Drc1R),
+        Sink::Block(Block::Drc2L),
+        Sink::Block(Block::Drc2R),
+        Sink::Out1L,
+        Sink::Asp1Tx1,
+        Sink::Asp1Tx2,
+    ];
+
+    /// The mixer input register for one of the 4 inputs of this sink (slot 0 to 3)
+    pub fn mixer_reg(&self, slot: usize) -> mixers::ImportSourceReg {
+        use mixers::ImportSourceReg as R;
+
+        let regs = match self {
+            Sink::Out1L => [
+                R::Out1LInput1,
+                R::Out1LInput2,
+                R::Out1LInput3,
+                R::Out1LInput4,
+            ],
+            Sink::Asp1Tx1 => [
+                R::Asp1Tx1Input1,
+                R::Asp1Tx1Input2,
+                R::Asp1Tx1Input3,
+                R::Asp1Tx1Input4,
+            ],
+            Sink::Asp1Tx2 => [
+                R::Asp1Tx2Input1,
+                R::Asp1Tx2Input2,
+                R::Asp1Tx2Input3,
+                R::Asp1Tx2Input4,
+            ],
+            Sink::Block(Block::Eq1) => [R::Eq1Input1, R::Eq1Input2, R::Eq1Input3, R::Eq1Input4],
+            Sink::Block(Block::Eq2) => [R::Eq2Input1, R::Eq2Input2, R::Eq2Input3, R::Eq2Input4],
+            Sink::Block(Block::Eq3) => [R::Eq3Input1, R::Eq3Input2, R::Eq3Input3, R::Eq3Input4],
+            Sink::Block(Block::Eq4) => [R::Eq4Input1, R::Eq4Input2, R::Eq4Input3, R::Eq4Input4],
+            Sink::Block(Block::Drc1L) => [
+                R::Drc1LInput1,
+                R::Drc1LInput2,
+                R::Drc1LInput3,
+                R::Drc1LInput4,
+            ],
+            Sink::Block(Block::Drc1R) => [
+                R::Drc1RInput1,
+                R::Drc1RInput2,
+                R::Drc1RInput3,
+                R::Drc1RInput4,
+            ],
+            Sink::Block(Block::Drc2L) => [
+                R::Drc2LInput1,
+                R::Drc2LInput2,
+                R::Drc2LInput3,
+                R::Drc2LInput4,
+            ],
+            Sink::Block(Block::Drc2R) => [
+                R::Drc2RInput1,
+                R::Drc2RInput2,
+                R::Drc2RInput3,
+                R::Drc2RInput4,
+            ],
+        };
+
+        let [input1, input2, input3, input4] = regs;
+        match slot {
+            0 => input1,
+            1 => input2,
+            2 => input3,
+            _ => input4,
+        }
+    }
+}
+
+/// Serializes a single mixer input with a gain in dB (-32dB to +16dB)
+pub fn mixer_input(sink: Sink, slot: usize, source: Source, gain_db: i8) -> [u32; 2] {
+    let gain_db = gain_db.clamp(MIXER_VOLUME_MIN_DB, MIXER_VOLUME_MAX_DB);
+    mixers::InputSource {
+        reg: sink.mixer_reg(slot),
+        input_volume: (MIXER_VOLUME_0DB + gain_db as i32) as u8,
+        status_enabled: false,
+        source_select: source.select(),
+    }
+    .serialize()
+}
+
+/// Serializes a mixer input that is not connected to anything
+pub fn mixer_input_clear(sink: Sink, slot: usize) -> [u32; 2] {
+    mixers::InputSource {
+        reg: sink.mixer_reg(slot),
+        input_volume: MIXER_VOLUME_0DB as u8,
+        status_enabled: false,
+        source_select: mixers::InputSourceSelect::Silence,
+    }
+    .serialize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the mixer writes of the old PDM_MIC_ENABLE_CONFIGURE_PASSTHOUGH preset
+    // MicL + MicR -> Drc1 -> Eq1 -> Out1L
+    fn old_preset_input(
+        reg: mixers::ImportSourceReg,
+        source_select: mixers::InputSourceSelect,
+    ) -> [u32; 2] {
+        mixers::InputSource {
+            reg,
+            input_volume: 0x40, // 0db
+            status_enabled: false,
+            source_select,
+        }
+        .serialize()
+    }
+
+    #[test]
+    fn connecting_more_than_mixer_inputs_to_a_sink_is_rejected() {
+        let sources = [
+            Source::Asp1Rx1,
+            Source::Asp1Rx2,
+            Source::ToneGenerator1,
+            Source::ToneGenerator2,
+        ];
+        let mut graph = RoutingGraph::new();
+        for source in sources {
+            graph.connect(source, Sink::Out1L, 0).unwrap();
+        }
+
+        assert_eq!(
+            graph.connect(Source::NoiseGenerator, Sink::Out1L, 0),
+            Err(RoutingError::TooManyInputs(Sink::Out1L))
+        );
+        assert_eq!(graph.routes().count(), MIXER_INPUTS);
+
+        // other sinks still have free inputs
+        graph
+            .connect(Source::NoiseGenerator, Sink::Asp1Tx1, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn connecting_the_same_source_twice_is_rejected() {
+        let mut graph = RoutingGraph::new();
+        graph.connect(Source::Asp1Rx1, Sink::Out1L, 0).unwrap();
+
+        assert_eq!(
+            graph.connect(Source::Asp1Rx1, Sink::Out1L, -6),
+            Err(RoutingError::DuplicateRoute(Source::Asp1Rx1, Sink::Out1L))
+        );
+        assert_eq!(graph.routes().count(), 1);
+
+        // the same source may feed a different sink
+        graph.connect(Source::Asp1Rx1, Sink::Asp1Tx1, 0).unwrap();
+    }
+
+    #[test]
+    fn connect_checks_the_gain_and_the_input() {
+        let mut graph = RoutingGraph::new();
+        assert_eq!(
+            graph.connect(Source::Asp1Rx1, Sink::Out1L, MIXER_VOLUME_MAX_DB + 1),
+            Err(RoutingError::GainOutOfRange(MIXER_VOLUME_MAX_DB + 1))
+        );
+        assert_eq!(
+            graph.connect(Source::Asp1Rx1, Sink::Out1L, MIXER_VOLUME_MIN_DB - 1),
+            Err(RoutingError::GainOutOfRange(MIXER_VOLUME_MIN_DB - 1))
+        );
+        assert_eq!(
+            graph.connect(Source::In1L, Sink::Out1L, 0),
+            Err(RoutingError::SourceNotEnabled(Source::In1L))
+        );
+
+        graph.enable_input(Input::In1L);
+        graph.connect(Source::In1L, Sink::Out1L, 0).unwrap();
+    }
+
+    #[test]
+    fn validate_finds_dangling_blocks_and_loops() {
+        let mut graph = RoutingGraph::new();
+        graph
+            .connect(Source::Block(Block::Eq1), Sink::Out1L, 0)
+            .unwrap();
+        assert_eq!(
+            graph.validate(),
+            Err(RoutingError::DanglingBlock(Block::Eq1))
+        );
+        assert_eq!(
+            graph.registers().err(),
+            Some(RoutingError::DanglingBlock(Block::Eq1))
+        );
+
+        // Eq1 -> Drc1L -> Eq1
+        graph
+            .connect(Source::Block(Block::Drc1L), Sink::Block(Block::Eq1), 0)
+            .unwrap()
+            .connect(Source::Block(Block::Eq1), Sink::Block(Block::Drc1L), 0)
+            .unwrap();
+        assert_eq!(graph.validate(), Err(RoutingError::Loop(Block::Eq1)));
+    }
+
+    #[test]
+    fn pdm_mic_drc_eq_writes_the_old_preset_routing() {
+        use mixers::{ImportSourceReg as R, InputSourceSelect as S};
+
+        let regs = pdm_mic_drc_eq().unwrap().registers().unwrap();
+
+        let preset = [
+            old_preset_input(R::Drc1LInput1, S::In1LSignalPath),
+            old_preset_input(R::Drc1LInput2, S::In1RSignalPath),
+            old_preset_input(R::Eq1Input1, S::Drc1Left),
+            old_preset_input(R::Out1LInput1, S::Eq1),
+        ];
+        for write in preset {
+            assert!(regs.contains(&write), "missing {:x?}", write);
+        }
+
+        let input_control = input_signal_path_enable::InputControl {
+            in2_left_enable: false,
+            in2_right_enable: false,
+            in1_left_enable: true,
+            in1_right_enable: true,
+        }
+        .serialize();
+        let output_enable = volume_ctrl::OutputEnable1 { enabled: true }.serialize();
+        assert_eq!(regs[0], input_control);
+        assert_eq!(regs[regs.len() - 1], output_enable);
+
+        // the remaining inputs of Drc1L, Eq1 and Out1L are silenced
+        let cleared = [
+            old_preset_input(R::Drc1LInput3, S::Silence),
+            old_preset_input(R::Drc1LInput4, S::Silence),
+            old_preset_input(R::Eq1Input2, S::Silence),
+            old_preset_input(R::Eq1Input3, S::Silence),
+            old_preset_input(R::Eq1Input4, S::Silence),
+            old_preset_input(R::Out1LInput2, S::Silence),
+            old_preset_input(R::Out1LInput3, S::Silence),
+            old_preset_input(R::Out1LInput4, S::Silence),
+        ];
+        for write in cleared {
+            assert!(regs.contains(&write), "missing {:x?}", write);
+        }
+
+        assert_eq!(regs.len(), 1 + preset.len() + cleared.len() + 1);
+    }
+}
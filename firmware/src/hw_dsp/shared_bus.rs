@@ -1,70 +1,180 @@
+use super::reg_cache::RegCache;
+use super::verify::DeviceBus;
 use core::ops::DerefMut;
 use cs47l63::hw_interface::Bus;
-use embassy_nrf::peripherals::P0_17;
 use embassy_nrf::{
-    gpio::{AnyPin, Output},
-    peripherals::SERIAL3,
-    spim::{self, Instance, Spim},
+    gpio::{AnyPin, Level, Output, OutputDrive, Pin},
+    interrupt::typelevel::Binding,
+    spim::{self, Frequency, Instance, InterruptHandler, Spim},
+    Peripheral, PeripheralRef,
 };
 use embassy_sync::{
     blocking_mutex::raw::NoopRawMutex,
     mutex::{Mutex, MutexGuard},
 };
 use embassy_time::{Duration, Timer};
+use embedded_hal_async::spi::{ErrorType, Operation, SpiDevice};
 
 const ZEROS: [u8; 4] = 0_u32.to_be_bytes();
 
-pub struct SharedBus {
-    inner: Mutex<NoopRawMutex, SpiBusInner>,
+// An spi bus with a chip select pin for the codec that can be shared between tasks.
+// Generic over the SPIM instance and chip select pin so that it can be used on both the
+// nRF5340 audio dk and the yote board.
+// The bus keeps a write-through shadow of the codec registers written through it so that reads of
+// those registers are served locally without any spi traffic (see `reg_cache`)
+pub struct SharedBus<T: Instance, P: Pin = AnyPin> {
+    inner: Mutex<NoopRawMutex, SpiBusInner<T, P>>,
 }
 
-struct SpiBusInner {
-    pub spi: Spim<'static, SERIAL3>,
-    pub cs: Output<'static, AnyPin>,
+struct SpiBusInner<T: Instance, P: Pin> {
+    pub spi: Spim<'static, T>,
+    pub cs: Output<'static, P>,
+    pub cache: RegCache,
 }
 
-pub struct SpiBusInnerFixed<'a> {
-    pub spi: Spim<'a, SERIAL3>,
-    pub cs: Output<'a, P0_17>, // hardcoded to p0_17 so that it can be dropped easily to save power
+// An spi bus that is owned rather than shared
+// Use a concrete pin type (e.g. P0_17) if the bus needs to be dropped easily to save power
+pub struct SpiBusInnerFixed<'a, T: Instance, P: Pin> {
+    pub spi: Spim<'a, T>,
+    pub cs: Output<'a, P>,
 }
 
-pub struct BusImpl<'a> {
-    bus: MutexGuard<'a, NoopRawMutex, SpiBusInner>,
+pub struct BusImpl<'a, T: Instance, P: Pin> {
+    bus: MutexGuard<'a, NoopRawMutex, SpiBusInner<T, P>>,
 }
 
-impl SharedBus {
-    pub fn new(spi: Spim<'static, SERIAL3>, cs: Output<'static, AnyPin>) -> Self {
+// A power gated bus only holds on to the spi peripheral and pins for the duration of a single transaction.
+// The SPIM is re-created for every transaction and dropped afterwards so that the peripheral is disabled
+// and the spi pins are released (disconnected) between transactions to save power.
+// The chip select pin is kept driven high between transactions so that it never floats low and selects the codec
+pub struct PowerGatedBus<'d, T: Instance, I, SCK: Pin, MISO: Pin, MOSI: Pin, CS: Pin> {
+    spi: PeripheralRef<'d, T>,
+    irq: I,
+    sck: PeripheralRef<'d, SCK>,
+    miso: PeripheralRef<'d, MISO>,
+    mosi: PeripheralRef<'d, MOSI>,
+    cs: Output<'d, CS>,
+    frequency: Frequency,
+}
+
+impl<T: Instance, P: Pin> SharedBus<T, P> {
+    pub fn new(spi: Spim<'static, T>, cs: Output<'static, P>) -> Self {
         Self {
-            inner: Mutex::new(SpiBusInner { spi, cs }),
+            inner: Mutex::new(SpiBusInner {
+                spi,
+                cs,
+                cache: RegCache::new(),
+            }),
         }
     }
 
-    pub async fn borrow(&self) -> BusImpl<'_> {
+    pub async fn borrow(&self) -> BusImpl<'_, T, P> {
         let bus = self.inner.lock().await;
         BusImpl { bus }
     }
+
+    /// Forgets all cached register values, call this after driving the codec reset pin
+    pub async fn invalidate_cache(&self) {
+        self.inner.lock().await.cache.invalidate();
+    }
+}
+
+impl<'a, T: Instance, P: Pin> BusImpl<'a, T, P> {
+    /// Reads a register from the device even if it is cached (the cache is left untouched)
+    pub async fn read_uncached(&mut self, reg: u32) -> Result<u32, spim::Error> {
+        read_inner(self, reg).await
+    }
+
+    /// Forgets all cached register values, call this after driving the codec reset pin
+    pub fn invalidate_cache(&mut self) {
+        self.bus.cache.invalidate();
+    }
+
+    /// A view of this bus where every read goes to the device (writes still update the cache)
+    /// Use this when the value on the device itself matters, e.g. for write-verify or health checks
+    pub fn uncached(&mut self) -> UncachedBus<'_, 'a, T, P> {
+        UncachedBus { bus: self }
+    }
+}
+
+pub struct UncachedBus<'b, 'a, T: Instance, P: Pin> {
+    bus: &'b mut BusImpl<'a, T, P>,
 }
 
-impl<'a> Bus<spim::Error> for SpiBusInnerFixed<'a> {
+impl<'b, 'a, T: Instance, P: Pin> Bus<spim::Error> for UncachedBus<'b, 'a, T, P> {
     async fn read(&mut self, reg: u32) -> Result<u32, spim::Error> {
-        self.cs.set_low();
-        let result = read_inner(&mut self.spi, reg).await;
-        self.cs.set_high();
-        result
+        self.bus.read_uncached(reg).await
     }
 
     async fn write(&mut self, reg: u32, val: u32) -> Result<(), spim::Error> {
-        self.cs.set_low();
-        let result = write_inner(&mut self.spi, reg, val).await;
-        self.cs.set_high();
-        result
+        Bus::write(self.bus, reg, val).await
     }
 
-    async fn write_block(&mut self, reg: u32, val: &[u8]) -> Result<(), spim::Error> {
-        self.cs.set_low();
-        let result = write_block_inner(&mut self.spi, reg, val).await;
-        self.cs.set_high();
-        result
+    async fn write_block(&mut self, reg: u32, bytes: &[u8]) -> Result<(), spim::Error> {
+        self.bus.write_block(reg, bytes).await
+    }
+
+    async fn delay_ms(&self, millis: u64) {
+        self.bus.delay_ms(millis).await;
+    }
+}
+
+impl<'b, 'a, T: Instance, P: Pin> DeviceBus<spim::Error> for UncachedBus<'b, 'a, T, P> {}
+
+impl<'d, T, I, SCK, MISO, MOSI, CS> PowerGatedBus<'d, T, I, SCK, MISO, MOSI, CS>
+where
+    T: Instance,
+    I: Binding<T::Interrupt, InterruptHandler<T>> + Copy + 'd,
+    SCK: Pin,
+    MISO: Pin,
+    MOSI: Pin,
+    CS: Pin,
+{
+    pub fn new(
+        spi: impl Peripheral<P = T> + 'd,
+        irq: I,
+        sck: impl Peripheral<P = SCK> + 'd,
+        miso: impl Peripheral<P = MISO> + 'd,
+        mosi: impl Peripheral<P = MOSI> + 'd,
+        cs: impl Peripheral<P = CS> + 'd,
+        frequency: Frequency,
+    ) -> Self {
+        Self {
+            spi: spi.into_ref(),
+            irq,
+            sck: sck.into_ref(),
+            miso: miso.into_ref(),
+            mosi: mosi.into_ref(),
+            cs: Output::new(cs, Level::High, OutputDrive::Standard),
+            frequency,
+        }
+    }
+}
+
+impl<'a, T: Instance, P: Pin> ErrorType for SpiBusInnerFixed<'a, T, P> {
+    type Error = spim::Error;
+}
+
+impl<'a, T: Instance, P: Pin> SpiDevice for SpiBusInnerFixed<'a, T, P> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), spim::Error> {
+        transaction_inner(&mut self.spi, &mut self.cs, operations).await
+    }
+}
+
+impl<'a, T: Instance, P: Pin> Bus<spim::Error> for SpiBusInnerFixed<'a, T, P> {
+    async fn read(&mut self, reg: u32) -> Result<u32, spim::Error> {
+        read_inner(self, reg).await
+    }
+
+    async fn write(&mut self, reg: u32, val: u32) -> Result<(), spim::Error> {
+        write_inner(self, reg, val).await
+    }
+
+    async fn write_block(&mut self, reg: u32, bytes: &[u8]) -> Result<(), spim::Error> {
+        write_block_inner(self, reg, bytes).await
     }
 
     async fn delay_ms(&self, millis: u64) {
@@ -72,30 +182,109 @@ impl<'a> Bus<spim::Error> for SpiBusInnerFixed<'a> {
     }
 }
 
+impl<'a, T: Instance, P: Pin> DeviceBus<spim::Error> for SpiBusInnerFixed<'a, T, P> {}
+
+impl<'a, T: Instance, P: Pin> ErrorType for BusImpl<'a, T, P> {
+    type Error = spim::Error;
+}
+
+impl<'a, T: Instance, P: Pin> SpiDevice for BusImpl<'a, T, P> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), spim::Error> {
+        let spi_bus = self.bus.deref_mut();
+        transaction_inner(&mut spi_bus.spi, &mut spi_bus.cs, operations).await
+    }
+}
+
 // implementation of the hardware interface to be used by the dsp driver
-impl<'a> Bus<spim::Error> for BusImpl<'a> {
+// reads of registers previously written are served from the cache
+impl<'a, T: Instance, P: Pin> Bus<spim::Error> for BusImpl<'a, T, P> {
     async fn read(&mut self, reg: u32) -> Result<u32, spim::Error> {
-        let spi_bus = self.bus.deref_mut();
-        spi_bus.cs.set_low();
-        let result = read_inner(&mut spi_bus.spi, reg).await;
-        spi_bus.cs.set_high();
-        result
+        match self.bus.cache.get(reg) {
+            Some(val) => Ok(val),
+            None => read_inner(self, reg).await,
+        }
     }
 
     async fn write(&mut self, reg: u32, val: u32) -> Result<(), spim::Error> {
-        let spi_bus = self.bus.deref_mut();
-        spi_bus.cs.set_low();
-        let result = write_inner(&mut spi_bus.spi, reg, val).await;
-        spi_bus.cs.set_high();
-        result
+        write_inner(self, reg, val).await?;
+        self.bus.cache.write(reg, val);
+        Ok(())
     }
 
     async fn write_block(&mut self, reg: u32, bytes: &[u8]) -> Result<(), spim::Error> {
-        let spi_bus = self.bus.deref_mut();
-        spi_bus.cs.set_low();
-        let result = write_block_inner(&mut spi_bus.spi, reg, bytes).await;
-        spi_bus.cs.set_high();
-        result
+        write_block_inner(self, reg, bytes).await?;
+        self.bus.cache.write_block(reg, bytes);
+        Ok(())
+    }
+
+    async fn delay_ms(&self, millis: u64) {
+        Timer::after(Duration::from_millis(millis)).await;
+    }
+}
+
+impl<'d, T, I, SCK, MISO, MOSI, CS> ErrorType for PowerGatedBus<'d, T, I, SCK, MISO, MOSI, CS>
+where
+    T: Instance,
+    SCK: Pin,
+    MISO: Pin,
+    MOSI: Pin,
+    CS: Pin,
+{
+    type Error = spim::Error;
+}
+
+impl<'d, T, I, SCK, MISO, MOSI, CS> SpiDevice for PowerGatedBus<'d, T, I, SCK, MISO, MOSI, CS>
+where
+    T: Instance,
+    I: Binding<T::Interrupt, InterruptHandler<T>> + Copy + 'd,
+    SCK: Pin,
+    MISO: Pin,
+    MOSI: Pin,
+    CS: Pin,
+{
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), spim::Error> {
+        let mut config = spim::Config::default();
+        config.frequency = self.frequency;
+        let mut spi = Spim::new(
+            self.spi.reborrow(),
+            self.irq,
+            self.sck.reborrow(),
+            self.miso.reborrow(),
+            self.mosi.reborrow(),
+            config,
+        );
+        transaction_inner(&mut spi, &mut self.cs, operations).await
+
+        // spi is dropped here which releases the spi pins (cs stays high)
+    }
+}
+
+impl<'d, T, I, SCK, MISO, MOSI, CS> Bus<spim::Error>
+    for PowerGatedBus<'d, T, I, SCK, MISO, MOSI, CS>
+where
+    T: Instance,
+    I: Binding<T::Interrupt, InterruptHandler<T>> + Copy + 'd,
+    SCK: Pin,
+    MISO: Pin,
+    MOSI: Pin,
+    CS: Pin,
+{
+    async fn read(&mut self, reg: u32) -> Result<u32, spim::Error> {
+        read_inner(self, reg).await
+    }
+
+    async fn write(&mut self, reg: u32, val: u32) -> Result<(), spim::Error> {
+        write_inner(self, reg, val).await
+    }
+
+    async fn write_block(&mut self, reg: u32, bytes: &[u8]) -> Result<(), spim::Error> {
+        write_block_inner(self, reg, bytes).await
     }
 
     async fn delay_ms(&self, millis: u64) {
@@ -103,37 +292,98 @@ impl<'a> Bus<spim::Error> for BusImpl<'a> {
     }
 }
 
-async fn read_inner<T: Instance>(spi: &mut Spim<'_, T>, reg: u32) -> Result<u32, spim::Error> {
+impl<'d, T, I, SCK, MISO, MOSI, CS> DeviceBus<spim::Error>
+    for PowerGatedBus<'d, T, I, SCK, MISO, MOSI, CS>
+where
+    T: Instance,
+    I: Binding<T::Interrupt, InterruptHandler<T>> + Copy + 'd,
+    SCK: Pin,
+    MISO: Pin,
+    MOSI: Pin,
+    CS: Pin,
+{
+}
+
+// drives the chip select pin low and raises it again when dropped
+// the transaction future can be dropped part way through (e.g. by a timeout) and the codec must be deselected then too
+struct ChipSelect<'a, 'd, P: Pin> {
+    cs: &'a mut Output<'d, P>,
+}
+
+impl<'a, 'd, P: Pin> ChipSelect<'a, 'd, P> {
+    fn select(cs: &'a mut Output<'d, P>) -> Self {
+        cs.set_low();
+        Self { cs }
+    }
+}
+
+impl<'a, 'd, P: Pin> Drop for ChipSelect<'a, 'd, P> {
+    fn drop(&mut self) {
+        self.cs.set_high();
+    }
+}
+
+// runs all operations with the chip select pin held low
+async fn transaction_inner<T: Instance, P: Pin>(
+    spi: &mut Spim<'_, T>,
+    cs: &mut Output<'_, P>,
+    operations: &mut [Operation<'_, u8>],
+) -> Result<(), spim::Error> {
+    let _selected = ChipSelect::select(cs);
+    let mut result = Ok(());
+    for operation in operations {
+        result = match operation {
+            Operation::Read(read) => spi.read(read).await,
+            Operation::Write(write) => spi.write(write).await,
+            Operation::Transfer(read, write) => spi.transfer(read, write).await,
+            Operation::TransferInPlace(words) => spi.transfer_in_place(words).await,
+            Operation::DelayNs(nanos) => {
+                Timer::after(Duration::from_nanos(*nanos as u64)).await;
+                Ok(())
+            }
+        };
+
+        if result.is_err() {
+            break;
+        }
+    }
+    result
+}
+
+async fn read_inner<D: SpiDevice>(spi: &mut D, reg: u32) -> Result<u32, D::Error> {
     // set the write bit
     let reg_with_write_bit = reg | 0x80000000;
     let write = reg_with_write_bit.to_be_bytes();
     let mut read: [u8; 4] = [0; 4];
 
-    spi.write(&write).await?; // write register
-    spi.write(&ZEROS).await?; // padding
-    spi.read(&mut read).await?; // read register result
+    spi.transaction(&mut [
+        Operation::Write(&write),   // write register
+        Operation::Write(&ZEROS),   // padding
+        Operation::Read(&mut read), // read register result
+    ])
+    .await?;
 
     Ok(u32::from_be_bytes(read))
 }
 
-async fn write_block_inner<T: Instance>(
-    spi: &mut Spim<'_, T>,
+async fn write_block_inner<D: SpiDevice>(
+    spi: &mut D,
     reg: u32,
     bytes: &[u8],
-) -> Result<(), spim::Error> {
-    spi.write(&reg.to_be_bytes()).await?; // write register
-    spi.write(&ZEROS).await?; // padding
-    spi.write(bytes).await?; // write bytes
-    Ok(())
+) -> Result<(), D::Error> {
+    spi.transaction(&mut [
+        Operation::Write(&reg.to_be_bytes()), // write register
+        Operation::Write(&ZEROS),             // padding
+        Operation::Write(bytes),              // write bytes
+    ])
+    .await
 }
 
-async fn write_inner<T: Instance>(
-    spi: &mut Spim<'_, T>,
-    reg: u32,
-    val: u32,
-) -> Result<(), spim::Error> {
-    spi.write(&reg.to_be_bytes()).await?; // write register
-    spi.write(&ZEROS).await?; // padding
-    spi.write(&val.to_be_bytes()).await?; // write value
-    Ok(())
+async fn write_inner<D: SpiDevice>(spi: &mut D, reg: u32, val: u32) -> Result<(), D::Error> {
+    spi.transaction(&mut [
+        Operation::Write(&reg.to_be_bytes()), // write register
+        Operation::Write(&ZEROS),             // padding
+        Operation::Write(&val.to_be_bytes()), // write value
+    ])
+    .await
 }
@@ -0,0 +1,573 @@
+use super::dsp::DEVICE_ID;
+use super::fll::FLL1_LOCK_STS1_MASK;
+use super::verify::{ignore_mask, DeviceBus};
+use cs47l63::{
+    hw_interface::Bus,
+    registers::{
+        input_signal_path::input_signal_path_control, output_signal_path::volume_ctrl, reset,
+    },
+    spec::{CS47L63_BOOT_DONE_EINT1_MASK, CS47L63_DEVID, CS47L63_IRQ1_EINT_2, CS47L63_IRQ1_STS_6},
+};
+
+// An in-memory simulation of the CS47L63 that implements the same `Bus` trait as the real spi bus.
+// It allows the functions in the `dsp` and `volume` modules and the `config` arrays to be exercised on the host without the physical codec.
+//
+// The simulation is a register file rather than a model of the chip:
+// - registers not written yet read back their reset default (or 0 if there is no default in the table below)
+// - self clearing bits (e.g. volume update) are cleared after a write, the same masks used for write-verify are applied
+// - a write to the soft reset register restores the reset defaults
+// - all transactions are logged and faults can be injected
+//
+// It builds without the `nrf` feature so the tests at the bottom of this file run on the host.
+
+/// Register values after a reset that differ from 0
+pub const RESET_DEFAULTS: [[u32; 2]; 6] = [
+    [CS47L63_DEVID, DEVICE_ID],
+    // the codec has booted
+    [CS47L63_IRQ1_EINT_2, CS47L63_BOOT_DONE_EINT1_MASK],
+    // the FLL is always locked
+    [CS47L63_IRQ1_STS_6, FLL1_LOCK_STS1_MASK],
+    // output at 0dB
+    volume_ctrl::Out1LVolume1 {
+        mute: false,
+        volume: 0x80,
+        update: false,
+    }
+    .serialize(),
+    // inputs at 0dB
+    input_signal_path_control::InControl2 {
+        reg: input_signal_path_control::Reg::In1Left,
+        mute: false,
+        digital_volume: 0x80,
+        analog_volume: 0x80,
+    }
+    .serialize(),
+    input_signal_path_control::InControl2 {
+        reg: input_signal_path_control::Reg::In1Right,
+        mute: false,
+        digital_volume: 0x80,
+        analog_volume: 0x80,
+    }
+    .serialize(),
+];
+
+const SOFT_RESET_REG: u32 = reset::SoftReset::new().serialize()[0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SimError {
+    /// A fault injected with `fail_after` or `fail_on_reg`
+    InjectedFault { reg: u32 },
+    /// More distinct registers were written than the register file can hold
+    RegisterFileFull { reg: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Transaction {
+    Read { reg: u32, val: u32 },
+    Write { reg: u32, val: u32 },
+    WriteBlock { reg: u32, len: usize },
+}
+
+/// Bits that always read back as 1 (stuck_high) or 0 (stuck_low) no matter what is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct StuckBits {
+    pub reg: u32,
+    pub stuck_high: u32,
+    pub stuck_low: u32,
+}
+
+/// Simulated codec with room for R registers that differ from their reset default and a log of the last L transactions (L > 0)
+pub struct SimCodec<const R: usize = 256, const L: usize = 64> {
+    regs: [[u32; 2]; R],
+    num_regs: usize,
+    log: [Option<Transaction>; L],
+    num_transactions: usize,
+    fail_after: Option<usize>,
+    fail_on_reg: Option<u32>,
+    stuck_bits: [Option<StuckBits>; 4],
+}
+
+impl<const R: usize, const L: usize> SimCodec<R, L> {
+    // the transaction log is a ring buffer indexed modulo L
+    const LOG_NOT_EMPTY: () = assert!(
+        L > 0,
+        "the transaction log needs room for at least one entry"
+    );
+
+    pub const fn new() -> Self {
+        let () = Self::LOG_NOT_EMPTY;
+        Self {
+            regs: [[0; 2]; R],
+            num_regs: 0,
+            log: [None; L],
+            num_transactions: 0,
+            fail_after: None,
+            fail_on_reg: None,
+            stuck_bits: [None; 4],
+        }
+    }
+
+    /// Restores all registers to their reset defaults (the transaction log and faults are kept)
+    pub fn reset(&mut self) {
+        self.num_regs = 0;
+    }
+
+    /// Reads a register without going through the bus (not logged and not subject to faults)
+    pub fn get(&self, reg: u32) -> u32 {
+        let val = match self.regs[..self.num_regs].iter().find(|[r, _]| *r == reg) {
+            Some([_, val]) => *val,
+            None => RESET_DEFAULTS
+                .iter()
+                .find(|[r, _]| *r == reg)
+                .map(|[_, val]| *val)
+                .unwrap_or(0),
+        };
+
+        match self
+            .stuck_bits
+            .iter()
+            .flatten()
+            .find(|stuck| stuck.reg == reg)
+        {
+            Some(stuck) => (val | stuck.stuck_high) & !stuck.stuck_low,
+            None => val,
+        }
+    }
+
+    /// Writes a register without going through the bus (e.g. to seed status registers the driver polls)
+    pub fn set(&mut self, reg: u32, val: u32) -> Result<(), SimError> {
+        if let Some(entry) = self.regs[..self.num_regs]
+            .iter_mut()
+            .find(|[r, _]| *r == reg)
+        {
+            entry[1] = val;
+            return Ok(());
+        }
+
+        if self.num_regs == R {
+            return Err(SimError::RegisterFileFull { reg });
+        }
+
+        self.regs[self.num_regs] = [reg, val];
+        self.num_regs += 1;
+        Ok(())
+    }
+
+    /// Every transaction after the first `count` transactions fails
+    pub fn fail_after(&mut self, count: usize) {
+        self.fail_after = Some(count);
+    }
+
+    /// Every transaction that accesses this register fails
+    pub fn fail_on_reg(&mut self, reg: u32) {
+        self.fail_on_reg = Some(reg);
+    }
+
+    /// Adds stuck bits to a register (up to 4 registers)
+    pub fn stick_bits(&mut self, stuck_bits: StuckBits) {
+        if let Some(free) = self.stuck_bits.iter_mut().find(|s| s.is_none()) {
+            *free = Some(stuck_bits);
+        }
+    }
+
+    /// Removes all injected faults
+    pub fn clear_faults(&mut self) {
+        self.fail_after = None;
+        self.fail_on_reg = None;
+        self.stuck_bits = [None; 4];
+    }
+
+    /// Total number of transactions since the simulation was created
+    pub fn num_transactions(&self) -> usize {
+        self.num_transactions
+    }
+
+    /// The last L transactions, oldest first
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        let start = self.num_transactions % L;
+        self.log[start..]
+            .iter()
+            .chain(self.log[..start].iter())
+            .flatten()
+    }
+
+    fn record(&mut self, transaction: Transaction) -> Result<(), SimError> {
+        let reg = match transaction {
+            Transaction::Read { reg, .. } => reg,
+            Transaction::Write { reg, .. } => reg,
+            Transaction::WriteBlock { reg, .. } => reg,
+        };
+
+        let failed = self.fail_on_reg == Some(reg)
+            || matches!(self.fail_after, Some(count) if self.num_transactions >= count);
+
+        self.log[self.num_transactions % L] = Some(transaction);
+        self.num_transactions += 1;
+
+        if failed {
+            Err(SimError::InjectedFault { reg })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_reg(&mut self, reg: u32, val: u32) -> Result<(), SimError> {
+        if reg == SOFT_RESET_REG {
+            self.reset();
+            return Ok(());
+        }
+
+        // self clearing bits never read back as set
+        self.set(reg, val & !ignore_mask(reg))
+    }
+}
+
+impl<const R: usize, const L: usize> Default for SimCodec<R, L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const R: usize, const L: usize> Bus<SimError> for SimCodec<R, L> {
+    async fn read(&mut self, reg: u32) -> Result<u32, SimError> {
+        let val = self.get(reg);
+        self.record(Transaction::Read { reg, val })?;
+        Ok(val)
+    }
+
+    async fn write(&mut self, reg: u32, val: u32) -> Result<(), SimError> {
+        self.record(Transaction::Write { reg, val })?;
+        self.write_reg(reg, val)
+    }
+
+    // registers are 32 bits wide and the address auto increments by 4 for every word written
+    async fn write_block(&mut self, reg: u32, bytes: &[u8]) -> Result<(), SimError> {
+        self.record(Transaction::WriteBlock {
+            reg,
+            len: bytes.len(),
+        })?;
+
+        for (i, word) in bytes.chunks_exact(4).enumerate() {
+            let val = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+            self.write_reg(reg + i as u32 * 4, val)?;
+        }
+
+        Ok(())
+    }
+
+    // time does not pass in the simulation
+    async fn delay_ms(&self, _millis: u64) {}
+}
+
+// there is no cache, every read goes to the register file
+impl<const R: usize, const L: usize> DeviceBus<SimError> for SimCodec<R, L> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::asp::AspConfig;
+    use crate::hw_dsp::config::{ASP1_ENABLE, FLL_ENABLE, SOFT_RESET};
+    use crate::hw_dsp::dsp::{self, reg_conf_write};
+    use crate::hw_dsp::error::DspError;
+    use crate::hw_dsp::verify::{reg_conf_write_verified, VerifyReport};
+    use crate::hw_dsp::volume::{self, OutputChannel};
+    use crate::sample_rate::{SampleRate, SampleRateConfig};
+    use cs47l63::registers::digital_core::mixers;
+    use embassy_futures::block_on;
+
+    const OUT1L_MUTED: [u32; 2] = volume_ctrl::Out1LVolume1 {
+        mute: true,
+        volume: 0x40,
+        update: false,
+    }
+    .serialize();
+
+    // the nRF master clock used for 48kHz (32MHz / 21 with 32 clocks per frame)
+    fn rate_48k() -> SampleRateConfig {
+        SampleRateConfig::with_clock(SampleRate::_48kHz, 1_523_809, 32).unwrap()
+    }
+
+    #[test]
+    fn unwritten_registers_read_reset_defaults() {
+        let mut sim: SimCodec = SimCodec::new();
+        assert_eq!(block_on(sim.read(CS47L63_DEVID)), Ok(DEVICE_ID));
+        assert_eq!(block_on(sim.read(0x1234)), Ok(0));
+    }
+
+    #[test]
+    fn soft_reset_restores_defaults() {
+        let mut sim: SimCodec = SimCodec::new();
+        block_on(reg_conf_write(&mut sim, &[OUT1L_MUTED])).unwrap();
+        assert_eq!(sim.get(OUT1L_MUTED[0]), OUT1L_MUTED[1]);
+
+        block_on(reg_conf_write(&mut sim, &SOFT_RESET)).unwrap();
+        assert_eq!(sim.get(OUT1L_MUTED[0]), RESET_DEFAULTS[3][1]);
+    }
+
+    #[test]
+    fn reset_keeps_log_and_faults() {
+        let mut sim: SimCodec = SimCodec::new();
+        sim.set(0x100, 1).unwrap();
+        sim.fail_on_reg(0x100);
+        block_on(sim.read(0x104)).unwrap();
+
+        sim.reset();
+        assert_eq!(sim.get(0x100), 0);
+        assert_eq!(sim.num_transactions(), 1);
+        assert!(block_on(sim.read(0x100)).is_err());
+    }
+
+    #[test]
+    fn self_clearing_bits_do_not_read_back() {
+        let mut sim: SimCodec = SimCodec::new();
+        let update = volume_ctrl::Out1LVolume1 {
+            mute: false,
+            volume: 0x90,
+            update: true,
+        }
+        .serialize();
+        block_on(reg_conf_write(&mut sim, &[update])).unwrap();
+        assert_eq!(
+            sim.get(update[0]),
+            volume_ctrl::Out1LVolume1 {
+                mute: false,
+                volume: 0x90,
+                update: false,
+            }
+            .serialize()[1]
+        );
+    }
+
+    #[test]
+    fn consecutive_registers_are_coalesced_into_block_writes() {
+        let mut sim: SimCodec = SimCodec::new();
+        let config = [[0x100, 1], [0x104, 2], [0x108, 3], [0x200, 4], [0x300, 5]];
+        block_on(reg_conf_write(&mut sim, &config)).unwrap();
+
+        let transactions: Vec<_> = sim.transactions().copied().collect();
+        assert_eq!(
+            transactions,
+            [
+                Transaction::WriteBlock {
+                    reg: 0x100,
+                    len: 12
+                },
+                Transaction::Write { reg: 0x200, val: 4 },
+                Transaction::Write { reg: 0x300, val: 5 },
+            ]
+        );
+        for [reg, val] in config {
+            assert_eq!(sim.get(reg), val);
+        }
+    }
+
+    #[test]
+    fn long_runs_are_split_into_blocks_of_16() {
+        let mut sim: SimCodec = SimCodec::new();
+        let config: Vec<[u32; 2]> = (0..20).map(|i| [0x1000 + i * 4, i]).collect();
+        block_on(reg_conf_write(&mut sim, &config)).unwrap();
+
+        let transactions: Vec<_> = sim.transactions().copied().collect();
+        assert_eq!(
+            transactions,
+            [
+                Transaction::WriteBlock {
+                    reg: 0x1000,
+                    len: 64
+                },
+                Transaction::WriteBlock {
+                    reg: 0x1040,
+                    len: 16
+                },
+            ]
+        );
+        assert_eq!(sim.get(0x1000 + 19 * 4), 19);
+    }
+
+    #[test]
+    fn default_conf_enable_configures_routing_and_fll() {
+        let mut sim: SimCodec = SimCodec::new();
+        let rate = rate_48k();
+        block_on(dsp::default_conf_enable(&mut sim, &rate, &AspConfig::new())).unwrap();
+
+        for [reg, val] in rate.codec_registers() {
+            assert_eq!(sim.get(reg), val);
+        }
+        for [reg, val] in ASP1_ENABLE {
+            assert_eq!(sim.get(reg), val);
+        }
+        for [reg, val] in rate.fll().registers() {
+            assert_eq!(sim.get(reg), val);
+        }
+        assert_eq!(
+            sim.get(FLL_ENABLE[0][0]),
+            FLL_ENABLE[0][1] & !ignore_mask(FLL_ENABLE[0][0])
+        );
+
+        let eq_to_out1l = mixers::InputSource {
+            reg: mixers::ImportSourceReg::Out1LInput1,
+            input_volume: 0x40,
+            status_enabled: false,
+            source_select: mixers::InputSourceSelect::Eq1,
+        }
+        .serialize();
+        assert_eq!(sim.get(eq_to_out1l[0]), eq_to_out1l[1]);
+    }
+
+    #[test]
+    fn default_conf_enable_verified_reads_back_everything() {
+        let mut sim: SimCodec = SimCodec::new();
+        let report = block_on(dsp::default_conf_enable_verified(
+            &mut sim,
+            &rate_48k(),
+            &AspConfig::new(),
+        ))
+        .unwrap();
+        assert!(report.is_ok(), "{:?}", report.mismatches());
+    }
+
+    #[test]
+    fn default_conf_enable_fails_if_the_fll_does_not_lock() {
+        let mut sim: SimCodec = SimCodec::new();
+        sim.stick_bits(StuckBits {
+            reg: CS47L63_IRQ1_STS_6,
+            stuck_high: 0,
+            stuck_low: FLL1_LOCK_STS1_MASK,
+        });
+        assert_eq!(
+            block_on(dsp::default_conf_enable(
+                &mut sim,
+                &rate_48k(),
+                &AspConfig::new()
+            )),
+            Err(DspError::FllLockFailed)
+        );
+    }
+
+    #[test]
+    fn volume_adjust_changes_the_configured_output() {
+        let mut sim: SimCodec = SimCodec::new();
+        block_on(dsp::default_conf_enable(
+            &mut sim,
+            &rate_48k(),
+            &AspConfig::new(),
+        ))
+        .unwrap();
+        let configured = volume_ctrl::Out1LVolume1::from(sim.get(OUT1L_MUTED[0]));
+
+        let level = block_on(volume::adjust(&mut sim, OutputChannel::Out1L, -3.0, None)).unwrap();
+        let adjusted = volume_ctrl::Out1LVolume1::from(sim.get(OUT1L_MUTED[0]));
+        assert_eq!(adjusted.volume, configured.volume - 6);
+        assert_eq!(adjusted.mute, configured.mute);
+        assert_eq!(level, volume::reg_to_db(adjusted.volume));
+    }
+
+    #[test]
+    fn volume_adjust_leaves_the_output_alone_on_a_bus_error() {
+        let mut sim: SimCodec = SimCodec::new();
+        sim.fail_on_reg(OUT1L_MUTED[0]);
+        assert_eq!(
+            block_on(volume::adjust(&mut sim, OutputChannel::Out1L, -3.0, None)),
+            Err(DspError::Bus(SimError::InjectedFault {
+                reg: OUT1L_MUTED[0]
+            }))
+        );
+        assert_eq!(sim.get(OUT1L_MUTED[0]), RESET_DEFAULTS[3][1]);
+    }
+
+    #[test]
+    fn volume_mute_reads_modifies_and_writes_the_output() {
+        let mut sim: SimCodec = SimCodec::new();
+        block_on(volume::mute(&mut sim, OutputChannel::Out1L, true)).unwrap();
+
+        let transactions: Vec<_> = sim.transactions().copied().collect();
+        let muted = volume_ctrl::Out1LVolume1 {
+            mute: true,
+            volume: 0x80,
+            update: true,
+        }
+        .serialize();
+        assert_eq!(
+            transactions,
+            [
+                Transaction::Read {
+                    reg: OUT1L_MUTED[0],
+                    val: RESET_DEFAULTS[3][1]
+                },
+                Transaction::Write {
+                    reg: muted[0],
+                    val: muted[1]
+                },
+            ]
+        );
+
+        block_on(volume::mute(&mut sim, OutputChannel::Out1L, false)).unwrap();
+        assert_eq!(sim.get(OUT1L_MUTED[0]), RESET_DEFAULTS[3][1]);
+    }
+
+    #[test]
+    fn fail_after_fails_every_later_transaction() {
+        let mut sim: SimCodec = SimCodec::new();
+        sim.fail_after(1);
+        assert_eq!(block_on(sim.write(0x100, 1)), Ok(()));
+        assert_eq!(
+            block_on(reg_conf_write(&mut sim, &[[0x200, 2]])),
+            Err(DspError::Bus(SimError::InjectedFault { reg: 0x200 }))
+        );
+        assert!(block_on(sim.read(0x100)).is_err());
+
+        sim.clear_faults();
+        assert_eq!(block_on(sim.read(0x100)), Ok(1));
+    }
+
+    #[test]
+    fn stuck_bits_are_reported_by_verified_writes() {
+        let mut sim: SimCodec = SimCodec::new();
+        sim.stick_bits(StuckBits {
+            reg: OUT1L_MUTED[0],
+            stuck_high: 0,
+            stuck_low: OUT1L_MUTED[1],
+        });
+
+        let mut report: VerifyReport = VerifyReport::new();
+        block_on(reg_conf_write_verified(
+            &mut sim,
+            &[OUT1L_MUTED],
+            &mut report,
+        ))
+        .unwrap();
+        assert_eq!(report.count(), 1);
+        assert_eq!(report.mismatches()[0].reg, OUT1L_MUTED[0]);
+        assert_eq!(report.mismatches()[0].actual, 0);
+    }
+
+    #[test]
+    fn transaction_log_keeps_the_last_entries_in_order() {
+        let mut sim: SimCodec<16, 2> = SimCodec::new();
+        for reg in [0x100, 0x104, 0x108] {
+            block_on(sim.read(reg)).unwrap();
+        }
+
+        let transactions: Vec<_> = sim.transactions().copied().collect();
+        assert_eq!(
+            transactions,
+            [
+                Transaction::Read { reg: 0x104, val: 0 },
+                Transaction::Read { reg: 0x108, val: 0 },
+            ]
+        );
+        assert_eq!(sim.num_transactions(), 3);
+    }
+
+    #[test]
+    fn full_register_file_is_an_error() {
+        let mut sim: SimCodec<2, 4> = SimCodec::new();
+        sim.set(0x100, 1).unwrap();
+        sim.set(0x104, 1).unwrap();
+        assert_eq!(sim.set(0x100, 2), Ok(()));
+        assert_eq!(
+            sim.set(0x108, 1),
+            Err(SimError::RegisterFileFull { reg: 0x108 })
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use super::dsp::reg_conf_write;
+use super::error::DspError;
+use super::fll;
+use super::routing::{mixer_input, mixer_input_clear, Sink, Source, MIXER_INPUTS};
+use crate::sample_rate::SampleRateConfig;
+use cs47l63::{
+    hw_interface::Bus,
+    registers::output_signal_path::volume_ctrl,
+    spec::{CS47L63_COMFORT_NOISE_GENERATOR, CS47L63_TONE_GENERATOR1},
+};
+
+// Prompts and listening checks played by the codec's own tone and noise generators rather than streamed over I2S.
+//
+// The two tone generators output a square wave at 1/48 of their sample rate and the noise generator outputs white noise at a selectable level.
+// A mixer only accepts inputs at its own sample rate so the generators always run at sample rate 1 like the rest of the signal path,
+// which makes the tone frequency follow the codec sample rate (1kHz at 48kHz), see `tone_frequency_hz`.
+// The level of a tone is set by its mixer gain.
+//
+// `start` routes a generator into the last Out1L mixer input, leaving the other three for the signal path.
+// Writing a `RoutingGraph` to Out1L afterwards clears that input again.
+// The generators need SYSCLK but not ASP1. SYSCLK comes from FLL1 which locks to the nRF master clock (MCLK1), so
+// to stop the nRF I2S while a generator plays call `start_without_i2s`, which holds the FLL at its current output,
+// and `stop_without_i2s` after the I2S has been started again. The tone pitch drifts slightly while the FLL is held.
+// `health::check` skips the FLL lock test while the FLL is held, so a `HealthMonitor` does not reset the codec
+// (and stop the prompt) in the meantime. A codec that stops responding or loses its configuration is still recovered,
+// but the recovery restarts the FLL and keeps failing until the I2S runs again.
+//
+// The cs47l63 crate has no masks for the generator fields so they are defined here.
+
+/// The Out1L mixer input used by the generators
+pub const OUT1L_SLOT: usize = MIXER_INPUTS - 1;
+
+/// Noise level range in dB
+pub const NOISE_MIN_DB: f32 = -114.0;
+pub const NOISE_MAX_DB: f32 = 0.0;
+
+const NOISE_STEP_DB: f32 = 6.0;
+
+// the tone period in samples
+const TONE_PERIOD_SAMPLES: u32 = 48;
+
+// TONE_GENERATOR1
+const TONE1_EN_MASK: u32 = 0x0000_0001;
+const TONE2_EN_MASK: u32 = 0x0000_0002;
+const TONE_RATE_MASK: u32 = 0x0000_7800;
+
+// COMFORT_NOISE_GENERATOR
+const NOISE_GEN_GAIN_MASK: u32 = 0x0000_001F;
+const NOISE_GEN_EN_MASK: u32 = 0x0000_0020;
+const NOISE_GEN_RATE_MASK: u32 = 0x0000_7800;
+
+/// One of the internal signal generators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Generator {
+    Tone1,
+    Tone2,
+    Noise,
+}
+
+impl Generator {
+    /// the mixer source of the generator
+    pub const fn source(&self) -> Source {
+        match self {
+            Self::Tone1 => Source::ToneGenerator1,
+            Self::Tone2 => Source::ToneGenerator2,
+            Self::Noise => Source::NoiseGenerator,
+        }
+    }
+
+    // the register and enable bit of the generator
+    const fn enable(&self) -> (u32, u32) {
+        match self {
+            Self::Tone1 => (CS47L63_TONE_GENERATOR1, TONE1_EN_MASK),
+            Self::Tone2 => (CS47L63_TONE_GENERATOR1, TONE2_EN_MASK),
+            Self::Noise => (CS47L63_COMFORT_NOISE_GENERATOR, NOISE_GEN_EN_MASK),
+        }
+    }
+
+    // the sample rate select field of the generator
+    const fn rate_mask(&self) -> u32 {
+        match self {
+            Self::Tone1 | Self::Tone2 => TONE_RATE_MASK,
+            Self::Noise => NOISE_GEN_RATE_MASK,
+        }
+    }
+}
+
+/// the frequency of the tone generators at the sample rate given
+pub fn tone_frequency_hz(rate: &SampleRateConfig) -> f32 {
+    rate.sample_rate() as f32 / TONE_PERIOD_SAMPLES as f32
+}
+
+/// enables or disables a generator (running at sample rate 1)
+pub async fn set_enabled<E>(
+    bus: &mut impl Bus<E>,
+    generator: Generator,
+    enabled: bool,
+) -> Result<(), DspError<E>> {
+    let (reg, bit) = generator.enable();
+    let val = bus.read(reg).await.map_err(DspError::Bus)?;
+
+    // a rate select of zero is sample rate 1
+    let val = val & !generator.rate_mask();
+    let val = if enabled { val | bit } else { val & !bit };
+    bus.write(reg, val).await.map_err(DspError::Bus)
+}
+
+/// sets the noise level in dB, clamped to the range -114dB to 0dB and rounded to the nearest 6dB step
+/// returns the level actually set
+pub async fn set_noise_level<E>(bus: &mut impl Bus<E>, level_db: f32) -> Result<f32, DspError<E>> {
+    let steps =
+        libm::roundf((level_db.clamp(NOISE_MIN_DB, NOISE_MAX_DB) - NOISE_MIN_DB) / NOISE_STEP_DB);
+    let mask = NOISE_GEN_GAIN_MASK;
+
+    let val = bus
+        .read(CS47L63_COMFORT_NOISE_GENERATOR)
+        .await
+        .map_err(DspError::Bus)?;
+    let val = (val & !mask) | (((steps as u32) << mask.trailing_zeros()) & mask);
+    bus.write(CS47L63_COMFORT_NOISE_GENERATOR, val)
+        .await
+        .map_err(DspError::Bus)?;
+
+    Ok(NOISE_MIN_DB + steps * NOISE_STEP_DB)
+}
+
+/// enables a generator and mixes it into Out1L with a mixer gain in dB (-32dB to +16dB)
+pub async fn start<E>(
+    bus: &mut impl Bus<E>,
+    generator: Generator,
+    gain_db: i8,
+) -> Result<(), DspError<E>> {
+    set_enabled(bus, generator, true).await?;
+    reg_conf_write(
+        bus,
+        &[
+            mixer_input(Sink::Out1L, OUT1L_SLOT, generator.source(), gain_db),
+            volume_ctrl::OutputEnable1 { enabled: true }.serialize(),
+        ],
+    )
+    .await
+}
+
+/// removes a generator from Out1L and disables it (the output stays enabled)
+pub async fn stop<E>(bus: &mut impl Bus<E>, generator: Generator) -> Result<(), DspError<E>> {
+    reg_conf_write(bus, &[mixer_input_clear(Sink::Out1L, OUT1L_SLOT)]).await?;
+    set_enabled(bus, generator, false).await
+}
+
+/// holds the FLL and then starts a generator so that it keeps playing once the nRF I2S is stopped
+pub async fn start_without_i2s<E>(
+    bus: &mut impl Bus<E>,
+    generator: Generator,
+    gain_db: i8,
+) -> Result<(), DspError<E>> {
+    fll::hold(bus, true).await?;
+    start(bus, generator, gain_db).await
+}
+
+/// stops a generator started with `start_without_i2s` and locks the FLL to the master clock again
+/// the nRF I2S has to be running again before this is called
+pub async fn stop_without_i2s<E>(
+    bus: &mut impl Bus<E>,
+    generator: Generator,
+) -> Result<(), DspError<E>> {
+    stop(bus, generator).await?;
+    fll::hold(bus, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::sim::SimCodec;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn playing_without_i2s_holds_the_fll_until_stopped() {
+        let mut sim: SimCodec = SimCodec::new();
+        block_on(start_without_i2s(&mut sim, Generator::Tone1, 0)).unwrap();
+        assert_eq!(block_on(fll::is_held(&mut sim)), Ok(true));
+        assert_ne!(sim.get(CS47L63_TONE_GENERATOR1) & TONE1_EN_MASK, 0);
+
+        block_on(stop_without_i2s(&mut sim, Generator::Tone1)).unwrap();
+        assert_eq!(block_on(fll::is_held(&mut sim)), Ok(false));
+        assert_eq!(sim.get(CS47L63_TONE_GENERATOR1) & TONE1_EN_MASK, 0);
+    }
+}
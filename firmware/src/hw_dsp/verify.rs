@@ -0,0 +1,229 @@
+use super::error::DspError;
+use super::routing::{mixer_input_clear, Sink, MIXER_INPUTS};
+use cs47l63::{
+    hw_interface::Bus,
+    registers::{
+        clocking_sample_rates::fll, digital_core::mixers,
+        input_signal_path::input_signal_path_control, output_signal_path::volume_ctrl, reset,
+    },
+};
+
+// Write-verify support for codec configuration.
+// Each register is read back after writing it and compared to the value written.
+// Some bits are self clearing (e.g. volume update) or read-only status bits, and some registers are write only (soft reset),
+// so these bits are masked out before comparing.
+// Reading back from a register cache would always match, so the verified writes only take a `DeviceBus`.
+
+/// A bus where every read goes to the device rather than a register cache
+/// A borrowed `SharedBus` is not one of these, use `BusImpl::uncached` to get one
+pub trait DeviceBus<E>: Bus<E> {}
+
+/// Bits of a register that should be ignored when reading it back after a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct IgnoreMask {
+    pub reg: u32,
+    pub mask: u32,
+}
+
+impl IgnoreMask {
+    // the mask is the difference between two serialized values of the same register
+    const fn from_diff(set: [u32; 2], clear: [u32; 2]) -> Self {
+        Self {
+            reg: set[0],
+            mask: set[1] ^ clear[1],
+        }
+    }
+}
+
+/// Registers with self clearing bits
+pub const SELF_CLEARING: [IgnoreMask; 4] = [
+    // soft reset is write only
+    IgnoreMask {
+        reg: reset::SoftReset::new().serialize()[0],
+        mask: u32::MAX,
+    },
+    // output volume update
+    IgnoreMask::from_diff(
+        volume_ctrl::Out1LVolume1 {
+            mute: false,
+            volume: 0,
+            update: true,
+        }
+        .serialize(),
+        volume_ctrl::Out1LVolume1 {
+            mute: false,
+            volume: 0,
+            update: false,
+        }
+        .serialize(),
+    ),
+    // input volume update
+    IgnoreMask::from_diff(
+        input_signal_path_control::InputControl3 {
+            volume_update: true,
+        }
+        .serialize(),
+        input_signal_path_control::InputControl3 {
+            volume_update: false,
+        }
+        .serialize(),
+    ),
+    // fll control update
+    IgnoreMask::from_diff(
+        fll::Fll1Control1 {
+            control_update: true,
+            hold: false,
+            enabled: false,
+        }
+        .serialize(),
+        fll::Fll1Control1 {
+            control_update: false,
+            hold: false,
+            enabled: false,
+        }
+        .serialize(),
+    ),
+];
+
+// the mixer status bit is read-only and set by the DSP when the selected source is active
+const MIXER_STATUS_MASK: u32 = mixers::InputSource {
+    reg: mixers::ImportSourceReg::Out1LInput1,
+    input_volume: 0,
+    status_enabled: true,
+    source_select: mixers::InputSourceSelect::Silence,
+}
+.serialize()[1]
+    ^ mixers::InputSource {
+        reg: mixers::ImportSourceReg::Out1LInput1,
+        input_volume: 0,
+        status_enabled: false,
+        source_select: mixers::InputSourceSelect::Silence,
+    }
+    .serialize()[1];
+
+/// Returns the bits of a register that cannot be verified by reading them back
+pub fn ignore_mask(reg: u32) -> u32 {
+    if let Some(entry) = SELF_CLEARING.iter().find(|entry| entry.reg == reg) {
+        return entry.mask;
+    }
+
+    if is_mixer_input(reg) {
+        return MIXER_STATUS_MASK;
+    }
+
+    0
+}
+
+fn is_mixer_input(reg: u32) -> bool {
+    Sink::ALL
+        .iter()
+        .any(|sink| (0..MIXER_INPUTS).any(|slot| mixer_input_clear(*sink, slot)[0] == reg))
+}
+
+/// A register that did not read back the value that was written to it (ignored bits are cleared)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Mismatch {
+    pub reg: u32,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+/// A list of mismatches found while verifying writes
+/// Only the first N mismatches are kept but all of them are counted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport<const N: usize = 8> {
+    mismatches: [Mismatch; N],
+    count: usize,
+}
+
+impl<const N: usize> VerifyReport<N> {
+    pub const fn new() -> Self {
+        Self {
+            mismatches: [Mismatch {
+                reg: 0,
+                expected: 0,
+                actual: 0,
+            }; N],
+            count: 0,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Total number of mismatches found (may be more than the number kept)
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mismatches(&self) -> &[Mismatch] {
+        &self.mismatches[..self.count.min(N)]
+    }
+
+    /// Returns `DspError::VerificationMismatch` if any mismatches were found
+    pub fn result<E>(&self) -> Result<(), DspError<E>> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(DspError::VerificationMismatch { count: self.count })
+        }
+    }
+
+    pub fn record(&mut self, mismatch: Mismatch) {
+        if self.count < N {
+            self.mismatches[self.count] = mismatch;
+        }
+
+        self.count += 1;
+    }
+}
+
+impl<const N: usize> Default for VerifyReport<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> defmt::Format for VerifyReport<N> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "VerifyReport {{ count: {}, mismatches: {} }}",
+            self.count,
+            self.mismatches()
+        )
+    }
+}
+
+/// Compares a value read back from the DSP with the value written, ignoring self clearing and status bits
+pub fn check(reg: u32, expected: u32, actual: u32) -> Option<Mismatch> {
+    let keep = !ignore_mask(reg);
+    if expected & keep == actual & keep {
+        None
+    } else {
+        Some(Mismatch {
+            reg,
+            expected: expected & keep,
+            actual: actual & keep,
+        })
+    }
+}
+
+/// Writes each register and reads it back, recording any mismatches in the report
+/// Mismatches do not stop the configuration from being written
+pub async fn reg_conf_write_verified<E, const N: usize>(
+    bus: &mut impl DeviceBus<E>,
+    config: &[[u32; 2]],
+    report: &mut VerifyReport<N>,
+) -> Result<(), DspError<E>> {
+    for [reg, value] in config {
+        bus.write(*reg, *value).await.map_err(DspError::Bus)?;
+        let actual = bus.read(*reg).await.map_err(DspError::Bus)?;
+        if let Some(mismatch) = check(*reg, *value, actual) {
+            report.record(mismatch);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use super::error::DspError;
+use cs47l63::{hw_interface::Bus, registers::output_signal_path::volume_ctrl};
+
+// Output volume control in absolute dB.
+// The output volume register is in 0.5dB steps where 0x00 is -64dB, 0x80 is 0dB and 0xBF is +31.5dB (0xC0 and above are reserved).
+// Requests outside of this range are clamped and the level actually written is returned.
+// Large changes can optionally be ramped in register sized steps to avoid zipper noise.
+// Note that a ramp holds on to the bus for the duration of the ramp.
+
+/// Lowest output volume in dB
+pub const MIN_VOLUME_DB: f32 = -64.0;
+
+/// Highest output volume in dB
+pub const MAX_VOLUME_DB: f32 = 31.5;
+
+const ZERO_DB_REG_VAL: i32 = 0x80;
+
+/// An output with a volume control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum OutputChannel {
+    /// analog output 1 (headphone / line out)
+    Out1L,
+}
+
+/// The volume fields common to all outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct OutputVolume {
+    pub mute: bool,
+    pub volume: u8,
+}
+
+/// converts a level in dB to a volume register value, clamped to the valid range and rounded to the nearest 0.5dB
+pub fn db_to_reg(volume_db: f32) -> u8 {
+    let half_steps = libm::roundf(volume_db.clamp(MIN_VOLUME_DB, MAX_VOLUME_DB) * 2.0) as i32;
+    (half_steps + ZERO_DB_REG_VAL) as u8
+}
+
+/// converts a volume register value to a level in dB
+pub fn reg_to_db(volume: u8) -> f32 {
+    (volume as i32 - ZERO_DB_REG_VAL) as f32 / 2.0
+}
+
+async fn read_volume<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+) -> Result<OutputVolume, DspError<E>> {
+    match output {
+        OutputChannel::Out1L => {
+            let out_vol: volume_ctrl::Out1LVolume1 = bus
+                .read(volume_ctrl::Out1LVolume1::REG)
+                .await
+                .map_err(DspError::Bus)?
+                .into();
+            Ok(OutputVolume {
+                mute: out_vol.mute,
+                volume: out_vol.volume,
+            })
+        }
+    }
+}
+
+// the update bit applies the new volume immediately
+async fn write_volume<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+    out_vol: OutputVolume,
+) -> Result<(), DspError<E>> {
+    let [reg, val] = match output {
+        OutputChannel::Out1L => volume_ctrl::Out1LVolume1 {
+            mute: out_vol.mute,
+            volume: out_vol.volume,
+            update: true,
+        }
+        .serialize(),
+    };
+
+    bus.write(reg, val).await.map_err(DspError::Bus)
+}
+
+/// returns the current volume and mute state of the output, e.g. to `restore` it after the codec was reset
+pub async fn save<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+) -> Result<OutputVolume, DspError<E>> {
+    read_volume(bus, output).await
+}
+
+/// writes back a volume and mute state returned by `save` in one step
+pub async fn restore<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+    saved: OutputVolume,
+) -> Result<(), DspError<E>> {
+    write_volume(bus, output, saved).await
+}
+
+/// returns the current volume of the output in dB
+pub async fn get<E>(bus: &mut impl Bus<E>, output: OutputChannel) -> Result<f32, DspError<E>> {
+    let out_vol = read_volume(bus, output).await?;
+    Ok(reg_to_db(out_vol.volume))
+}
+
+/// sets the output volume in dB, clamped to the range MIN_VOLUME_DB to MAX_VOLUME_DB
+/// with a ramp time the volume is changed in 0.5dB steps spread over the ramp (at most one step per millisecond)
+/// returns the volume actually set
+pub async fn set<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+    volume_db: f32,
+    ramp_ms: Option<u32>,
+) -> Result<f32, DspError<E>> {
+    let out_vol = read_volume(bus, output).await?;
+    set_from(bus, output, out_vol, db_to_reg(volume_db), ramp_ms).await
+}
+
+/// changes the output volume by a number of dB relative to the current volume
+/// the result is clamped in the same way as `set` and the volume actually set is returned
+pub async fn adjust<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+    adjustment_db: f32,
+    ramp_ms: Option<u32>,
+) -> Result<f32, DspError<E>> {
+    let out_vol = read_volume(bus, output).await?;
+    let target = db_to_reg(reg_to_db(out_vol.volume) + adjustment_db);
+    set_from(bus, output, out_vol, target, ramp_ms).await
+}
+
+async fn set_from<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+    mut out_vol: OutputVolume,
+    target: u8,
+    ramp_ms: Option<u32>,
+) -> Result<f32, DspError<E>> {
+    // there is no point in ramping a muted output
+    if let (Some(ramp_ms), false) = (ramp_ms, out_vol.mute) {
+        let start = out_vol.volume as i32;
+        let distance = target as i32 - start;
+        let num_steps = distance.unsigned_abs().min(ramp_ms);
+
+        // the last step is written below
+        for step in 1..num_steps {
+            out_vol.volume = (start + distance * step as i32 / num_steps as i32) as u8;
+            write_volume(bus, output, out_vol).await?;
+            bus.delay_ms((ramp_ms / num_steps) as u64).await;
+        }
+    }
+
+    out_vol.volume = target;
+    write_volume(bus, output, out_vol).await?;
+    Ok(reg_to_db(target))
+}
+
+/// mutes or unmutes the output without changing its volume
+pub async fn mute<E>(
+    bus: &mut impl Bus<E>,
+    output: OutputChannel,
+    mute: bool,
+) -> Result<(), DspError<E>> {
+    let mut out_vol = read_volume(bus, output).await?;
+    out_vol.mute = mute;
+    write_volume(bus, output, out_vol).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::sim::{SimCodec, Transaction};
+    use embassy_futures::block_on;
+
+    const OUT1L_REG: u32 = volume_ctrl::Out1LVolume1::REG;
+
+    fn out1l(sim: &SimCodec) -> OutputVolume {
+        let out_vol: volume_ctrl::Out1LVolume1 = sim.get(OUT1L_REG).into();
+        OutputVolume {
+            mute: out_vol.mute,
+            volume: out_vol.volume,
+        }
+    }
+
+    // the volumes written to Out1L, oldest first
+    fn written_volumes(sim: &SimCodec) -> Vec<u8> {
+        sim.transactions()
+            .filter_map(|transaction| match transaction {
+                Transaction::Write { reg, val } if *reg == OUT1L_REG => {
+                    let out_vol: volume_ctrl::Out1LVolume1 = (*val).into();
+                    Some(out_vol.volume)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn levels_are_clamped_and_rounded() {
+        assert_eq!(db_to_reg(0.0), 0x80);
+        assert_eq!(db_to_reg(-10.3), 0x6B);
+        assert_eq!(db_to_reg(MAX_VOLUME_DB), 0xBF);
+        assert_eq!(db_to_reg(MIN_VOLUME_DB), 0x00);
+        assert_eq!(db_to_reg(1e30), 0xBF);
+        assert_eq!(db_to_reg(f32::INFINITY), 0xBF);
+        assert_eq!(db_to_reg(-1e30), 0x00);
+        assert_eq!(db_to_reg(f32::NEG_INFINITY), 0x00);
+        assert_eq!(reg_to_db(0x6B), -10.5);
+    }
+
+    #[test]
+    fn set_clamps_at_both_ends_and_reports_the_register_level() {
+        let mut sim: SimCodec = SimCodec::new();
+        let out = OutputChannel::Out1L;
+
+        assert_eq!(
+            block_on(set(&mut sim, out, 1000.0, None)),
+            Ok(MAX_VOLUME_DB)
+        );
+        assert_eq!(out1l(&sim).volume, 0xBF);
+
+        assert_eq!(
+            block_on(set(&mut sim, out, -1000.0, None)),
+            Ok(MIN_VOLUME_DB)
+        );
+        assert_eq!(out1l(&sim).volume, 0x00);
+
+        let level = block_on(set(&mut sim, out, -10.3, None)).unwrap();
+        assert_eq!(level, reg_to_db(out1l(&sim).volume));
+        assert_eq!(block_on(get(&mut sim, out)), Ok(level));
+    }
+
+    #[test]
+    fn adjust_is_relative_to_the_current_level_and_clamped() {
+        let mut sim: SimCodec = SimCodec::new();
+        let out = OutputChannel::Out1L;
+
+        // the output resets to 0dB
+        assert_eq!(block_on(adjust(&mut sim, out, -6.0, None)), Ok(-6.0));
+        assert_eq!(block_on(adjust(&mut sim, out, 1.5, None)), Ok(-4.5));
+        assert_eq!(out1l(&sim).volume, 0x77);
+
+        assert_eq!(
+            block_on(adjust(&mut sim, out, 100.0, None)),
+            Ok(MAX_VOLUME_DB)
+        );
+        assert_eq!(
+            block_on(adjust(&mut sim, out, f32::NEG_INFINITY, None)),
+            Ok(MIN_VOLUME_DB)
+        );
+    }
+
+    #[test]
+    fn ramp_writes_every_step_up_to_the_target() {
+        let mut sim: SimCodec = SimCodec::new();
+        let out = OutputChannel::Out1L;
+
+        // 4 half dB steps down from 0dB within 10ms
+        assert_eq!(block_on(set(&mut sim, out, -2.0, Some(10))), Ok(-2.0));
+        assert_eq!(written_volumes(&sim), [0x7F, 0x7E, 0x7D, 0x7C]);
+
+        // a short ramp takes at most one step per millisecond
+        let mut sim: SimCodec = SimCodec::new();
+        assert_eq!(block_on(set(&mut sim, out, 5.0, Some(2))), Ok(5.0));
+        assert_eq!(written_volumes(&sim), [0x85, 0x8A]);
+    }
+
+    #[test]
+    fn mute_keeps_the_volume() {
+        let mut sim: SimCodec = SimCodec::new();
+        let out = OutputChannel::Out1L;
+        block_on(set(&mut sim, out, -6.0, None)).unwrap();
+
+        block_on(mute(&mut sim, out, true)).unwrap();
+        assert_eq!(
+            out1l(&sim),
+            OutputVolume {
+                mute: true,
+                volume: 0x74
+            }
+        );
+        assert_eq!(block_on(get(&mut sim, out)), Ok(-6.0));
+
+        // a muted output is not ramped and stays muted
+        let writes = written_volumes(&sim).len();
+        assert_eq!(block_on(set(&mut sim, out, 0.0, Some(10))), Ok(0.0));
+        assert_eq!(written_volumes(&sim).len(), writes + 1);
+        assert!(out1l(&sim).mute);
+
+        block_on(mute(&mut sim, out, false)).unwrap();
+        assert_eq!(
+            out1l(&sim),
+            OutputVolume {
+                mute: false,
+                volume: 0x80
+            }
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use super::drc::{Drc, DrcError, DrcSettings, Ratio, KNEE1_OUTPUT_MIN_DB};
+use super::dsp::reg_conf_write;
+use super::eq::{self, Band, EqBlock, EqConfig, EqError};
+use super::error::DspError;
+use super::reg_list::{CapacityError, RegList};
+use super::routing::{self, Block, Input, RoutingError, RoutingGraph, Sink, Source};
+use crate::sample_rate::SampleRateConfig;
+use cs47l63::{hw_interface::Bus, registers::digital_core::equalizer};
+
+// A four band wide dynamic range compressor (WDRC) built entirely from the codec's hardware blocks.
+//
+// Each band splits the mic signal with one of the equalizers and compresses it with one DRC channel
+// before the four bands are mixed back together into Out1L:
+//
+// Mic -> EQ1 -> DRC1 left  -> Out1L input 1
+// Mic -> EQ2 -> DRC1 right -> Out1L input 2
+// Mic -> EQ3 -> DRC2 left  -> Out1L input 3
+// Mic -> EQ4 -> DRC2 right -> Out1L input 4
+//
+// Hardware limitations:
+// - the equalizers can only attenuate out of band frequencies by 12dB per shelf so the band splitting is gentle
+//   (a peak band adds some extra attenuation further away from each crossover)
+// - both channels of a DRC share their settings so bands 1 and 2 must have the same knee and ratio, as must bands 3 and 4
+//
+// The equalizer coefficients depend on the sample rate so the registers are generated for the `SampleRateConfig` the codec runs at.
+// `apply` writes them after `dsp::default_conf_enable` and replaces the default routing, equalizer and compressor settings.
+
+/// Number of bands
+pub const NUM_BANDS: usize = 4;
+
+/// Enough for the routing, the four equalizers, their enables and the two DRCs
+pub const MAX_REGISTERS: usize = routing::MAX_REGISTERS + NUM_BANDS * eq::MAX_REGISTERS + 1 + 2 * 4;
+
+// out of band attenuation of the equalizer bands used for band splitting
+const STOP_BAND_GAIN_DB: i8 = eq::BAND_GAIN_MIN_DB;
+
+// the blocks used by each band
+const BANDS: [(EqBlock, Block, Block); NUM_BANDS] = [
+    (EqBlock::Eq1, Block::Eq1, Block::Drc1L),
+    (EqBlock::Eq2, Block::Eq2, Block::Drc1R),
+    (EqBlock::Eq3, Block::Eq3, Block::Drc2L),
+    (EqBlock::Eq4, Block::Eq4, Block::Drc2R),
+];
+
+/// Compression of a single band
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct WdrcBand {
+    /// compression threshold (0dB to -45dB)
+    pub knee_db: f32,
+    /// ratio above the threshold
+    pub ratio: Ratio,
+    /// gain of the band when mixed back into Out1L (-32dB to +16dB)
+    pub gain_db: i8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Wdrc {
+    /// the frequencies between bands 1 and 2, 2 and 3, and 3 and 4 in ascending order
+    pub crossover_hz: [f32; NUM_BANDS - 1],
+    pub bands: [WdrcBand; NUM_BANDS],
+    /// mix In1R with In1L (otherwise only In1L is used)
+    pub stereo_mics: bool,
+}
+
+impl Default for Wdrc {
+    /// mild compression of the low bands and stronger compression of the high bands
+    fn default() -> Self {
+        let low = WdrcBand {
+            knee_db: -30.0,
+            ratio: Ratio::R2,
+            gain_db: 0,
+        };
+        let high = WdrcBand {
+            knee_db: -36.0,
+            ratio: Ratio::R4,
+            gain_db: 0,
+        };
+
+        Self {
+            crossover_hz: [500.0, 1500.0, 4000.0],
+            bands: [low, low, high, high],
+            stereo_mics: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum WdrcError {
+    /// Crossover frequencies must be ascending and below half the sample rate
+    CrossoverOrder,
+    /// Two bands on the same DRC have different knees or ratios (bands 1 and 2 or bands 3 and 4)
+    SharedDrcMismatch {
+        band: usize,
+    },
+    Routing(RoutingError),
+    Eq(EqError),
+    Drc(DrcError),
+    /// The generated configuration does not fit in the register list
+    Capacity,
+}
+
+impl From<RoutingError> for WdrcError {
+    fn from(e: RoutingError) -> Self {
+        Self::Routing(e)
+    }
+}
+
+impl From<EqError> for WdrcError {
+    fn from(e: EqError) -> Self {
+        Self::Eq(e)
+    }
+}
+
+impl From<DrcError> for WdrcError {
+    fn from(e: DrcError) -> Self {
+        Self::Drc(e)
+    }
+}
+
+impl From<CapacityError> for WdrcError {
+    fn from(_: CapacityError) -> Self {
+        Self::Capacity
+    }
+}
+
+impl Wdrc {
+    /// Checks the crossovers, that bands sharing a DRC have the same compression and that every block can be configured
+    /// (by generating the registers, use `registers` directly when they are needed)
+    pub fn validate(&self, rate: &SampleRateConfig) -> Result<(), WdrcError> {
+        self.registers(rate).map(|_| ())
+    }
+
+    /// Validates the description and generates the register writes for the routing, equalizers and compressors
+    /// the equalizers are designed for the actual sample rate of `rate`
+    pub fn registers(&self, rate: &SampleRateConfig) -> Result<RegList<MAX_REGISTERS>, WdrcError> {
+        let nyquist = rate.sample_rate() as f32 / 2.0;
+        let mut previous = 0.0;
+        for crossover in self.crossover_hz {
+            if crossover <= previous || crossover >= nyquist {
+                return Err(WdrcError::CrossoverOrder);
+            }
+
+            previous = crossover;
+        }
+
+        for band in [1, 3] {
+            let (a, b) = (self.bands[band - 1], self.bands[band]);
+            if a.knee_db != b.knee_db || a.ratio != b.ratio {
+                return Err(WdrcError::SharedDrcMismatch { band: band + 1 });
+            }
+        }
+
+        // the routing, equalizer and compressor settings are validated as they are serialized
+        let mut regs = RegList::new();
+        regs.extend(&self.graph()?.registers()?)?;
+
+        for (band, &(eq_block, _, _)) in BANDS.iter().enumerate() {
+            regs.extend(
+                &self
+                    .eq_config(band, rate.sample_rate())?
+                    .registers(eq_block),
+            )?;
+        }
+
+        regs.push(
+            equalizer::EqControl1 {
+                eq1_enabled: true,
+                eq2_enabled: true,
+                eq3_enabled: true,
+                eq4_enabled: true,
+            }
+            .serialize(),
+        )?;
+
+        regs.extend(&self.drc_settings(0).registers(Drc::Drc1, true, true)?)?;
+        regs.extend(&self.drc_settings(2).registers(Drc::Drc2, true, true)?)?;
+        Ok(regs)
+    }
+
+    fn graph(&self) -> Result<RoutingGraph, RoutingError> {
+        let mut graph = RoutingGraph::new();
+        graph.enable_input(Input::In1L);
+        if self.stereo_mics {
+            graph.enable_input(Input::In1R);
+        }
+
+        for (&(_, eq_block, drc_block), band) in BANDS.iter().zip(self.bands.iter()) {
+            graph.connect(Source::In1L, Sink::Block(eq_block), 0)?;
+            if self.stereo_mics {
+                graph.connect(Source::In1R, Sink::Block(eq_block), 0)?;
+            }
+
+            graph
+                .connect(Source::Block(eq_block), Sink::Block(drc_block), 0)?
+                .connect(Source::Block(drc_block), Sink::Out1L, band.gain_db)?;
+        }
+
+        Ok(graph)
+    }
+
+    // attenuates everything outside of the band
+    fn eq_config(&self, band: usize, sample_rate_hz: u32) -> Result<EqConfig, EqError> {
+        let low = band.checked_sub(1).map(|i| self.crossover_hz[i]);
+        let high = self.crossover_hz.get(band).copied();
+        let nyquist = sample_rate_hz as f32 / 2.0;
+        let mut config = EqConfig::new();
+
+        if let Some(low) = low {
+            // low shelf at the lower crossover with extra attenuation an octave below it
+            config
+                .frequency(Band::Band1, low, 0.0, sample_rate_hz)?
+                .gain(Band::Band1, STOP_BAND_GAIN_DB)?
+                .frequency(Band::Band2, low / 2.0, low / 2.0, sample_rate_hz)?
+                .gain(Band::Band2, STOP_BAND_GAIN_DB)?;
+        }
+
+        if let Some(high) = high {
+            // high shelf at the upper crossover with extra attenuation an octave above it (if there is room)
+            config
+                .frequency(Band::Band5, high, 0.0, sample_rate_hz)?
+                .gain(Band::Band5, STOP_BAND_GAIN_DB)?;
+
+            if high * 2.0 < nyquist {
+                config
+                    .frequency(Band::Band4, high * 2.0, high, sample_rate_hz)?
+                    .gain(Band::Band4, STOP_BAND_GAIN_DB)?;
+            }
+        }
+
+        // band 3 is not used for band splitting
+        config.enable(Band::Band3, false);
+        Ok(config)
+    }
+
+    // unity gain at the knee (the knee output level cannot go as low as the knee input level)
+    fn drc_settings(&self, band: usize) -> DrcSettings {
+        let band = self.bands[band];
+        DrcSettings {
+            knee1_input_db: band.knee_db,
+            knee1_output_db: band.knee_db.max(KNEE1_OUTPUT_MIN_DB),
+            upper_ratio: band.ratio,
+            lower_ratio: Ratio::R1,
+            ..DrcSettings::default()
+        }
+    }
+}
+
+/// writes the WDRC configured for the sample rate the codec runs at
+/// call this after `dsp::default_conf_enable`, it replaces the default routing, equalizers and compressors
+pub async fn apply<E>(
+    bus: &mut impl Bus<E>,
+    wdrc: &Wdrc,
+    rate: &SampleRateConfig,
+) -> Result<(), DspError<E>> {
+    let regs = wdrc.registers(rate).map_err(DspError::Wdrc)?;
+    reg_conf_write(bus, &regs).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw_dsp::routing::mixer_input;
+    use crate::sample_rate::SampleRate;
+    use cs47l63::registers::digital_core::compression;
+
+    // the nRF master clock used for 48kHz (32MHz / 21 with 32 clocks per frame)
+    fn rate_48k() -> SampleRateConfig {
+        SampleRateConfig::with_clock(SampleRate::_48kHz, 1_523_809, 32).unwrap()
+    }
+
+    #[test]
+    fn four_bands_use_every_equalizer_and_both_channels_of_both_drcs() {
+        let wdrc = Wdrc::default();
+        let rate = rate_48k();
+        let regs = wdrc.registers(&rate).unwrap();
+
+        // each equalizer is configured for its band and enabled
+        for (band, &(eq_block, _, _)) in BANDS.iter().enumerate() {
+            let eq_regs = wdrc
+                .eq_config(band, rate.sample_rate())
+                .unwrap()
+                .registers(eq_block);
+            for reg_val in eq_regs.iter() {
+                assert!(regs.contains(reg_val), "EQ{} {reg_val:x?}", band + 1);
+            }
+        }
+        let eq_enable = equalizer::EqControl1 {
+            eq1_enabled: true,
+            eq2_enabled: true,
+            eq3_enabled: true,
+            eq4_enabled: true,
+        }
+        .serialize();
+        assert!(regs.contains(&eq_enable));
+
+        // both channels of both DRCs
+        for (drc, reg) in [
+            (Drc::Drc1, compression::DrcControl1Reg::Drc1Control1),
+            (Drc::Drc2, compression::DrcControl1Reg::Drc2Control1),
+        ] {
+            let enable = compression::DrcControl1 {
+                reg,
+                left_enabled: true,
+                right_enabled: true,
+            }
+            .serialize();
+            assert!(regs.contains(&enable), "{drc:?}");
+        }
+
+        // the band of each DRC channel is mixed into Out1L
+        for (slot, drc_block) in [Block::Drc1L, Block::Drc1R, Block::Drc2L, Block::Drc2R]
+            .into_iter()
+            .enumerate()
+        {
+            let input = mixer_input(Sink::Out1L, slot, Source::Block(drc_block), 0);
+            assert!(regs.contains(&input), "{drc_block:?}");
+        }
+
+        assert_eq!(wdrc.validate(&rate), Ok(()));
+    }
+
+    #[test]
+    fn crossovers_must_be_ascending_and_below_nyquist() {
+        let rate = rate_48k();
+        for crossover_hz in [
+            [1500.0, 500.0, 4000.0],
+            [500.0, 500.0, 4000.0],
+            [0.0, 1500.0, 4000.0],
+            [500.0, 1500.0, 24_000.0],
+        ] {
+            let wdrc = Wdrc {
+                crossover_hz,
+                ..Wdrc::default()
+            };
+            assert_eq!(
+                wdrc.registers(&rate).err(),
+                Some(WdrcError::CrossoverOrder),
+                "{crossover_hz:?}"
+            );
+            assert_eq!(wdrc.validate(&rate), Err(WdrcError::CrossoverOrder));
+        }
+    }
+
+    #[test]
+    fn bands_sharing_a_drc_must_match() {
+        let mut wdrc = Wdrc::default();
+        wdrc.bands[3].ratio = Ratio::R8;
+        assert_eq!(
+            wdrc.validate(&rate_48k()),
+            Err(WdrcError::SharedDrcMismatch { band: 4 })
+        );
+
+        // a ratio the DRC does not support in the upper region is caught while serializing
+        let mut wdrc = Wdrc::default();
+        wdrc.bands[0].knee_db = -50.0;
+        wdrc.bands[1].knee_db = -50.0;
+        assert_eq!(
+            wdrc.validate(&rate_48k()),
+            Err(WdrcError::Drc(DrcError::KneeOutOfRange))
+        );
+    }
+}
@@ -1,6 +1,25 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 pub mod hw_dsp;
 pub mod play_state;
+pub mod sample_rate;
+#[cfg(feature = "nrf")]
 pub mod sw_dsp;
+#[cfg(feature = "nrf")]
 pub mod wave;
+
+// defmt needs a global logger to link, on the host the log frames are discarded
+#[cfg(test)]
+mod test_logger {
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    defmt::timestamp!("");
+}
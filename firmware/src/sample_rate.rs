@@ -0,0 +1,325 @@
+use crate::hw_dsp::fll::{FllError, FllSettings, FllSource};
+use cs47l63::registers::clocking_sample_rates::{clock, sample_rate};
+#[cfg(feature = "nrf")]
+use embassy_nrf::i2s::{MasterClock, MckFreq, Ratio};
+
+// A single place to choose the audio sample rate so that the codec, the nRF I2S peripheral and the software DSP agree.
+//
+// The nRF is the I2S master and derives its sample rate from a 32MHz clock so it can only approximate the rates the codec supports.
+// The codec runs in slave mode and follows the nRF frame clock so a small error is fine but a large one means
+// the codec processes audio at a different rate than the one it is configured for (EQ and DRC timings would be off).
+// The software DSP is designed for the actual nRF rate rather than the nominal one.
+//
+// The nRF master clock also drives the codec MCLK1 pin which is the FLL reference for SYSCLK.
+// SYSCLK is scaled by the same error as the sample rate so that the codec runs in step with the nRF frame clock.
+// SYSCLK is nominally 49.152MHz for the 48kHz family of rates and 45.1584MHz for the 44.1kHz family (e.g. 11.025kHz).
+// On the host there is no nRF I2S peripheral so the master clock is given in Hz with `SampleRateConfig::with_clock`.
+
+/// The largest allowed difference between the nominal and actual sample rate in percent
+pub const MAX_ERROR_PERCENT: f32 = 1.0;
+
+// SYSCLK for a sample rate that is exactly 48kHz or 44.1kHz (or an integer fraction of them)
+const NOMINAL_SYSCLK_48K_HZ: u32 = 49_152_000;
+const NOMINAL_SYSCLK_44K1_HZ: u32 = 45_158_400;
+
+/// A sample rate supported by both the codec and the nRF I2S peripheral
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SampleRate {
+    _11025Hz,
+    _16kHz,
+    _24kHz,
+    _48kHz,
+}
+
+impl SampleRate {
+    pub const ALL: [SampleRate; 4] = [
+        SampleRate::_11025Hz,
+        SampleRate::_16kHz,
+        SampleRate::_24kHz,
+        SampleRate::_48kHz,
+    ];
+
+    /// the nominal rate in Hz
+    pub const fn hz(&self) -> u32 {
+        match self {
+            Self::_11025Hz => 11025,
+            Self::_16kHz => 16000,
+            Self::_24kHz => 24000,
+            Self::_48kHz => 48000,
+        }
+    }
+
+    // the nRF master clock closest to the nominal rate
+    #[cfg(feature = "nrf")]
+    fn master_clock(&self) -> (MckFreq, Ratio) {
+        match self {
+            Self::_11025Hz => (MckFreq::_32MDiv15, Ratio::_192x),
+            Self::_16kHz => (MckFreq::_32MDiv21, Ratio::_96x),
+            Self::_24kHz => (MckFreq::_32MDiv21, Ratio::_64x),
+            Self::_48kHz => (MckFreq::_32MDiv21, Ratio::_32x),
+        }
+    }
+
+    const fn codec_select(&self) -> sample_rate::Select {
+        match self {
+            Self::_11025Hz => sample_rate::Select::_11p025KHz,
+            Self::_16kHz => sample_rate::Select::_16kHz,
+            Self::_24kHz => sample_rate::Select::_24kHz,
+            Self::_48kHz => sample_rate::Select::_48kHz,
+        }
+    }
+
+    const fn nominal_sysclk_hz(&self) -> u32 {
+        match self {
+            Self::_11025Hz => NOMINAL_SYSCLK_44K1_HZ,
+            _ => NOMINAL_SYSCLK_48K_HZ,
+        }
+    }
+
+    const fn sysclk_fraction(&self) -> clock::Fraction {
+        match self {
+            Self::_11025Hz => clock::Fraction::MultipleOf5M6448Hz,
+            _ => clock::Fraction::MultipleOf6M144Hz,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SampleRateError {
+    /// The I2S master clock produces a rate too far from the codec rate
+    MasterClockMismatch { expected_hz: u32, actual_hz: u32 },
+    /// The codec FLL cannot generate SYSCLK from the master clock
+    Fll(FllError),
+}
+
+impl From<FllError> for SampleRateError {
+    fn from(e: FllError) -> Self {
+        Self::Fll(e)
+    }
+}
+
+/// Matching sample rate settings for the codec, the nRF I2S peripheral and the software DSP
+#[derive(Clone, Copy)]
+pub struct SampleRateConfig {
+    rate: SampleRate,
+    mck_hz: u32,
+    ratio: u32,
+    fll: FllSettings,
+    #[cfg(feature = "nrf")]
+    master_clock: MasterClock,
+}
+
+impl SampleRateConfig {
+    /// uses the nRF master clock closest to the rate
+    #[cfg(feature = "nrf")]
+    pub fn new(rate: SampleRate) -> Result<Self, SampleRateError> {
+        let (mck_freq, ratio) = rate.master_clock();
+        Self::with_master_clock(rate, mck_freq, ratio)
+    }
+
+    /// uses a specific nRF master clock, fails if its sample rate is more than MAX_ERROR_PERCENT away from the codec rate
+    /// or if the codec cannot generate SYSCLK from it
+    #[cfg(feature = "nrf")]
+    pub fn with_master_clock(
+        rate: SampleRate,
+        mck_freq: MckFreq,
+        ratio: Ratio,
+    ) -> Result<Self, SampleRateError> {
+        let (mck_hz, ratio_divisor) = (mck_freq.to_frequency(), ratio.to_divisor());
+        Ok(Self {
+            rate,
+            mck_hz,
+            ratio: ratio_divisor,
+            fll: Self::calculate_fll(rate, mck_hz, ratio_divisor)?,
+            master_clock: MasterClock::new(mck_freq, ratio),
+        })
+    }
+
+    /// uses a master clock of `mck_hz` with `ratio` master clock cycles per frame
+    /// fails if its sample rate is more than MAX_ERROR_PERCENT away from the codec rate or if the codec cannot generate SYSCLK from it
+    #[cfg(not(feature = "nrf"))]
+    pub fn with_clock(rate: SampleRate, mck_hz: u32, ratio: u32) -> Result<Self, SampleRateError> {
+        Ok(Self {
+            rate,
+            mck_hz,
+            ratio,
+            fll: Self::calculate_fll(rate, mck_hz, ratio)?,
+        })
+    }
+
+    fn calculate_fll(
+        rate: SampleRate,
+        mck_hz: u32,
+        ratio: u32,
+    ) -> Result<FllSettings, SampleRateError> {
+        let expected_hz = rate.hz();
+        let actual_hz = mck_hz / ratio;
+        let error_percent = expected_hz.abs_diff(actual_hz) as f32 * 100.0 / expected_hz as f32;
+        if error_percent > MAX_ERROR_PERCENT {
+            return Err(SampleRateError::MasterClockMismatch {
+                expected_hz,
+                actual_hz,
+            });
+        }
+
+        // e.g. 1024 times the actual rate for 48kHz
+        let sysclk_hz =
+            mck_hz as u64 * (rate.nominal_sysclk_hz() / expected_hz) as u64 / ratio as u64;
+        Ok(FllSettings::calculate(
+            FllSource::Mclk1,
+            mck_hz,
+            sysclk_hz as u32,
+        )?)
+    }
+
+    /// the nominal sample rate the codec is configured for
+    pub fn rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    /// the nRF I2S master clock
+    #[cfg(feature = "nrf")]
+    pub fn master_clock(&self) -> MasterClock {
+        self.master_clock
+    }
+
+    /// the actual sample rate in Hz of the audio streamed over I2S
+    pub fn sample_rate(&self) -> u32 {
+        self.mck_hz / self.ratio
+    }
+
+    /// the codec FLL settings that generate SYSCLK from the master clock
+    pub fn fll(&self) -> &FllSettings {
+        &self.fll
+    }
+
+    /// the sample rate the software DSP should be designed for
+    pub fn dsp_sample_rate(&self) -> f64 {
+        self.sample_rate() as f64
+    }
+
+    /// the codec sample rate registers (rate 1 is used by the whole signal path and ASP1, the other rates are disabled)
+    /// and SYSCLK from FLL1 for the family of the rate
+    pub fn codec_registers(&self) -> [[u32; 2]; 5] {
+        [
+            sample_rate::SampleRate {
+                num: sample_rate::Num::_1,
+                select: self.rate.codec_select(),
+            }
+            .serialize(),
+            sample_rate::SampleRate {
+                num: sample_rate::Num::_2,
+                select: sample_rate::Select::None,
+            }
+            .serialize(),
+            sample_rate::SampleRate {
+                num: sample_rate::Num::_3,
+                select: sample_rate::Select::None,
+            }
+            .serialize(),
+            sample_rate::SampleRate {
+                num: sample_rate::Num::_4,
+                select: sample_rate::Select::None,
+            }
+            .serialize(),
+            clock::SystemClock1 {
+                frac: self.rate.sysclk_fraction(),
+                freq: clock::Freqency::_49p152MHz,
+                enabled: true,
+                src: clock::Source::FLL1_45to50MHz,
+            }
+            .serialize(),
+        ]
+    }
+}
+
+impl defmt::Format for SampleRateConfig {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "SampleRateConfig {{ rate: {}, actual_hz: {} }}",
+            self.rate,
+            self.sample_rate()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the nRF master clocks in Hz (32MHz / 15 and 32MHz / 21)
+    const MCK_32M_DIV15_HZ: u32 = 2_133_333;
+    const MCK_32M_DIV21_HZ: u32 = 1_523_809;
+
+    // the master clock and ratio `SampleRate::master_clock` picks on the nRF
+    fn nrf_clock(rate: SampleRate) -> (u32, u32) {
+        match rate {
+            SampleRate::_11025Hz => (MCK_32M_DIV15_HZ, 192),
+            SampleRate::_16kHz => (MCK_32M_DIV21_HZ, 96),
+            SampleRate::_24kHz => (MCK_32M_DIV21_HZ, 64),
+            SampleRate::_48kHz => (MCK_32M_DIV21_HZ, 32),
+        }
+    }
+
+    #[test]
+    fn every_rate_matches_the_codec_the_nrf_and_the_software_dsp() {
+        for rate in SampleRate::ALL {
+            let (mck_hz, ratio) = nrf_clock(rate);
+            let config = SampleRateConfig::with_clock(rate, mck_hz, ratio).unwrap();
+
+            // the I2S rate is within MAX_ERROR_PERCENT of the codec rate and the software DSP uses it
+            let actual_hz = config.sample_rate();
+            assert_eq!(actual_hz, mck_hz / ratio);
+            assert!(
+                actual_hz.abs_diff(rate.hz()) as f32 * 100.0 / rate.hz() as f32
+                    <= MAX_ERROR_PERCENT
+            );
+            assert_eq!(config.dsp_sample_rate(), actual_hz as f64);
+
+            // the codec runs at the nominal rate from SYSCLK scaled by the same error
+            let registers = config.codec_registers();
+            let rate1 = sample_rate::SampleRate {
+                num: sample_rate::Num::_1,
+                select: rate.codec_select(),
+            }
+            .serialize();
+            assert_eq!(registers[0], rate1);
+
+            let sysclk_hz = config.fll().output_hz() as f64;
+            let expected_hz = rate.nominal_sysclk_hz() as f64 * actual_hz as f64 / rate.hz() as f64;
+            assert!(
+                (sysclk_hz - expected_hz).abs() / expected_hz < 1e-4,
+                "{rate:?}: SYSCLK {sysclk_hz} instead of {expected_hz}"
+            );
+        }
+    }
+
+    #[test]
+    fn master_clocks_too_far_from_the_rate_are_rejected() {
+        // the 48kHz clock is 32kHz with the 11.025kHz ratio
+        assert_eq!(
+            SampleRateConfig::with_clock(SampleRate::_11025Hz, MCK_32M_DIV21_HZ, 192).err(),
+            Some(SampleRateError::MasterClockMismatch {
+                expected_hz: 11025,
+                actual_hz: 7936
+            })
+        );
+        assert_eq!(
+            SampleRateConfig::with_clock(SampleRate::_48kHz, MCK_32M_DIV15_HZ, 32).err(),
+            Some(SampleRateError::MasterClockMismatch {
+                expected_hz: 48000,
+                actual_hz: 66666
+            })
+        );
+    }
+
+    #[test]
+    fn master_clocks_the_fll_cannot_multiply_are_rejected() {
+        // one master clock cycle per frame needs a multiplier of 1024
+        assert_eq!(
+            SampleRateConfig::with_clock(SampleRate::_48kHz, 48_000, 1).err(),
+            Some(SampleRateError::Fll(FllError::MultiplierOutOfRange))
+        );
+    }
+}
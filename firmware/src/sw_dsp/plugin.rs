@@ -4,6 +4,7 @@ use core::ffi::c_void;
 
 use defmt::info;
 
+use crate::sample_rate::SampleRateConfig;
 use crate::sw_dsp::chapro::{
     _cc, cha_agc_channel, cha_agc_input, cha_agc_output, cha_agc_prepare, cha_firfb_analyze,
     cha_firfb_prepare, cha_firfb_synthesize, CHA_DSL, CHA_WDRC, NPTR,
@@ -61,10 +62,11 @@ impl FirFilterBank {
             cha_agc_output(cp, y, y, cs);
         }
     }
-}
 
-impl Default for FirFilterBank {
-    fn default() -> Self {
+    /// a filter bank designed for the actual I2S sample rate of the config
+    pub fn new(rate: &SampleRateConfig) -> Self {
+        let sample_rate = rate.dsp_sample_rate();
+
         // compressor config - desired sensation level
         static DSL: CHA_DSL = CHA_DSL {
             attack: 5.0,
@@ -99,11 +101,10 @@ impl Default for FirFilterBank {
         };
 
         // compressor config - wide dynamic range compressor (aka automatic gain control)
-        static AGC: CHA_WDRC = CHA_WDRC {
+        let agc = CHA_WDRC {
             attack: 1.0,
             release: 50.0,
-            fs: 24000.0,
-            //fs: 50000.0,
+            fs: sample_rate,
             maxdB: 119.0,
             tkgain: 0.0,
             tk: 105.0,
@@ -118,12 +119,11 @@ impl Default for FirFilterBank {
         static mut CP: [*mut c_void; NPTR as usize] = [0 as *mut c_void; NPTR as usize];
 
         Self {
-            sample_rate: 24000.0,
-            // sample_rate: 50000.0,
+            sample_rate,
             chunk_size: 32, // num samples
             cp: unsafe { CP },
             dsl: DSL,
-            agc: AGC,
+            agc,
             is_prepared: false,
         }
     }